@@ -25,4 +25,606 @@ $4016 	JOY1 	Joystick 1 data (R) and joystick strobe (W)
 $4017 	JOY2 	Joystick 2 data (R) and frame counter control (W) 
 
 http://wiki.nesdev.com/w/index.php/2A03
-*/
\ No newline at end of file
+*/
+
+pub use crate::core::*;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./apu_test.rs"]
+pub mod apu_test;
+
+static LENGTHS: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30];
+static DUTIES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1]];
+static TRIANGLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15];
+//Noise and DMC periods, in CPU cycles.
+static NOISE_NTSC: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+static NOISE_PAL:  [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+static DMC_NTSC:   [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+static DMC_PAL:    [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+//Frame counter steps, in CPU cycles: quarter, half, quarter, half/IRQ, 5-step half.
+static FRAME_NTSC: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+static FRAME_PAL:  [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/// Volume envelope, shared by the pulses and noise.
+#[derive(Clone, Copy, Default)]
+pub struct Envelope {
+    pub start:      bool,
+    pub looping:    bool,   //Also the length counter halt.
+    pub constant:   bool,
+    pub period:     u8,     //Or the constant volume.
+    pub divider:    u8,
+    pub decay:      u8,
+}
+
+impl Envelope {
+    fn write(&mut self, val: u8) {
+        self.looping  = val & 0x20 != 0;
+        self.constant = val & 0x10 != 0;
+        self.period   = val & 0x0F;
+    }
+    pub(crate) fn quarter(&mut self) {
+        if self.start {
+            self.start   = false;
+            self.decay   = 15;
+            self.divider = self.period;
+        }
+        else if self.divider == 0 {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            }
+            else if self.looping {
+                self.decay = 15;
+            }
+        }
+        else {
+            self.divider -= 1;
+        }
+    }
+    fn volume(&self) -> u8 {
+        if self.constant { self.period } else { self.decay }
+    }
+}
+
+/// One of the two square channels.
+#[derive(Clone, Copy, Default)]
+pub struct Pulse {
+    pub second:         bool,   //Pulse 2 negates in two's complement.
+    pub sweepless:      bool,   //MMC5's copies: no sweep, nothing mutes.
+    pub enabled:        bool,
+    pub duty:           u8,
+    pub step:           u8,
+    pub period:         u16,
+    pub timer:          u16,
+    pub length:         u8,
+    pub envelope:       Envelope,
+    pub sweep_enabled:  bool,
+    pub sweep_period:   u8,
+    pub sweep_negate:   bool,
+    pub sweep_shift:    u8,
+    pub sweep_divider:  u8,
+    pub sweep_reload:   bool,
+}
+
+impl Pulse {
+    pub(crate) fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.duty = val >> 6;
+                self.envelope.write(val);
+            },
+            1 => {
+                self.sweep_enabled = val & 0x80 != 0;
+                self.sweep_period  = (val >> 4) & 7;
+                self.sweep_negate  = val & 0x08 != 0;
+                self.sweep_shift   = val & 7;
+                self.sweep_reload  = true;
+            },
+            2 => self.period = (self.period & 0x700) | val as u16,
+            _ => {
+                self.period = (self.period & 0xFF) | ((val as u16 & 7) << 8);
+                if self.enabled {
+                    self.length = LENGTHS[(val >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            },
+        }
+    }
+    /// Where the sweep unit wants to take the period.
+    fn target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        }
+        else if self.second {
+            self.period.saturating_sub(change)
+        }
+        else {
+            self.period.saturating_sub(change + 1)
+        }
+    }
+    fn muted(&self) -> bool {
+        !self.sweepless && (self.period < 8 || self.target() > 0x7FF)
+    }
+    /// Every other CPU cycle.
+    pub(crate) fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step  = (self.step + 1) & 7;
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+    pub(crate) fn half(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload  = false;
+        }
+        else {
+            self.sweep_divider -= 1;
+        }
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+    pub(crate) fn output(&self) -> u8 {
+        if self.length == 0 || self.muted() || DUTIES[self.duty as usize][self.step as usize] == 0 {
+            0
+        }
+        else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Triangle {
+    pub enabled:        bool,
+    pub control:        bool,   //Also the length counter halt.
+    pub linear_period:  u8,
+    pub linear:         u8,
+    pub linear_reload:  bool,
+    pub period:         u16,
+    pub timer:          u16,
+    pub step:           u8,
+    pub length:         u8,
+}
+
+impl Triangle {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.control       = val & 0x80 != 0;
+                self.linear_period = val & 0x7F;
+            },
+            2 => self.period = (self.period & 0x700) | val as u16,
+            3 => {
+                self.period = (self.period & 0xFF) | ((val as u16 & 7) << 8);
+                if self.enabled {
+                    self.length = LENGTHS[(val >> 3) as usize];
+                }
+                self.linear_reload = true;
+            },
+            _ => {},
+        }
+    }
+    /// Every CPU cycle.
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length > 0 && self.linear > 0 {
+                self.step = (self.step + 1) & 31;
+            }
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+    fn quarter(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_period;
+        }
+        else if self.linear > 0 {
+            self.linear -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+    fn half(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+    fn output(&self) -> u8 {
+        TRIANGLE[self.step as usize]
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Noise {
+    pub enabled:    bool,
+    pub envelope:   Envelope,
+    pub short:      bool,   //Mode flag, 93 step sequence.
+    pub period:     u16,
+    pub timer:      u16,
+    pub shift:      u16,
+    pub length:     u8,
+}
+
+impl Default for Noise {
+    fn default() -> Noise {
+        Noise {
+            enabled:    false,
+            envelope:   Envelope::default(),
+            short:      false,
+            period:     NOISE_NTSC[0],
+            timer:      0,
+            shift:      1,
+            length:     0,
+        }
+    }
+}
+
+impl Noise {
+    fn write(&mut self, reg: u16, val: u8, pal: bool) {
+        match reg {
+            0 => self.envelope.write(val),
+            2 => {
+                self.short  = val & 0x80 != 0;
+                let table   = if pal { &NOISE_PAL } else { &NOISE_NTSC };
+                self.period = table[(val & 0x0F) as usize];
+            },
+            3 => {
+                if self.enabled {
+                    self.length = LENGTHS[(val >> 3) as usize];
+                }
+                self.envelope.start = true;
+            },
+            _ => {},
+        }
+    }
+    /// Every CPU cycle, periods are already in CPU cycles.
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+    fn half(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 == 1 { 0 } else { self.envelope.volume() }
+    }
+}
+
+/// Delta modulation channel. It reads samples off the CPU bus, which the
+///  APU can't see, so MEM fetches for it through dmc_request/dmc_fill.
+#[derive(Clone, Copy)]
+pub struct Dmc {
+    pub irq_enabled:    bool,
+    pub looping:        bool,
+    pub period:         u16,
+    pub timer:          u16,
+    pub level:          u8,
+    pub sample_address: u16,
+    pub sample_length:  u16,
+    pub address:        u16,
+    pub remaining:      u16,    //Bytes left to fetch.
+    pub buffer:         Option<u8>,
+    pub shift:          u8,
+    pub bits:           u8,
+    pub silence:        bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Dmc {
+        Dmc {
+            irq_enabled:    false,
+            looping:        false,
+            period:         DMC_NTSC[0],
+            timer:          0,
+            level:          0,
+            sample_address: 0xC000,
+            sample_length:  1,
+            address:        0xC000,
+            remaining:      0,
+            buffer:         None,
+            shift:          0,
+            bits:           8,
+            silence:        true,
+        }
+    }
+}
+
+impl Dmc {
+    fn restart(&mut self) {
+        self.address   = self.sample_address;
+        self.remaining = self.sample_length;
+    }
+    /// Every CPU cycle.
+    fn clock(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        if !self.silence {
+            if self.shift & 1 == 1 {
+                if self.level <= 125 { self.level += 2; }
+            }
+            else if self.level >= 2 {
+                self.level -= 2;
+            }
+            self.shift >>= 1;
+        }
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift   = byte;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+/// The 2A03's audio half.
+/// Every channel is clocked per CPU cycle through clock(), and output()
+///  mixes them the way the hardware's resistor DACs do.
+#[allow(non_snake_case)]
+pub struct APU {
+    pub REG:            [u8; 0x14], //$4000-$4013, as last written.
+    pub status:         u8,         //$4015 channel enables.
+    pub frame_counter:  u8,         //$4017 mode and IRQ inhibit.
+    pub frame_irq:      bool,
+    pub dmc_irq:        bool,
+    pub timing:         Timing,     //Picks the PAL noise/DMC/frame tables.
+    pub pulse:          [Pulse; 2],
+    pub triangle:       Triangle,
+    pub noise:          Noise,
+    pub dmc:            Dmc,
+    pub frame_cycle:    u32,
+    pub cycle:          u64,
+}
+
+impl Default for APU {
+    fn default() -> APU { APU::new() }
+}
+
+impl APU {
+    pub fn new() -> APU {
+        let mut pulse = [Pulse::default(); 2];
+        pulse[1].second = true;
+        APU {
+            REG:            [0; 0x14],
+            status:         0,
+            frame_counter:  0,
+            frame_irq:      false,
+            dmc_irq:        false,
+            timing:         Timing::Ntsc,
+            pulse,
+            triangle:       Triangle::default(),
+            noise:          Noise::default(),
+            dmc:            Dmc::default(),
+            frame_cycle:    0,
+            cycle:          0,
+        }
+    }
+
+    /// Power-up state, per nesdev's CPU_power_up_state.
+    /// All channels are silenced ($4015 = 0), the frame counter comes up
+    ///  in 4-step mode with its IRQ enabled ($4017 = 0), and $4000-$4013
+    ///  are cleared. Nothing here is left to chance, so there's no power-on
+    ///  policy to take.
+    pub fn power_on(&mut self) {
+        let timing = self.timing;
+        *self = APU::new();
+        self.timing = timing;
+        //The noise shift register comes up as 1.
+        self.noise.shift = 1;
+    }
+
+    fn pal(&self) -> bool {
+        self.timing == Timing::Pal
+    }
+
+    /// Reads an APU register, given a CPU address in $4000-$4015.
+    /// Only $4015 is readable, everything else is open bus.
+    pub fn read_register(&mut self, address: u16) -> u8 {
+        let val = self.peek_register(address);
+        if address == 0x4015 {
+            self.frame_irq = false;     //Reading acknowledges the IRQ.
+        }
+        val
+    }
+
+    /// Returns what read_register would, without acknowledging the IRQ.
+    pub fn peek_register(&self, address: u16) -> u8 {
+        match address {
+            0x4015 => ((self.dmc_irq as u8) << 7) | ((self.frame_irq as u8) << 6)
+                    | ((self.dmc.remaining > 0) as u8) << 4
+                    | ((self.noise.length > 0) as u8) << 3
+                    | ((self.triangle.length > 0) as u8) << 2
+                    | ((self.pulse[1].length > 0) as u8) << 1
+                    | (self.pulse[0].length > 0) as u8,
+            _ => 0,
+        }
+    }
+
+    /// Writes an APU register, given a CPU address in $4000-$4017.
+    pub fn write_register(&mut self, address: u16, val: u8) {
+        if let 0x4000..=0x4013 = address {
+            self.REG[(address - 0x4000) as usize] = val;
+        }
+        let reg = address & 3;
+        match address {
+            0x4000..=0x4003 => self.pulse[0].write(reg, val),
+            0x4004..=0x4007 => self.pulse[1].write(reg, val),
+            0x4008..=0x400B => self.triangle.write(reg, val),
+            0x400C..=0x400F => {
+                let pal = self.pal();
+                self.noise.write(reg, val, pal);
+            },
+            0x4010 => {
+                self.dmc.irq_enabled = val & 0x80 != 0;
+                self.dmc.looping     = val & 0x40 != 0;
+                let table = if self.pal() { &DMC_PAL } else { &DMC_NTSC };
+                self.dmc.period = table[(val & 0x0F) as usize];
+                if !self.dmc.irq_enabled {
+                    self.dmc_irq = false;
+                }
+            },
+            0x4011 => self.dmc.level = val & 0x7F,
+            0x4012 => self.dmc.sample_address = 0xC000 | ((val as u16) << 6),
+            0x4013 => self.dmc.sample_length  = ((val as u16) << 4) | 1,
+            0x4015 => {
+                self.status  = val & 0x1F;
+                self.dmc_irq = false;
+                self.pulse[0].enabled = val & 0x01 != 0;
+                self.pulse[1].enabled = val & 0x02 != 0;
+                self.triangle.enabled = val & 0x04 != 0;
+                self.noise.enabled    = val & 0x08 != 0;
+                if !self.pulse[0].enabled { self.pulse[0].length = 0; }
+                if !self.pulse[1].enabled { self.pulse[1].length = 0; }
+                if !self.triangle.enabled { self.triangle.length = 0; }
+                if !self.noise.enabled    { self.noise.length = 0; }
+                if val & 0x10 == 0 {
+                    self.dmc.remaining = 0;
+                }
+                else if self.dmc.remaining == 0 {
+                    self.dmc.restart();
+                }
+            },
+            0x4017 => {
+                self.frame_counter = val;
+                self.frame_cycle   = 0;
+                if val & 0x40 != 0 {
+                    self.frame_irq = false;
+                }
+                //5-step mode clocks everything straight away.
+                if val & 0x80 != 0 {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulse[0].envelope.quarter();
+        self.pulse[1].envelope.quarter();
+        self.triangle.quarter();
+        self.noise.envelope.quarter();
+    }
+    fn half_frame(&mut self) {
+        self.pulse[0].half();
+        self.pulse[1].half();
+        self.triangle.half();
+        self.noise.half();
+    }
+
+    /// One CPU cycle: the frame sequencer, then every channel.
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        self.frame_cycle += 1;
+        let steps = if self.pal() { &FRAME_PAL } else { &FRAME_NTSC };
+        let five_step = self.frame_counter & 0x80 != 0;
+        let cycle = self.frame_cycle;
+        if cycle == steps[0] || cycle == steps[2] {
+            self.quarter_frame();
+        }
+        else if cycle == steps[1] {
+            self.quarter_frame();
+            self.half_frame();
+        }
+        else if !five_step && cycle == steps[3] {
+            self.quarter_frame();
+            self.half_frame();
+            if self.frame_counter & 0x40 == 0 {
+                self.frame_irq = true;
+            }
+            self.frame_cycle = 0;
+        }
+        else if five_step && cycle == steps[4] {
+            self.quarter_frame();
+            self.half_frame();
+            self.frame_cycle = 0;
+        }
+
+        //Pulse timers run off the APU clock, half the CPU's.
+        if self.cycle & 1 == 0 {
+            self.pulse[0].clock();
+            self.pulse[1].clock();
+        }
+        self.triangle.clock();
+        self.noise.clock();
+        self.dmc.clock();
+    }
+
+    /// Address the DMC wants a sample byte from, if its buffer is empty.
+    pub fn dmc_request(&self) -> Option<u16> {
+        if self.dmc.buffer.is_none() && self.dmc.remaining > 0 {
+            Some(self.dmc.address)
+        } else {
+            None
+        }
+    }
+    /// Hands the DMC the byte it asked for.
+    pub fn dmc_fill(&mut self, byte: u8) {
+        self.dmc.buffer  = Some(byte);
+        self.dmc.address = if self.dmc.address == 0xFFFF { 0x8000 } else { self.dmc.address + 1 };
+        self.dmc.remaining -= 1;
+        if self.dmc.remaining == 0 {
+            if self.dmc.looping {
+                self.dmc.restart();
+            }
+            else if self.dmc.irq_enabled {
+                self.dmc_irq = true;
+            }
+        }
+    }
+
+    /// True while the frame counter or DMC is pulling /IRQ low.
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc_irq
+    }
+
+    /// The mixed output, 0.0 to ~1.0, using nesdev's approximation of the
+    ///  nonlinear DAC.
+    pub fn output(&self) -> f32 {
+        let p = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let pulse = if p == 0.0 { 0.0 } else { 95.88 / (8128.0 / p + 100.0) };
+        let t = self.triangle.output() as f32 / 8227.0
+              + self.noise.output() as f32 / 12241.0
+              + self.dmc.level as f32 / 22638.0;
+        let tnd = if t == 0.0 { 0.0 } else { 159.79 / (1.0 / t + 100.0) };
+        pulse + tnd
+    }
+}
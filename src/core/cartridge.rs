@@ -5,95 +5,249 @@ use std::fs::File;
 use std::io;
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use std::fmt;               //Implementing fmt::Debug.
 
 pub use ::log::*;
+use crate::core::ppu::Mirroring;
+use crate::core::validate::*;
+use crate::core::romdb::*;
+use crate::core::hash::*;
+use crate::core::patch::*;
+use crate::core::unif;
+use crate::core::power::PowerSource;
+use crate::core::registry::{find_board, mapper_name};
+use std::sync::Arc;
 
-#[allow(non_snake_case)]
+//Test module definition.
+#[cfg(test)]
+#[path = "./cartridge_test.rs"]
+pub mod cartridge_test;
+
+/// CPU/PPU timing the cart was made for. NES 2.0 byte 12, iNES byte 9.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,       //RP2C02, North America/Japan.
+    Pal,        //RP2C07, Europe/Australia.
+    Multi,      //Works on either.
+    Dendy,      //UMC 6527P famiclones.
+}
+
+/// Knobs for how a ROM image is turned into a CART.
+#[derive(Clone, Debug)]
+pub struct LoadOptions {
+    /// What to do when the image doesn't match its header.
+    pub validation: Validation,
+    /// Database used to correct known-bad headers, None to trust the header.
+    /// Defaults to the bundled one, see GameDb::bundled.
+    pub database: Option<Arc<GameDb>>,
+    /// Patches to apply to the image, in order, before anything else.
+    pub patches: Vec<Patch>,
+    /// Also apply .ips/.ups/.bps files named after the ROM, before the
+    ///  explicit ones. Only for carts read from a file.
+    pub auto_patch: bool,
+}
 
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            validation: Validation::default(),
+            database:   Some(GameDb::bundled()),
+            patches:    Vec::new(),
+            auto_patch: true,
+        }
+    }
+}
+
+/// What the cart expects to be plugged into. NES 2.0 bytes 7 and 13.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Console {
+    Nes,
+    /// Vs. System, with its PPU type and hardware type (byte 13).
+    VsSystem { ppu: u8, hardware: u8 },
+    Playchoice,
+    /// Extended console type, byte 13 (Famiclone with decimal mode, etc).
+    Extended(u8),
+}
+
+#[allow(non_snake_case)]
 pub struct CART {
-    pub filename: &'static str, 
+    pub filename: Option<PathBuf>,  //None for carts loaded from memory.
     pub ines_fmt: bool, //True if ROM is in iNES format.
     pub nes2_fmt: bool, //True if ROM is NES 2.0 format.
+    pub unif_fmt: bool, //True if ROM is UNIF, HEAD is then left blank.
+    pub board: Option<String>,  //UNIF board name, ie: "NES-SNROM".
+    pub battery: bool,  //True if PRG-RAM is battery backed (flags 6, bit 1).
     pub section_sizes: [u32;5], // Differing sizes of certain registers:
                                 // [0] : Trainer, either 0 or 512.
-                                // [1] : PRG_ROM 
-                                // [2] : CHR_ROM, can be 0.
+                                // [1] : PRG_ROM, in bytes.
+                                // [2] : CHR_ROM, in bytes, can be 0.
                                 // [3] : INST_ROM, either 0 or 8192.
                                 // [4] : PROM, either 0 or 32.
+    pub mapper: u16,            // 8 bits in iNES, 12 bits in NES 2.0.
+    pub submapper: u8,          // NES 2.0 only, 0 otherwise.
+    pub mirroring: Mirroring,   // Soldered mirroring, flags 6 bits 0 and 3.
+    pub prg_ram_size: usize,    // Volatile PRG-RAM, in bytes.
+    pub prg_nvram_size: usize,  // Battery backed PRG-RAM (or EEPROM), in bytes.
+    pub chr_ram_size: usize,    // Volatile CHR-RAM, in bytes.
+    pub chr_nvram_size: usize,  // Battery backed CHR-RAM, in bytes.
+    pub timing: Timing,
+    pub console: Console,
+    pub misc_roms: u8,          // Number of miscellaneous ROMs after CHR.
+    pub expansion: u8,          // Default expansion device, 1 = std. pads.
+    pub report: RomReport,      // What validation found at load time.
+    pub crc32: u32,             // CRC32 of PRG+CHR, for database lookups.
+    pub sha1: [u8;20],          // SHA-1 of PRG+CHR.
+    pub db_entry: Option<DbEntry>,      // Database match, if there was one.
+    pub corrections: Vec<Correction>,   // Header fields the database fixed.
+    pub patches: Vec<String>,   // Names of the patches applied, in order.
     pub HEAD: [u8;16],
     pub TRAIN: [u8;512],
     pub PRG: Vec<u8>,
     pub CHR: Vec<u8>,
     pub INST_ROM: [u8;8192],
     pub PROM: [u8;32],
+    pub PRG_RAM: Vec<u8>,       // $6000-$7FFF work RAM, sized from flags 8.
 }
 
-impl CART {
-    pub fn new(file_n: &'static str) -> CART {
+impl Default for CART {
+    fn default() -> CART {
     	CART {
-            filename:   file_n,
+            filename: None,
             ines_fmt: false,
             nes2_fmt: false,
+            unif_fmt: false,
+            board: None,
+            battery: false,
             section_sizes: [0;5], 
+            mapper: 0,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console: Console::Nes,
+            misc_roms: 0,
+            expansion: 0,
+            report: RomReport::default(),
+            crc32: 0,
+            sha1: [0;20],
+            db_entry: None,
+            corrections: Vec::new(),
+            patches: Vec::new(),
             HEAD: [0;16],
             TRAIN: [0;512],
             PRG:  Vec::new(), //Possibly make a Box<[T]>
             CHR: Vec::new(),
             INST_ROM: [0;8192],
             PROM: [0;32],
+            PRG_RAM: Vec::new(),
         }
     }
-    ///TODO: Differentiate between iNES and NES2.0
-    pub fn read_cart(&mut self) -> io::Result<()> { 
-        let mut f = File::open(self.filename.to_string())?;
-        let mut reader = BufReader::new(f);
+}
 
-        //Store and read header.
-        reader.read(&mut self.HEAD)?; // I think.. this works... maybe?
-        self.section_sizes[1] = self.HEAD[4] as u32; //This is in 16kb units!
-        self.section_sizes[2] = self.HEAD[5] as u32; //This is in 8kb units!
-        self.section_sizes[0] = if self.HEAD[6] & 0b100 == 0b100 {512} else {0};
+impl CART {
+    /// An empty cart, to be filled by read_cart() from the given file.
+    pub fn new<P: AsRef<Path>>(file_n: P) -> CART {
+        CART {
+            filename: Some(file_n.as_ref().to_path_buf()),
+            ..CART::default()
+        }
+    }
+    /// Reads a cart from a file, ie: CART::from_path("zelda.nes")
+    pub fn from_path<P: AsRef<Path>>(file_n: P) -> io::Result<CART> {
+        CART::from_path_with(file_n, &LoadOptions::default())
+    }
+    /// Reads a cart from anything readable: a socket, a zip entry, etc.
+    /// There is no file behind it, so there's no .sav either.
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<CART> {
+        CART::from_reader_with(reader, &LoadOptions::default())
+    }
+    /// Reads a cart from memory, ie: CART::from_bytes(include_bytes!("a.nes"))
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<CART> {
+        CART::from_reader(bytes)
+    }
 
-        //Check for iNES format identifier. 
-        if self.HEAD[0]==0x4E && self.HEAD[1]==0x45 
-            && self.HEAD[2]==0x53 && self.HEAD[3]==0x1A {
-                self.ines_fmt = true;
-        } 
-        //Check for NES 2.0 format identifier.
-        if self.ines_fmt && ((self.HEAD[7] & 0x0C) == 0x08) {
-            self.nes2_fmt = true;
+    /// from_path, with LoadOptions.
+    pub fn from_path_with<P: AsRef<Path>>(file_n: P, opts: &LoadOptions) -> io::Result<CART> {
+        let mut cart = CART::new(file_n);
+        cart.read_cart_with(opts)?;
+        Ok(cart)
+    }
+    /// from_reader, with LoadOptions.
+    pub fn from_reader_with<R: Read>(reader: R, opts: &LoadOptions) -> io::Result<CART> {
+        let mut cart = CART::default();
+        cart.read_from(reader, opts)?;
+        Ok(cart)
+    }
+    /// from_bytes, with LoadOptions.
+    pub fn from_bytes_with(bytes: &[u8], opts: &LoadOptions) -> io::Result<CART> {
+        CART::from_reader_with(bytes, opts)
+    }
+
+    /// Reads the cart from its file.
+    pub fn read_cart(&mut self) -> io::Result<()> { 
+        self.read_cart_with(&LoadOptions::default())
+    }
+    /// read_cart, with LoadOptions.
+    pub fn read_cart_with(&mut self, opts: &LoadOptions) -> io::Result<()> { 
+        let path = match self.filename {
+            Some(ref path) => path.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound,
+                                              "Cart has no file to read.")),
+        };
+        let f = File::open(&path)?;
+        if !opts.auto_patch {
+            return self.read_from(f, opts);
         }
-        //Snippet from nesdev -> NES_2.0#Identification
 
-        //Handle NES 2.0 format.
-        if self.ines_fmt && self.nes2_fmt { 
-            panic!("NES 2.0 not currently supported.");
+        //Patches found next to the ROM go first.
+        let mut opts = opts.clone();
+        let mut found = Vec::new();
+        for patch in find_patches(&path) {
+            found.push(Patch::from_path(patch)?);
         }
+        opts.patches.splice(0..0, found);
+        self.read_from(f, &opts)
+    }
 
-        //Fill trainer if it exists.
-        if self.section_sizes[0] > 0 {
-            reader.read(&mut self.TRAIN)?;
+    /// Reads an iNES/NES 2.0 image from a reader into the cart.
+    pub fn read_from<R: Read>(&mut self, source: R, opts: &LoadOptions) -> io::Result<()> { 
+        let mut image = Vec::new();
+        BufReader::new(source).read_to_end(&mut image)?;
+        self.read_image(image, opts)
+    }
+
+    /// Patches an in-memory iNES/NES 2.0/UNIF image, then splits it up into
+    ///  the cart's sections.
+    pub fn read_image(&mut self, mut image: Vec<u8>, opts: &LoadOptions) -> io::Result<()> { 
+        //Patches cover the whole file, header included.
+        self.patches.clear();
+        for patch in opts.patches.iter() {
+            image = patch.apply(&image)?;
+            info!("PATCH    -> Applied {}", patch.name);
+            self.patches.push(patch.name.clone());
         }
-        
-        //Filling variable length PRG
-        let mut i = 0;
-        let s = self.section_sizes[1]*16384; 
-        for byte in reader.by_ref().bytes() {
-            self.PRG.push(byte.unwrap());
 
-            if i >= s { break;}
-            i += 1;
+        if unif::is_unif(&image) {
+            self.read_unif(&image, opts)?;
+        }
+        else {
+            self.read_ines(image, opts)?;
         }
 
-        //Filling variable length CHR 
-        let mut i = 0;
-        let s = self.section_sizes[2]*8192; 
-        for byte in reader.by_ref().bytes() {
-            self.CHR.push(byte.unwrap());
+        //Identify the dump, and let the database overrule a bad header.
+        self.crc32 = crc32_update(crc32(&self.PRG), &self.CHR);
+        self.sha1  = sha1(&[&self.PRG[..], &self.CHR[..]].concat());
+        if let Some(ref db) = opts.database {
+            self.lookup(db);
+        }
 
-            if i >= s { break;}
-            i += 1;
+        //No CHR-ROM means the board has CHR-RAM instead.
+        if self.CHR.is_empty() {
+            self.CHR = vec![0; self.chr_ram_size + self.chr_nvram_size];
         }
 
         //INST-ROM, when I need it
@@ -102,16 +256,512 @@ impl CART {
         Ok(())
     }
 
+    /// Validates an iNES/NES 2.0 image, then reads its header and sections.
+    fn read_ines(&mut self, image: Vec<u8>, opts: &LoadOptions) -> io::Result<()> { 
+        if image.len() < 16 || image[0..4] != [0x4E, 0x45, 0x53, 0x1A] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "Not an iNES/NES 2.0 image."));
+        }
+
+        let image = if self.judge(validate(&image), opts.validation)? {
+            repair(&image, &self.report)
+        }
+        else {
+            image
+        };
+
+        //Store and read header.
+        self.HEAD.copy_from_slice(&image[..16]);
+        self.parse_header();
+        let mut offset = 16;
+
+        //Fill trainer if it exists.
+        if self.section_sizes[0] > 0 {
+            let end = (offset + 512).min(image.len());
+            self.TRAIN[..end - offset].copy_from_slice(&image[offset..end]);
+            offset = end;
+        }
+
+        //Filling variable length PRG, then CHR. Short images give short
+        // sections, validation has already complained about it.
+        let end = (offset + self.section_sizes[1] as usize).min(image.len());
+        self.PRG = image[offset..end].to_vec();
+        offset = end;
+
+        let end = (offset + self.section_sizes[2] as usize).min(image.len());
+        self.CHR = image[offset..end].to_vec();
+        Ok(())
+    }
+
+    /// Logs what validation found, then applies the Validation policy:
+    ///  Err if the image is refused, true if it should be repaired.
+    fn judge(&mut self, mut report: RomReport, validation: Validation) -> io::Result<bool> {
+        for issue in report.issues.iter() {
+            warn!("ROM      -> {}", issue);
+        }
+        let repair = match validation {
+            _ if report.is_clean() => false,
+            Validation::Refuse => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("ROM refused: {}", report)));
+            },
+            Validation::Repair => true,
+            Validation::Warn => false,
+        };
+        report.repaired = repair;
+        self.report = report;
+        Ok(repair)
+    }
+
+    /// Reads a UNIF image. There's no header, so the board name is looked
+    ///  up to find a mapper, and RAM sizes are the usual 8kb guesses.
+    fn read_unif(&mut self, image: &[u8], opts: &LoadOptions) -> io::Result<()> {
+        let mut unif = unif::parse(image)?;
+        let info = match find_board(&unif.board) {
+            Some(info) => info,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              format!("UNIF board {} is not supported.", unif.board))),
+        };
+        //Known boards that aren't emulated would only fail later, at boot.
+        if info.new.is_none() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported,
+                                      format!("UNIF board {} ({}) is not emulated.", unif.board, info.name)));
+        }
+        let (mapper, submapper) = (info.number, info.submapper());
+        if self.judge(unif::validate_chunks(&unif), opts.validation)? {
+            unif::repair_chunks(&mut unif, &self.report);
+        }
+        debug!("UNIF     -> Board {} is mapper {}.{}", unif.board, mapper, submapper);
+
+        self.unif_fmt  = true;
+        self.ines_fmt  = false;
+        self.nes2_fmt  = false;
+        self.mapper    = mapper;
+        self.submapper = submapper;
+        self.battery   = unif.battery;
+        self.mirroring = unif.mirroring.unwrap_or(Mirroring::Horizontal);
+        self.timing    = unif.timing.unwrap_or(Timing::Ntsc);
+        self.section_sizes = [0, unif.prg.len() as u32, 0, 0, 0];
+
+        //Like iNES, there's no saying how much PRG-RAM there is.
+        if self.battery {
+            self.prg_ram_size   = 0;
+            self.prg_nvram_size = 8192;
+        }
+        else {
+            self.prg_ram_size   = 8192;
+            self.prg_nvram_size = 0;
+        }
+        self.PRG_RAM = vec![0; 8192];
+
+        self.PRG = unif.prg;
+        if unif.chr.is_empty() {
+            self.CHR = Vec::new();
+            self.chr_ram_size = 8192;
+        }
+        else if unif.chr_ram {
+            //VROR: the CHR chunks are what the RAM starts out holding.
+            self.chr_ram_size = unif.chr.len().max(8192);
+            self.CHR = unif.chr;
+            self.CHR.resize(self.chr_ram_size, 0);
+        }
+        else {
+            self.section_sizes[2] = unif.chr.len() as u32;
+            self.CHR = unif.chr;
+            self.chr_ram_size = 0;
+        }
+        self.board = Some(unif.board);
+        Ok(())
+    }
+
+    /// Looks the cart's hashes up in a database, and applies its corrections.
+    pub fn lookup(&mut self, db: &GameDb) {
+        let entry = match db.find(self.crc32, &self.sha1) {
+            Some(entry) => entry.clone(),
+            None => {
+                debug!("DB       -> No match for CRC32 {:08X}.", self.crc32);
+                return;
+            },
+        };
+        info!("DB       -> Matched {}", entry);
+        self.corrections = correct(self, &entry);
+        for fix in self.corrections.iter() {
+            info!("DB       -> Corrected {}", fix);
+        }
+        self.db_entry = Some(entry);
+    }
+
+    /// Decodes HEAD into the cart's fields, for either iNES or NES 2.0.
+    /// http://wiki.nesdev.com/w/index.php/NES_2.0
+    pub fn parse_header(&mut self) {
+        let h = self.HEAD;
+
+        //Check for iNES format identifier. 
+        self.ines_fmt = h[0..4] == [0x4E, 0x45, 0x53, 0x1A];
+        //Check for NES 2.0 format identifier.
+        //Snippet from nesdev -> NES_2.0#Identification
+        self.nes2_fmt = self.ines_fmt && ((h[7] & 0x0C) == 0x08);
+
+        //Shared between both formats.
+        self.section_sizes[0] = if h[6] & 0b100 == 0b100 {512} else {0};
+        self.battery = h[6] & 0b10 == 0b10;
+        self.mirroring = if h[6] & 0b1000 != 0 { Mirroring::FourScreen }
+                         else if h[6] & 1 != 0 { Mirroring::Vertical }
+                         else { Mirroring::Horizontal };
+        let console_type = h[7] & 3;
+
+        if self.nes2_fmt {
+            self.mapper    = ((h[8] as u16 & 0x0F) << 8) | (h[7] & 0xF0) as u16
+                                                         | (h[6] >> 4) as u16;
+            self.submapper = h[8] >> 4;
+
+            //ROM sizes get an upper nibble in byte 9.
+            self.section_sizes[1] = nes2_rom_size(h[4], h[9] & 0x0F, 16384);
+            self.section_sizes[2] = nes2_rom_size(h[5], h[9] >> 4, 8192);
+
+            //RAM sizes are shift counts, 64 << n, where 0 means none.
+            self.prg_ram_size   = nes2_ram_size(h[10] & 0x0F);
+            self.prg_nvram_size = nes2_ram_size(h[10] >> 4);
+            self.chr_ram_size   = nes2_ram_size(h[11] & 0x0F);
+            self.chr_nvram_size = nes2_ram_size(h[11] >> 4);
+
+            self.timing = match h[12] & 3 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::Multi,
+                _ => Timing::Dendy,
+            };
+            self.console = match console_type {
+                0 => Console::Nes,
+                1 => Console::VsSystem { ppu: h[13] & 0x0F, hardware: h[13] >> 4 },
+                2 => Console::Playchoice,
+                _ => Console::Extended(h[13] & 0x0F),
+            };
+            self.misc_roms = h[14] & 3;
+            self.expansion = h[15] & 0x3F;
+        }
+        else {
+            self.mapper    = ((h[7] & 0xF0) | (h[6] >> 4)) as u16;
+            self.submapper = 0;
+
+            self.section_sizes[1] = h[4] as u32 * 16384;
+            self.section_sizes[2] = h[5] as u32 * 8192;
+
+            //PRG-RAM is in 8kb units, 0 infers 8kb for compatibility. [7]
+            let ram = if h[8] == 0 {1} else {h[8] as usize} * 8192;
+            if self.battery {
+                self.prg_ram_size   = 0;
+                self.prg_nvram_size = ram;
+            }
+            else {
+                self.prg_ram_size   = ram;
+                self.prg_nvram_size = 0;
+            }
+            //iNES can't say, so boards without CHR-ROM get the usual 8kb.
+            self.chr_ram_size   = if h[5] == 0 {8192} else {0};
+            self.chr_nvram_size = 0;
+
+            self.timing = if h[9] & 1 == 1 { Timing::Pal } else { Timing::Ntsc };
+            self.console = match console_type {
+                1 => Console::VsSystem { ppu: 0, hardware: 0 },
+                2 => Console::Playchoice,
+                _ => Console::Nes,
+            };
+            self.misc_roms = 0;
+            self.expansion = 0;
+        }
+
+        self.PRG_RAM = vec![0; self.prg_ram_size + self.prg_nvram_size];
+    }
+
+    /// Whether the cart needs NES 2.0 to be described exactly: big
+    ///  mappers, submappers, odd sizes, or anything iNES has no room for.
+    pub fn needs_nes2(&self) -> bool {
+        let ram = self.prg_ram_size + self.prg_nvram_size;
+        self.mapper > 0xFF || self.submapper != 0
+            || !self.section_sizes[1].is_multiple_of(16384) || self.section_sizes[1] > 255 * 16384
+            || !self.section_sizes[2].is_multiple_of(8192) || self.section_sizes[2] > 255 * 8192
+            || !ram.is_multiple_of(8192) || (self.prg_ram_size > 0 && self.prg_nvram_size > 0)
+            || self.chr_nvram_size > 0 || (self.section_sizes[2] > 0 && self.chr_ram_size > 0)
+            || !matches!(self.timing, Timing::Ntsc | Timing::Pal)
+            || matches!(self.console, Console::Extended(_))
+            || self.misc_roms > 0 || self.expansion > 0
+    }
+
+    /// Encodes the cart's fields, database corrections included, back into
+    ///  a 16 byte header. The inverse of parse_header(), as far as the
+    ///  format allows: iNES drops whatever needs_nes2() is about.
+    pub fn header(&self, nes2: bool) -> [u8;16] {
+        let mut h = [0u8;16];
+        h[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        h[6] = ((self.mapper as u8 & 0x0F) << 4)
+             | if self.section_sizes[0] > 0 {0b100} else {0}
+             | if self.battery {0b10} else {0}
+             | match self.mirroring {
+                   Mirroring::FourScreen => 0b1000,
+                   Mirroring::Vertical   => 1,
+                   _ => 0,
+               };
+        h[7] = (self.mapper as u8 & 0xF0) | match self.console {
+            Console::Nes => 0,
+            Console::VsSystem { .. } => 1,
+            Console::Playchoice => 2,
+            Console::Extended(_) => 3,
+        };
+
+        if nes2 {
+            h[7] |= 0x08;
+            h[8]  = (self.submapper << 4) | ((self.mapper >> 8) as u8 & 0x0F);
+            let (prg_lsb, prg_msb) = nes2_rom_fields(self.section_sizes[1], 16384);
+            let (chr_lsb, chr_msb) = nes2_rom_fields(self.section_sizes[2], 8192);
+            h[4]  = prg_lsb;
+            h[5]  = chr_lsb;
+            h[9]  = (chr_msb << 4) | prg_msb;
+            h[10] = (nes2_ram_shift(self.prg_nvram_size) << 4) | nes2_ram_shift(self.prg_ram_size);
+            h[11] = (nes2_ram_shift(self.chr_nvram_size) << 4) | nes2_ram_shift(self.chr_ram_size);
+            h[12] = match self.timing {
+                Timing::Ntsc  => 0,
+                Timing::Pal   => 1,
+                Timing::Multi => 2,
+                Timing::Dendy => 3,
+            };
+            h[13] = match self.console {
+                Console::VsSystem { ppu, hardware } => (hardware << 4) | (ppu & 0x0F),
+                Console::Extended(kind) => kind & 0x0F,
+                _ => 0,
+            };
+            h[14] = self.misc_roms & 3;
+            h[15] = self.expansion & 0x3F;
+        }
+        else {
+            //Rounded up, so nothing gets cut off.
+            h[4] = self.section_sizes[1].div_ceil(16384).min(255) as u8;
+            h[5] = self.section_sizes[2].div_ceil(8192).min(255) as u8;
+            let ram = self.prg_ram_size + self.prg_nvram_size;
+            h[8] = ram.div_ceil(8192).min(255) as u8;
+            h[9] = (self.timing == Timing::Pal) as u8;
+        }
+        h
+    }
+
+    /// The cart as an iNES/NES 2.0 image: a fresh header, then the trainer,
+    ///  PRG and CHR-ROM. UNIF carts come out converted.
+    pub fn to_ines(&self, nes2: bool) -> Vec<u8> {
+        let mut image = self.header(nes2).to_vec();
+        if self.section_sizes[0] > 0 {
+            image.extend_from_slice(&self.TRAIN);
+        }
+        image.extend_from_slice(&self.PRG);
+        //CHR holds CHR-RAM too, only ROM belongs in the file.
+        let chr = (self.section_sizes[2] as usize).min(self.CHR.len());
+        image.extend_from_slice(&self.CHR[..chr]);
+        image
+    }
+
     pub fn fill_banks(){
 
     }
+
+    /// Reads a byte of PRG-RAM, given a CPU address in $6000-$7FFF.
+    /// Carts without RAM read back as 0 (open bus, for now).
+    pub fn get_ram(&self, address: u16) -> u8 {
+        if self.PRG_RAM.is_empty() {
+            return 0;
+        }
+        self.PRG_RAM[(address as usize - 0x6000) % self.PRG_RAM.len()]
+    }
+    /// Writes a byte of PRG-RAM, given a CPU address in $6000-$7FFF.
+    pub fn set_ram(&mut self, address: u16, val: u8) {
+        if self.PRG_RAM.is_empty() {
+            return;
+        }
+        let len = self.PRG_RAM.len();
+        self.PRG_RAM[(address as usize - 0x6000) % len] = val;
+    }
+
+    /// The battery save lives next to the ROM, ie: zelda.nes -> zelda.sav
+    /// Carts read from memory have nowhere to put one.
+    pub fn sav_path(&self) -> Option<PathBuf> {
+        self.filename.as_ref().map(|f| f.with_extension("sav"))
+    }
+    /// Power-on contents of PRG-RAM. Battery backed RAM keeps what the .sav
+    ///  gave it, work RAM comes up like the console's own.
+    pub fn power_on_ram(&mut self, src: &mut PowerSource) {
+        if !self.battery {
+            src.fill(&mut self.PRG_RAM);
+        }
+    }
+    /// Fills PRG-RAM from the .sav file, if the cart has a battery.
+    /// A missing .sav is not an error, it just means a fresh save.
+    pub fn load_sav(&mut self) -> io::Result<()> {
+        let path = match self.sav_path() {
+            Some(ref path) if self.battery => path.clone(),
+            _ => return Ok(()),
+        };
+        if !path.exists() {
+            debug!("SAV      -> No save found at {}.", path.display());
+            return Ok(());
+        }
+
+        let data = std::fs::read(&path)?;
+        let n = data.len().min(self.PRG_RAM.len());
+        self.PRG_RAM[..n].copy_from_slice(&data[..n]);
+        debug!("COMPLETE -> SAV read from {}.", path.display());
+        Ok(())
+    }
+    /// Flushes PRG-RAM out to the .sav file, if the cart has a battery.
+    pub fn save_sav(&self) -> io::Result<()> {
+        self.save_sav_with(&[])
+    }
+    /// save_sav(), with more battery backed bytes after PRG-RAM, for
+    ///  mappers that keep their own (N163's sound RAM).
+    pub fn save_sav_with(&self, extra: &[u8]) -> io::Result<()> {
+        let path = match self.sav_path() {
+            Some(ref path) if self.battery => path.clone(),
+            _ => return Ok(()),
+        };
+        let mut data = self.PRG_RAM.clone();
+        data.extend_from_slice(extra);
+        std::fs::write(&path, &data)?;
+        debug!("COMPLETE -> SAV written to {}.", path.display());
+        Ok(())
+    }
+    /// Whatever save_sav_with() put after PRG-RAM, if there's a save.
+    pub fn sav_extra(&self) -> Option<Vec<u8>> {
+        match self.sav_path() {
+            Some(ref path) if self.battery => {
+                let data = std::fs::read(path).ok()?;
+                data.get(self.PRG_RAM.len()..).map(|extra| extra.to_vec())
+            },
+            _ => None,
+        }
+    }
+}
+/// NES 2.0 ROM size from the LSB (byte 4/5) and MSB nibble (byte 9).
+/// An MSB nibble of $F switches to exponent-multiplier notation:
+///  LSB = EEEEEEMM, size = 2^E * (MM*2 + 1) bytes.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: u32) -> u32 {
+    if msb == 0x0F {
+        let exponent   = (lsb >> 2) as u32;
+        let multiplier = (lsb & 3) as u32 * 2 + 1;
+        2u32.checked_pow(exponent).map_or(u32::MAX, |e| e.saturating_mul(multiplier))
+    }
+    else {
+        (((msb as u32) << 8) | lsb as u32) * unit
+    }
 }
+
+/// NES 2.0 RAM size from a shift count, 0 means no RAM at all.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+/// The LSB and MSB nibble for a NES 2.0 ROM size: whole units when they
+///  fit, else the exponent-multiplier form, else rounded up to a unit.
+fn nes2_rom_fields(size: u32, unit: u32) -> (u8, u8) {
+    let units = size / unit;
+    if size.is_multiple_of(unit) && units <= 0xEFF {
+        return (units as u8, (units >> 8) as u8);
+    }
+    for mm in 0..4u32 {
+        let multiplier = mm * 2 + 1;
+        if size.is_multiple_of(multiplier) && (size / multiplier).is_power_of_two() {
+            let exponent = (size / multiplier).trailing_zeros();
+            return (((exponent << 2) | mm) as u8, 0x0F);
+        }
+    }
+    let units = size.div_ceil(unit).min(0xEFF);
+    (units as u8, (units >> 8) as u8)
+}
+
+/// The smallest NES 2.0 shift count that holds a RAM size.
+fn nes2_ram_shift(size: usize) -> u8 {
+    if size == 0 {
+        return 0;
+    }
+    let mut shift = 1;
+    while (64 << shift) < size && shift < 15 {
+        shift += 1;
+    }
+    shift
+}
+
+/// Sizes the way people talk about ROMs: "256 KB", or bytes when small.
+fn human_size(bytes: usize) -> String {
+    if bytes >= 1024 && bytes.is_multiple_of(1024) {
+        format!("{} KB", bytes / 1024)
+    }
+    else {
+        format!("{} bytes", bytes)
+    }
+}
+
+/// The human readable summary, as printed by `soliloquy info`.
+impl fmt::Display for CART {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = if self.unif_fmt { "UNIF" }
+                     else if self.nes2_fmt { "NES 2.0" }
+                     else if self.ines_fmt { "iNES" }
+                     else { "unknown" };
+        writeln!(f, "File:       {}", self.filename.as_ref()
+                 .map_or("<memory>".into(), |f| f.display().to_string()))?;
+        writeln!(f, "Format:     {}", format)?;
+        if let Some(ref board) = self.board {
+            writeln!(f, "Board:      {}", board)?;
+        }
+        writeln!(f, "Mapper:     {} ({})", self.mapper,
+                 mapper_name(self.mapper, self.submapper).unwrap_or("unknown"))?;
+        writeln!(f, "Submapper:  {}", self.submapper)?;
+        writeln!(f, "PRG-ROM:    {}", human_size(self.section_sizes[1] as usize))?;
+        if self.section_sizes[2] > 0 {
+            writeln!(f, "CHR-ROM:    {}", human_size(self.section_sizes[2] as usize))?;
+        }
+        else {
+            writeln!(f, "CHR-ROM:    none")?;
+        }
+        for (name, size) in [("PRG-RAM:   ", self.prg_ram_size), ("PRG-NVRAM: ", self.prg_nvram_size),
+                             ("CHR-RAM:   ", self.chr_ram_size), ("CHR-NVRAM: ", self.chr_nvram_size)].iter() {
+            if *size > 0 {
+                writeln!(f, "{} {}", name, human_size(*size))?;
+            }
+        }
+        writeln!(f, "Mirroring:  {:?}", self.mirroring)?;
+        writeln!(f, "Battery:    {}", if self.battery { "yes" } else { "no" })?;
+        writeln!(f, "Trainer:    {}", if self.section_sizes[0] > 0 { "yes" } else { "no" })?;
+        writeln!(f, "Region:     {:?}", self.timing)?;
+        if self.console != Console::Nes {
+            writeln!(f, "Console:    {:?}", self.console)?;
+        }
+        writeln!(f, "CRC32:      {:08X}", self.crc32)?;
+        writeln!(f, "SHA-1:      {}", to_hex(&self.sha1))?;
+        match self.db_entry {
+            Some(ref entry) => writeln!(f, "Database:   {}", entry)?,
+            None => writeln!(f, "Database:   no match")?,
+        }
+        for fix in self.corrections.iter() {
+            writeln!(f, "  Corrected {}", fix)?;
+        }
+        for patch in self.patches.iter() {
+            writeln!(f, "Patched:    {}", patch)?;
+        }
+        if !self.report.is_clean() {
+            writeln!(f, "Problems:   {}", self.report)?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for CART {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f,   "Filename: {} \n
-                     INES: {}\tNES2.0: {} \n 
+                     INES: {}\tNES2.0: {}\tUNIF: {:?} \n 
+                     MAPPER: {}.{}\tTIMING: {:?}\tCONSOLE: {:?} \n 
                      SECT-Sizes: {:?} \n 
+                     PRG-RAM: {}\tBATTERY: {} \n 
                      HEAD: {:?}\n",
-                     self.filename, self.ines_fmt, self.nes2_fmt, self.section_sizes, self.HEAD)
+                     self.filename.as_ref().map_or("<memory>".into(), |f| f.display().to_string()),
+                     self.ines_fmt, self.nes2_fmt, self.board,
+                     self.mapper, self.submapper, self.timing, self.console,
+                     self.section_sizes, self.PRG_RAM.len(), self.battery, self.HEAD)
     }
-}
\ No newline at end of file
+}
\ No newline at end of file
@@ -0,0 +1,591 @@
+/*  Unit test module of the cartridge loader (cartridge.rs).
+ *  Headers are built by hand, byte by byte, straight from the nesdev
+ *   iNES/NES 2.0 tables.
+ */
+use crate::core::cartridge::*;
+
+#[cfg(test)]
+pub mod cartridge_test {
+    use super::*;
+    use crate::core::ppu::Mirroring;
+
+    fn cart_with_head(head: [u8; 16]) -> CART {
+        let mut cart = CART::new("test/header.nes");
+        cart.HEAD = head;
+        cart.parse_header();
+        cart
+    }
+
+    /// A whole iNES image in memory: 16kb of PRG counting up, 8kb CHR of $CC.
+    pub fn ines_image(flags6: u8) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, flags6, 0,
+                           0, 0, 0, 0, 0, 0, 0, 0];
+        rom.extend((0..16384).map(|i| i as u8));
+        rom.extend(vec![0xCC; 8192]);
+        rom
+    }
+
+    //~~~LOADING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_from_bytes(){
+        let rom = ines_image(0);
+        let cart = CART::from_bytes(&rom).unwrap();
+
+        assert!(cart.filename.is_none());
+        assert!(cart.sav_path().is_none());
+        assert_eq!(cart.PRG[0x1234], 0x34);
+        assert_eq!(cart.CHR[0], 0xCC);
+    }
+
+    #[test]
+    fn test_from_reader_and_path(){
+        let rom = ines_image(0b1);
+        let cart = CART::from_reader(std::io::Cursor::new(rom.clone())).unwrap();
+        assert_eq!(cart.mirroring, Mirroring::Vertical);
+
+        //Owned, runtime built paths work, no 'static needed.
+        let path = std::env::temp_dir().join(format!("soliloquy_{}.nes", "path"));
+        std::fs::write(&path, &rom).unwrap();
+        let cart = CART::from_path(&path).unwrap();
+        assert_eq!(cart.PRG[0x10], 0x10);
+        assert_eq!(cart.sav_path().unwrap(), path.with_extension("sav"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    //~~~VALIDATION~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_validate_clean(){
+        let cart = CART::from_bytes(&ines_image(0)).unwrap();
+        assert!(cart.report.is_clean());
+        //Exactly the header's worth, no stray byte from the next section.
+        assert_eq!(cart.PRG.len(), 16384);
+        assert_eq!(cart.CHR.len(), 8192);
+        assert_eq!(cart.PRG[16383], 0xFF);
+    }
+
+    #[test]
+    fn test_validate_sizes(){
+        //Short PRG.
+        let rom = ines_image(0)[..1000].to_vec();
+        let report = validate(&rom);
+        assert_eq!(report.issues, vec![
+            RomIssue::PrgTruncated { expected: 16384, found: 984 },
+            RomIssue::MissingChr]);
+
+        //Short CHR, and a few bytes of junk tacked onto another.
+        let mut rom = ines_image(0);
+        rom.truncate(rom.len() - 100);
+        assert_eq!(validate(&rom).issues,
+                   vec![RomIssue::ChrTruncated { expected: 8192, found: 8092 }]);
+        let mut rom = ines_image(0);
+        rom.extend(vec![0; 128]);
+        assert_eq!(validate(&rom).issues, vec![RomIssue::TrailingData(128)]);
+    }
+
+    #[test]
+    fn test_validate_header(){
+        //The classic: "DiskDude!" in bytes 7-15 makes a mapper 64 out of NROM.
+        let mut rom = ines_image(0);
+        rom[7..16].copy_from_slice(b"DiskDude!");
+        let report = validate(&rom);
+        assert_eq!(report.issues, vec![RomIssue::DirtyHeader("DiskDude!".into())]);
+
+        //NES 2.0 mapper in plane 15, and no CHR memory at all.
+        let mut rom = ines_image(0);
+        rom[5] = 0;
+        rom[7] = 0x08;
+        rom[8] = 0x0F;
+        rom.truncate(16 + 16384);
+        let report = validate(&rom);
+        assert_eq!(report.issues, vec![RomIssue::MissingChr,
+                                       RomIssue::ImpossibleMapper(0xF00)]);
+        assert!(!report.issues[1].repairable());
+    }
+
+    #[test]
+    fn test_validate_refuse_repair(){
+        let mut rom = ines_image(0);
+        rom[7..16].copy_from_slice(b"DiskDude!");
+        rom.truncate(16 + 8192);
+
+        let refuse = LoadOptions { validation: Validation::Refuse, ..LoadOptions::default() };
+        assert!(CART::from_bytes_with(&rom, &refuse).is_err());
+
+        let fix = LoadOptions { validation: Validation::Repair, ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(&rom, &fix).unwrap();
+        assert!(cart.report.repaired);
+        assert_eq!(cart.mapper, 0);
+        assert_eq!(cart.PRG.len(), 16384);
+        assert_eq!(cart.PRG[16383], 0xFF);
+        assert_eq!(cart.CHR.len(), 8192);
+
+        //Warn (the default) loads it as-is, short sections and all.
+        let cart = CART::from_bytes(&rom).unwrap();
+        assert_eq!(cart.mapper, 0x40);
+        assert_eq!(cart.PRG.len(), 8192);
+    }
+
+    //~~~DATABASE~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_hashes(){
+        use crate::core::hash::*;
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF43926);
+        assert_eq!(to_hex(&sha1(b"")), "DA39A3EE5E6B4B0D3255BFEF95601890AFD80709");
+        assert_eq!(to_hex(&sha1(b"abc")), "A9993E364706816ABA3E25717850C26C9CD0D89D");
+    }
+
+    #[test]
+    fn test_database_correction(){
+        use crate::core::romdb::*;
+        use crate::core::hash::*;
+        use std::sync::Arc;
+
+        //The bundled database parses, every entry is keyed, and it's what
+        // carts are checked against by default.
+        let bundled = GameDb::bundled();
+        assert!(!bundled.entries.is_empty());
+        assert!(bundled.entries.iter().all(|e| e.crc32 != 0 && !e.title.is_empty()));
+        let smb = bundled.entries.iter().find(|e| e.title == "Super Mario Bros. (World)").unwrap();
+        assert_eq!((smb.mapper, smb.mirroring), (Some(0), Some(Mirroring::Vertical)));
+        assert!(LoadOptions::default().database.is_some());
+
+        //The synthetic cart claims NROM/horizontal, the "database" knows
+        // better: MMC1, vertical, with a battery.
+        let rom = ines_image(0);
+        let crc = crc32(&rom[16..]);
+        let xml = format!(r#"<?xml version="1.0"?>
+            <nes20db>
+              <game>
+                <!-- Homebrew\Synthetic Test (PD).nes -->
+                <rom size="24576" crc32="{:08X}"/>
+                <prgnvram size="8192"/>
+                <pcb mapper="1" submapper="0" mirroring="V" battery="1" board="NES-SNROM"/>
+              </game>
+            </nes20db>"#, crc);
+        let db = GameDb::from_xml(&xml).unwrap();
+        assert_eq!(db.entries.len(), 1);
+        assert_eq!(db.entries[0].title, "Synthetic Test (PD)");
+
+        let opts = LoadOptions { database: Some(Arc::new(db)), ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+        assert_eq!(cart.crc32, crc);
+        assert_eq!(cart.mapper, 1);
+        assert_eq!(cart.mirroring, Mirroring::Vertical);
+        assert!(cart.battery);
+        assert_eq!(cart.prg_nvram_size, 8192);
+        assert_eq!(cart.db_entry.as_ref().unwrap().board.as_deref(), Some("NES-SNROM"));
+        let fixed: Vec<&str> = cart.corrections.iter().map(|c| c.field).collect();
+        assert_eq!(fixed, vec!["mapper", "mirroring", "battery", "PRG-NVRAM"]);
+
+        //Turning the database off trusts the header.
+        let opts = LoadOptions { database: None, ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+        assert_eq!(cart.mapper, 0);
+    }
+
+    //~~~PATCHING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    fn varint(mut n: usize, out: &mut Vec<u8>) {
+        loop {
+            let x = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(0x80 | x);
+                return;
+            }
+            out.push(x);
+            n -= 1;
+        }
+    }
+
+    /// Tacks the UPS/BPS CRC32 footer onto a patch body.
+    fn footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        use crate::core::hash::*;
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let own = crc32(&patch);
+        patch.extend_from_slice(&own.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_patch_ips(){
+        use crate::core::patch::*;
+        let rom = ines_image(0);
+
+        let mut ips = b"PATCH".to_vec();
+        ips.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x02, 0xAA, 0xBB]); //Plain.
+        ips.extend_from_slice(&[0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x04, 0xCC]); //RLE.
+        ips.extend_from_slice(b"EOF");
+        let patch = Patch::from_bytes(ips.clone()).unwrap();
+        assert_eq!(patch.format, PatchFormat::Ips);
+
+        let out = patch.apply(&rom).unwrap();
+        assert_eq!(out.len(), rom.len());
+        assert_eq!(&out[0x10..0x18], &[0xAA, 0xBB, 0xCC, 0xCC, 0xCC, 0xCC, 0x06, 0x07]);
+
+        //Truncation, ie: a hack that drops the CHR.
+        ips.extend_from_slice(&[0x00, 0x40, 0x10]);
+        let out = Patch::from_bytes(ips).unwrap().apply(&rom).unwrap();
+        assert_eq!(out.len(), 0x4010);
+
+        //Records past the end grow the image.
+        let mut ips = b"PATCH".to_vec();
+        ips.extend_from_slice(&[0x00, 0x60, 0x10, 0x00, 0x00, 0x20, 0x00, 0xEE]);
+        ips.extend_from_slice(b"EOF");
+        let out = Patch::from_bytes(ips).unwrap().apply(&rom).unwrap();
+        assert_eq!(out.len(), 0x8010);
+        assert_eq!(out[0x800F], 0xEE);
+    }
+
+    #[test]
+    fn test_patch_ups(){
+        use crate::core::patch::*;
+        let source = [1, 2, 3, 4];
+        let target = [1, 9, 3, 4, 5];
+
+        let mut ups = b"UPS1".to_vec();
+        varint(4, &mut ups);
+        varint(5, &mut ups);
+        varint(1, &mut ups); ups.extend_from_slice(&[2 ^ 9, 0]);
+        varint(1, &mut ups); ups.extend_from_slice(&[5, 0]);
+        let ups = footer(ups, &source, &target);
+
+        let patch = Patch::from_bytes(ups.clone()).unwrap();
+        assert_eq!(patch.format, PatchFormat::Ups);
+        assert_eq!(patch.apply(&source).unwrap(), target);
+
+        //Wrong dump.
+        let err = patch.apply(&[1, 2, 3, 5]).unwrap_err();
+        assert!(err.to_string().contains("source CRC32 mismatch"));
+
+        //Corrupt patch.
+        let mut ups = ups;
+        ups[6] ^= 0xFF;
+        let err = Patch::from_bytes(ups).unwrap().apply(&source).unwrap_err();
+        assert!(err.to_string().contains("patch CRC32 mismatch"));
+    }
+
+    #[test]
+    fn test_patch_bps(){
+        use crate::core::patch::*;
+        let source = [1, 2, 3, 4];
+        let target = [1, 2, 7, 7, 7, 7, 3, 4];
+
+        let mut bps = b"BPS1".to_vec();
+        varint(4, &mut bps);
+        varint(8, &mut bps);
+        varint(2, &mut bps); bps.extend_from_slice(b"hi"); //Metadata.
+        varint(1 << 2, &mut bps);                           //SourceRead 2.
+        varint(1, &mut bps); bps.push(7);                   //TargetRead 1.
+        varint((2 << 2) | 3, &mut bps); varint(2 << 1, &mut bps); //TargetCopy 3, +2.
+        varint((1 << 2) | 2, &mut bps); varint(2 << 1, &mut bps); //SourceCopy 2, +2.
+        let bps = footer(bps, &source, &target);
+
+        let patch = Patch::from_bytes(bps).unwrap();
+        assert_eq!(patch.format, PatchFormat::Bps);
+        assert_eq!(patch.apply(&source).unwrap(), target);
+        assert!(patch.apply(&target).is_err());
+
+        assert!(Patch::from_bytes(b"NOPE".to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_patch_bps_malformed(){
+        use crate::core::patch::*;
+        let source = [1, 2, 3, 4];
+        let bps = |body: &dyn Fn(&mut Vec<u8>)| {
+            let mut bps = b"BPS1".to_vec();
+            varint(4, &mut bps);
+            varint(8, &mut bps);
+            body(&mut bps);
+            Patch::from_bytes(footer(bps, &source, &[])).unwrap().apply(&source)
+        };
+        let truncated = |res: std::io::Result<Vec<u8>>| res.unwrap_err().to_string().contains("truncated");
+
+        //SourceCopy before the start of the source.
+        assert!(truncated(bps(&|p| {
+            varint(0, p);
+            varint((1 << 2) | 2, p); varint((3 << 1) | 1, p);
+        })));
+        //SourceCopy as far past the source as an offset goes.
+        assert!(truncated(bps(&|p| {
+            varint(0, p);
+            varint((1 << 2) | 2, p); varint((isize::MAX as usize) << 1, p);
+        })));
+        //Metadata longer than the address space.
+        assert!(truncated(bps(&|p| varint(usize::MAX - 1, p))));
+        //TargetRead past the end of the patch.
+        assert!(truncated(bps(&|p| {
+            varint(0, p);
+            varint((1 << 2) | 1, p);
+        })));
+    }
+
+    #[test]
+    fn test_patch_oversized_target(){
+        use crate::core::patch::*;
+        //Made for the right source, but asking for an absurd target. It has
+        // to be refused before anything that size is allocated.
+        let source = [1, 2, 3, 4];
+        for magic in [b"UPS1", b"BPS1"] {
+            let mut patch = magic.to_vec();
+            varint(4, &mut patch);
+            varint(1 << 60, &mut patch);
+            varint(0, &mut patch);
+            let err = Patch::from_bytes(footer(patch, &source, &[])).unwrap()
+                .apply(&source).unwrap_err();
+            assert!(err.to_string().contains("more than the"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_patch_at_load(){
+        let rom = ines_image(0);
+        let path = std::env::temp_dir().join("soliloquy_patched.nes");
+        let ips_path = path.with_extension("ips");
+        let mut ips = b"PATCH".to_vec();
+        ips.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x01, 0x01]); //Header: vertical.
+        ips.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x01, 0x42]);
+        ips.extend_from_slice(b"EOF");
+        std::fs::write(&path, &rom).unwrap();
+        std::fs::write(&ips_path, &ips).unwrap();
+
+        //Found next to the ROM, applied before the header is read.
+        let cart = CART::from_path(&path).unwrap();
+        assert_eq!(cart.patches, vec!["soliloquy_patched.ips".to_string()]);
+        assert_eq!(cart.mirroring, Mirroring::Vertical);
+        assert_eq!(cart.PRG[0], 0x42);
+
+        //Explicit only.
+        let opts = LoadOptions {
+            auto_patch: false,
+            patches: vec![crate::core::patch::Patch::from_bytes(ips).unwrap()],
+            ..LoadOptions::default()
+        };
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+        assert_eq!(cart.patches, vec!["IPS patch".to_string()]);
+        assert_eq!(cart.PRG[0], 0x42);
+
+        let opts = LoadOptions { auto_patch: false, ..LoadOptions::default() };
+        let cart = CART::from_path_with(&path, &opts).unwrap();
+        assert!(cart.patches.is_empty());
+        assert_eq!(cart.PRG[0], 0x00);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&ips_path).unwrap();
+    }
+
+    //~~~UNIF~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    fn unif_chunk(image: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+        image.extend_from_slice(id);
+        image.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        image.extend_from_slice(data);
+    }
+
+    pub fn unif_image(board: &str) -> Vec<u8> {
+        let mut rom = b"UNIF".to_vec();
+        rom.extend_from_slice(&7u32.to_le_bytes());
+        rom.resize(32, 0);
+        unif_chunk(&mut rom, b"MAPR", format!("{}\0", board).as_bytes());
+        unif_chunk(&mut rom, b"NAME", b"Synthetic\0");
+        //Out of order on purpose, PRG1 still goes after PRG0.
+        unif_chunk(&mut rom, b"PRG1", &[0x11; 16384]);
+        unif_chunk(&mut rom, b"PRG0", &[0x00; 16384]);
+        unif_chunk(&mut rom, b"CHR0", &[0xCC; 8192]);
+        unif_chunk(&mut rom, b"MIRR", &[1]);
+        unif_chunk(&mut rom, b"BATR", &[1]);
+        unif_chunk(&mut rom, b"DINF", &[0; 204]);
+        rom
+    }
+
+    #[test]
+    fn test_unif(){
+        let opts = LoadOptions { database: None, ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(&unif_image("NES-NROM-256"), &opts).unwrap();
+        assert!(cart.unif_fmt);
+        assert!(!cart.ines_fmt);
+        assert_eq!(cart.board.as_deref(), Some("NES-NROM-256"));
+        assert_eq!(cart.mapper, 0);
+        assert_eq!(cart.PRG.len(), 32768);
+        assert_eq!((cart.PRG[0x3FFF], cart.PRG[0x4000]), (0x00, 0x11));
+        assert_eq!(cart.CHR, vec![0xCC; 8192]);
+        assert_eq!(cart.mirroring, Mirroring::Vertical);
+        assert!(cart.battery);
+        assert_eq!(cart.PRG_RAM.len(), 8192);
+
+        //And it boots.
+        crate::core::nes::NES::from_cart(cart).unwrap();
+
+        //Board names map through the table, prefixes and case aside.
+        assert_eq!(crate::core::unif::board_mapper("HVC-SNROM"), Some((1, 0)));
+        assert_eq!(crate::core::unif::board_mapper("unl-cnrom"), Some((3, 0)));
+        let cart = CART::from_bytes_with(&unif_image("NES-UNROM"), &opts).unwrap();
+        assert_eq!(cart.mapper, 2);
+
+        let err = CART::from_bytes_with(&unif_image("BMC-NOT-A-BOARD"), &opts).unwrap_err();
+        assert!(err.to_string().contains("BMC-NOT-A-BOARD"));
+
+        //Boards the registry knows but can't build are refused up front.
+        let err = CART::from_bytes_with(&unif_image("NES-CPROM"), &opts).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("CPROM"));
+    }
+
+    #[test]
+    fn test_unif_vror(){
+        //VROR keeps the CHR chunks, as the RAM's starting contents.
+        let opts = LoadOptions { database: None, ..LoadOptions::default() };
+        let mut rom = unif_image("NES-UNROM");
+        unif_chunk(&mut rom, b"VROR", &[1]);
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+        assert_eq!(cart.CHR, vec![0xCC; 8192]);
+        assert_eq!((cart.section_sizes[2], cart.chr_ram_size), (0, 8192));
+
+        let mut map = crate::core::mapper::new_map(cart.mapper, Box::new(cart)).unwrap();
+        map.set_chr(0x0010, 0x42);
+        assert_eq!((map.get_chr(0x0010), map.get_chr(0x0011)), (0x42, 0xCC));
+    }
+
+    #[test]
+    fn test_unif_validation(){
+        //A PRG chunk cut off partway through a bank.
+        let mut rom = b"UNIF".to_vec();
+        rom.extend_from_slice(&7u32.to_le_bytes());
+        rom.resize(32, 0);
+        unif_chunk(&mut rom, b"MAPR", b"NES-NROM-128\0");
+        unif_chunk(&mut rom, b"PRG0", &[0x00; 0x3000]);
+
+        let opts = LoadOptions { database: None, ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+        assert_eq!(cart.report.issues, vec![RomIssue::PrgTruncated { expected: 0x4000, found: 0x3000 }]);
+        assert!(!cart.report.repaired);
+        assert_eq!(cart.PRG.len(), 0x3000);
+
+        let opts = LoadOptions { validation: Validation::Repair, ..opts };
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+        assert!(cart.report.repaired);
+        assert_eq!((cart.PRG.len(), cart.PRG[0x2FFF], cart.PRG[0x3000]), (0x4000, 0x00, 0xFF));
+
+        let opts = LoadOptions { validation: Validation::Refuse, ..opts };
+        let err = CART::from_bytes_with(&rom, &opts).unwrap_err();
+        assert!(err.to_string().starts_with("ROM refused"));
+    }
+
+    //~~~HEADERS~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_ines_header(){
+        //Mapper 1, 8 x 16kb PRG, no CHR (so CHR-RAM), vertical, battery.
+        let cart = cart_with_head([0x4E, 0x45, 0x53, 0x1A, 8, 0, 0x13, 0x00,
+                                   0, 1, 0, 0, 0, 0, 0, 0]);
+        assert!(cart.ines_fmt);
+        assert!(!cart.nes2_fmt);
+        assert_eq!(cart.mapper, 1);
+        assert_eq!(cart.section_sizes[1], 8 * 16384);
+        assert_eq!(cart.section_sizes[2], 0);
+        assert_eq!(cart.chr_ram_size, 8192);
+        assert_eq!(cart.prg_nvram_size, 8192);
+        assert_eq!(cart.mirroring, Mirroring::Vertical);
+        assert_eq!(cart.timing, Timing::Pal);
+        assert!(cart.battery);
+    }
+
+    #[test]
+    fn test_nes2_header(){
+        //Mapper $1A3, submapper 5. PRG uses the long form ($102 x 16kb),
+        // CHR the exponent-multiplier form (2^10 * 3 bytes).
+        let cart = cart_with_head([0x4E, 0x45, 0x53, 0x1A,
+                                   0x02,        //PRG LSB
+                                   0b0010_1001, //CHR E=10, MM=1
+                                   0x32,        //Mapper D0..D3 = 3, battery
+                                   0xA9,        //Mapper D4..D7 = A, NES 2.0, Vs.
+                                   0x51,        //Submapper 5, mapper D8..D11 = 1
+                                   0xF1,        //CHR MSB = $F, PRG MSB = 1
+                                   0x97,        //PRG-NVRAM 32kb, PRG-RAM 8kb
+                                   0x07,        //CHR-RAM 8kb
+                                   0x03,        //Dendy
+                                   0x24,        //Vs. hardware 2, PPU 4
+                                   0x01,        //One misc ROM
+                                   0x2A]);      //Expansion $2A
+        assert!(cart.nes2_fmt);
+        assert_eq!(cart.mapper, 0x1A3);
+        assert_eq!(cart.submapper, 5);
+        assert_eq!(cart.section_sizes[1], 0x102 * 16384);
+        assert_eq!(cart.section_sizes[2], 1024 * 3);
+        assert_eq!(cart.prg_ram_size, 8192);
+        assert_eq!(cart.prg_nvram_size, 32768);
+        assert_eq!(cart.chr_ram_size, 8192);
+        assert_eq!(cart.chr_nvram_size, 0);
+        assert_eq!(cart.PRG_RAM.len(), 8192 + 32768);
+        assert_eq!(cart.timing, Timing::Dendy);
+        assert_eq!(cart.console, Console::VsSystem { ppu: 4, hardware: 2 });
+        assert_eq!(cart.misc_roms, 1);
+        assert_eq!(cart.expansion, 0x2A);
+    }
+
+    //~~~REWRITING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_header_round_trip(){
+        //Everything test_nes2_header decodes comes back out byte for byte,
+        // including both odd ROM size forms.
+        let head = [0x4E, 0x45, 0x53, 0x1A, 0x02, 0b0010_1001, 0x32, 0xA9,
+                    0x51, 0xF1, 0x97, 0x07, 0x03, 0x24, 0x01, 0x2A];
+        let cart = cart_with_head(head);
+        assert!(cart.needs_nes2());
+        assert_eq!(cart.header(true), head);
+
+        //iNES says 0 for 8kb of PRG-RAM, a rewrite spells it out.
+        let cart = cart_with_head([0x4E, 0x45, 0x53, 0x1A, 8, 0, 0x13, 0x00,
+                                   0, 1, 0, 0, 0, 0, 0, 0]);
+        assert!(!cart.needs_nes2());
+        let ines = cart.header(false);
+        assert_eq!(ines, [0x4E, 0x45, 0x53, 0x1A, 8, 0, 0x13, 0x00,
+                          1, 1, 0, 0, 0, 0, 0, 0]);
+        //And as NES 2.0, with the same meaning.
+        let nes2 = cart_with_head(cart.header(true));
+        assert!(nes2.nes2_fmt);
+        assert_eq!((nes2.mapper, nes2.battery, nes2.timing), (1, true, Timing::Pal));
+        assert_eq!((nes2.prg_nvram_size, nes2.chr_ram_size), (8192, 8192));
+        assert_eq!(nes2.section_sizes[1], 8 * 16384);
+    }
+
+    #[test]
+    fn test_fix_header(){
+        use crate::core::romdb::*;
+        use crate::core::hash::*;
+        use std::sync::Arc;
+
+        //Same lying cart as test_database_correction.
+        let rom = ines_image(0);
+        let xml = format!(r#"<nes20db><game><rom size="24576" crc32="{:08X}"/>
+                <prgnvram size="8192"/>
+                <pcb mapper="1" submapper="0" mirroring="V" battery="1"/>
+            </game></nes20db>"#, crc32(&rom[16..]));
+        let opts = LoadOptions { database: Some(Arc::new(GameDb::from_xml(&xml).unwrap())),
+                                 ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(&rom, &opts).unwrap();
+
+        //The rewrite has the database's fields, and the same PRG/CHR.
+        let fixed = cart.to_ines(false);
+        assert_eq!(fixed.len(), rom.len());
+        assert_eq!(&fixed[16..], &rom[16..]);
+        assert_eq!(fixed[6], 0x13);
+        let reread = CART::from_bytes_with(&fixed, &LoadOptions { database: None,
+                                                                  ..LoadOptions::default() }).unwrap();
+        assert_eq!(reread.mapper, 1);
+        assert_eq!(reread.mirroring, Mirroring::Vertical);
+        assert!(reread.battery);
+        assert!(reread.report.is_clean());
+
+        //The summary covers what `soliloquy info` promises.
+        let info = cart.to_string();
+        for line in ["Format:     iNES", "Mapper:     1 (MMC1 (SxROM))", "PRG-ROM:    16 KB",
+                     "CHR-ROM:    8 KB", "Mirroring:  Vertical", "Battery:    yes",
+                     "Trainer:    no", "Region:     Ntsc", "PRG-NVRAM:  8 KB",
+                     "Corrected mapper: 0 -> 1"].iter() {
+            assert!(info.contains(line), "{} missing from:\n{}", line, info);
+        }
+        assert!(info.contains(&format!("CRC32:      {:08X}", cart.crc32)));
+        assert!(info.contains(&format!("Database:   \"\" (CRC32: {:08X})", cart.crc32)));
+    }
+}
@@ -0,0 +1,65 @@
+/* Emulates the standard NES controller, on $4016 (port 1) and $4017 (port 2).
+ * Author: Spalynx
+ *--------------Protocol-----------------------------------------------
+ * Write $4016 bit 0 = 1    Strobe, the pad keeps reloading its buttons.
+ * Write $4016 bit 0 = 0    Latch, the buttons are held in a shift register.
+ * Read  $4016/$4017        Bit 0 is the next button, in the order:
+ *                          A, B, Select, Start, Up, Down, Left, Right.
+ *                          After 8 reads, official pads return 1s.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/Standard_controller
+ */
+
+pub const BUTTON_A:      u8 = 1 << 0;
+pub const BUTTON_B:      u8 = 1 << 1;
+pub const BUTTON_SELECT: u8 = 1 << 2;
+pub const BUTTON_START:  u8 = 1 << 3;
+pub const BUTTON_UP:     u8 = 1 << 4;
+pub const BUTTON_DOWN:   u8 = 1 << 5;
+pub const BUTTON_LEFT:   u8 = 1 << 6;
+pub const BUTTON_RIGHT:  u8 = 1 << 7;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Controller {
+    pub buttons:    u8,     //Currently held buttons, BUTTON_* bits.
+    pub shift:      u8,     //Latched buttons, shifted out one per read.
+    pub strobe:     bool,
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller { buttons: 0, shift: 0, strobe: false }
+    }
+
+    /// Called by the frontend with the BUTTON_* bits currently held.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    /// Write to $4016, only bit 0 (the strobe) matters.
+    pub fn write(&mut self, val: u8) {
+        self.strobe = val & 1 == 1;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    /// Read from $4016/$4017, shifts the next button out.
+    pub fn read(&mut self) -> u8 {
+        let val = self.peek();
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+        else {
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        val
+    }
+
+    /// What a read would return, without shifting anything.
+    /// Upper bits are open bus, which is usually $40 from the address.
+    pub fn peek(&self) -> u8 {
+        let bit = if self.strobe { self.buttons & 1 } else { self.shift & 1 };
+        0x40 | bit
+    }
+}
@@ -8,6 +8,7 @@
 
 //Imports
     use crate::core::memory::MEM;
+    use crate::core::power::PowerSource;
     pub use ::log::*;
 
 //Test module definition.
@@ -125,6 +126,26 @@ impl CPU {
             stall:          0,		        // number of cycles to stall
         }
     }
+    /// Applies the power-up state to the registers, and then the rest of
+    ///  the memory map.
+    /// SP and P are fixed by the hardware's reset sequence [nesdev:
+    ///  CPU_power_up_state], A/X/Y are left to the power-on policy.
+    /// PC comes from the reset vector once the cart is powered, like the
+    ///  reset sequence that follows power-up.
+    pub fn power_on(&mut self, src: &mut PowerSource) {
+        self.a          = src.byte();
+        self.x          = src.byte();
+        self.y          = src.byte();
+        self.sp         = 0xFD;
+        self.status     = 0x24;
+        self.cycles     = 0;
+        self.interrupt  = 0;
+        self.stall      = 0;
+        self.memory.power_on(src);
+        let lo = self.memory.get(0xFFFC) as u16;
+        let hi = self.memory.get(0xFFFD) as u16;
+        self.pc = (hi << 8) | lo;
+    }
     /// Though memory is already initialized, I felt it appropriate to
     /// keep the manual option available.
     pub fn new_memory(&mut self, mem: MEM){
@@ -163,10 +184,33 @@ impl CPU {
 
 
 
-    fn reset(&mut self){
-        self.sp = 0xFD;
+    /// Disassembles the instruction at an address, ie:
+    ///  "C000  4C F5 C5  JMP"
+    /// Memory is peeked, so this never disturbs the PPU/APU/controllers.
+    /// Returns the text, and the address of the following instruction.
+    pub fn disassemble(&self, address: u16) -> (String, u16) {
+        let opnum = self.memory.peek(address);
+        let size  = OP_SIZES[opnum as usize].max(1) as u16;
+
+        let bytes: Vec<String> = self.memory.peek_range(address, size as usize)
+            .iter().map(|b| format!("{:02X}", b)).collect();
+        let text = format!("{:04X}  {:<8}  {}", address, bytes.join(" "),
+                           OP_NAMES[opnum as usize]);
+
+        (text, address.wrapping_add(size))
+    }
+
+    /// The reset button. The CPU goes through the motions of an interrupt
+    ///  with writes disabled, so SP drops by 3 and nothing is pushed, then
+    ///  jumps through the reset vector at $FFFC.
+    pub fn reset(&mut self){
+        self.memory.reset();
+        self.sp = self.sp.wrapping_sub(3);
         self.set_status(2, true);
         self.memory.set(0x4015, 0);
+        let lo = self.memory.get(0xFFFC) as u16;
+        let hi = self.memory.get(0xFFFD) as u16;
+        self.pc = (hi << 8) | lo;
     }
 
     /// Receives a string as a param, and throws one of the 3 (?) cpu
@@ -182,6 +226,24 @@ impl CPU {
 
     }
 
+    /// Services /IRQ, unless the I flag masks it.
+    /// Like BRK, PC then P go on the stack, but with 'B' clear, and PC is
+    ///  loaded from the vector at $FFFE/$FFFF.
+    pub fn irq(&mut self) {
+        if self.status & (1 << 2) != 0 {
+            return;
+        }
+        self.stack_push(word_to_h_byte!(self.pc) as u8);
+        self.stack_push(word_to_l_byte!(self.pc) as u8);
+        let P: u8 = (self.status | (1 << 5)) & !(1 << 4);
+        self.stack_push(P);
+        self.SEI();
+
+        let PCL = self.memory.get(0xFFFE) as u16;
+        let PCH = self.memory.get(0xFFFF) as u16;
+        self.pc = bytes_to_word!(PCH, PCL);
+    }
+
 
     /// Sets flags based upon a given byte.  
     /// Bit crunching here to reduce overhead, as flags are going to change often.
@@ -1399,6 +1461,42 @@ pub static OP_SIZES:[u8; 256] =
     2, 0, 0, 2, 2, 2, 0, 1, 2, 1, 0, 3, 3, 3, 0, 2, 2, 0, 0, 0, 2, 2, 0, 1, 3,
     0, 0, 0, 3, 3, 0];
 
+/// Mnemonics of the official opcodes, unofficial ones are "???".
+/// Only used for disassembly, so it stays out of the hot path.
+pub static OP_NAMES: [&str; 256] =
+    ["BRK", "ORA", "???", "???", "???", "ORA", "ASL", "???",
+    "PHP", "ORA", "ASL", "???", "???", "ORA", "ASL", "???",
+    "BPL", "ORA", "???", "???", "???", "ORA", "ASL", "???",
+    "CLC", "ORA", "???", "???", "???", "ORA", "ASL", "???",
+    "JSR", "AND", "???", "???", "BIT", "AND", "ROL", "???",
+    "PLP", "AND", "ROL", "???", "BIT", "AND", "ROL", "???",
+    "BMI", "AND", "???", "???", "???", "AND", "ROL", "???",
+    "SEC", "AND", "???", "???", "???", "AND", "ROL", "???",
+    "RTI", "EOR", "???", "???", "???", "EOR", "LSR", "???",
+    "PHA", "EOR", "LSR", "???", "JMP", "EOR", "LSR", "???",
+    "BVC", "EOR", "???", "???", "???", "EOR", "LSR", "???",
+    "CLI", "EOR", "???", "???", "???", "EOR", "LSR", "???",
+    "RTS", "ADC", "???", "???", "???", "ADC", "ROR", "???",
+    "PLA", "ADC", "ROR", "???", "JMP", "ADC", "ROR", "???",
+    "BVS", "ADC", "???", "???", "???", "ADC", "ROR", "???",
+    "SEI", "ADC", "???", "???", "???", "ADC", "ROR", "???",
+    "???", "STA", "???", "???", "STY", "STA", "STX", "???",
+    "DEY", "???", "TXA", "???", "STY", "STA", "STX", "???",
+    "BCC", "STA", "???", "???", "STY", "STA", "STX", "???",
+    "TYA", "STA", "TXS", "???", "???", "STA", "???", "???",
+    "LDY", "LDA", "LDX", "???", "LDY", "LDA", "LDX", "???",
+    "TAY", "LDA", "TAX", "???", "LDY", "LDA", "LDX", "???",
+    "BCS", "LDA", "???", "???", "LDY", "LDA", "LDX", "???",
+    "CLV", "LDA", "TSX", "???", "LDY", "LDA", "LDX", "???",
+    "CPY", "CMP", "???", "???", "CPY", "CMP", "DEC", "???",
+    "INY", "CMP", "DEX", "???", "CPY", "CMP", "DEC", "???",
+    "BNE", "CMP", "???", "???", "???", "CMP", "DEC", "???",
+    "CLD", "CMP", "???", "???", "???", "CMP", "DEC", "???",
+    "CPX", "SBC", "???", "???", "CPX", "SBC", "INC", "???",
+    "INX", "SBC", "NOP", "???", "CPX", "SBC", "INC", "???",
+    "BEQ", "SBC", "???", "???", "???", "SBC", "INC", "???",
+    "SED", "SBC", "???", "???", "???", "SBC", "INC", "???"];
+
 /* Temporary dead code.
 
 macro_rules! vec_of_strings {
@@ -3,6 +3,8 @@
  *  Init: 6/17/18
  */
 use crate::core::cpu::*;
+use crate::core::power::PowerOn;
+use crate::core::controller::*;
 //use core::memory::*;
 
 #[cfg(test)]
@@ -95,6 +97,75 @@ pub mod cpu_test {
         //Further mem testing should be done in it's module.
     }
 
+    #[test]
+    fn test_power_on(){
+        let mut cpu = super::CPU::new_empty();
+
+        //Stripes of $00 x4, $FF x4, with the documented register values.
+        cpu.power_on(&mut PowerOn::Pattern.source());
+        assert_eq!(cpu.memory.get(0x03), 0x00);
+        assert_eq!(cpu.memory.get(0x04), 0xFF);
+        assert_eq!(cpu.a,      0);
+        assert_eq!(cpu.sp,     0xFD);
+        assert_eq!(cpu.status, 0x24);
+
+        cpu.power_on(&mut PowerOn::Ones.source());
+        assert_eq!(cpu.memory.get(0x7FF), 0xFF);
+        assert_eq!(cpu.x, 0xFF);
+
+        //The same seed has to give the same console, every time.
+        let mut other = super::CPU::new_empty();
+        cpu.power_on(&mut PowerOn::Random(1234).source());
+        other.power_on(&mut PowerOn::Random(1234).source());
+        assert_eq!((cpu.a, cpu.x, cpu.y), (other.a, other.x, other.y));
+        for address in 0..0x800 {
+            assert_eq!(cpu.memory.get(address), other.memory.get(address));
+        }
+        assert_eq!(cpu.memory.PPU.OAM[..], other.memory.PPU.OAM[..]);
+    }
+
+    #[test]
+    fn test_peek(){
+        let mut cpu = super::CPU::new_empty();
+
+        //PPUSTATUS, peeking must leave VBlank alone. Reading clears it.
+        cpu.memory.PPU.status = 0x80;
+        assert_eq!(cpu.memory.peek(0x2002) & 0x80, 0x80);
+        assert_eq!(cpu.memory.peek(0x2002) & 0x80, 0x80);
+        assert_eq!(cpu.memory.get(0x2002) & 0x80, 0x80);
+        assert_eq!(cpu.memory.peek(0x2002) & 0x80, 0);
+
+        //PPUDATA, peeking must not advance the buffer or the address.
+        cpu.memory.set(0x2006, 0x20);
+        cpu.memory.set(0x2006, 0x00);
+        cpu.memory.set(0x2007, 0x11);
+        cpu.memory.set(0x2006, 0x20);
+        cpu.memory.set(0x2006, 0x00);
+        let v = cpu.memory.PPU.v;
+        cpu.memory.peek(0x2007);
+        assert_eq!(cpu.memory.PPU.v, v);
+        cpu.memory.get(0x2007);                         //Dummy read.
+        assert_eq!(cpu.memory.peek(0x2007), 0x11);
+        assert_eq!(cpu.memory.get(0x2007), 0x11);
+
+        //Controllers, peeking must not shift the buttons out.
+        cpu.memory.INPUT[0].set_buttons(BUTTON_B);
+        cpu.memory.set(0x4016, 1);
+        cpu.memory.set(0x4016, 0);
+        assert_eq!(cpu.memory.peek(0x4016) & 1, 0);
+        assert_eq!(cpu.memory.peek(0x4016) & 1, 0);
+        assert_eq!(cpu.memory.get(0x4016) & 1, 0);     //A
+        assert_eq!(cpu.memory.peek(0x4016) & 1, 1);
+        assert_eq!(cpu.memory.get(0x4016) & 1, 1);     //B
+
+        //Disassembly goes through peek.
+        cpu.memory.set(0x0200, 0xA9);
+        cpu.memory.set(0x0201, 0x42);
+        let (text, next) = cpu.disassemble(0x0200);
+        assert_eq!(text, "0200  A9 42     LDA");
+        assert_eq!(next, 0x0202);
+    }
+
     #[test]
     fn test_AM(){
         //All of these are being tested in one large module because
@@ -182,6 +253,27 @@ pub mod cpu_test {
         assert!(false);
     }
 
+    #[test]
+    pub fn test_irq(){
+        let mut cpu = CPU::new_empty();
+        cpu.pc = 0x8123;
+
+        //Masked by I.
+        cpu.status = 0x24;
+        cpu.irq();
+        assert_eq!((cpu.pc, cpu.sp), (0x8123, 0xFD));
+
+        //PC and P go on the stack with 'B' clear, I is set.
+        cpu.status = 0x20 | 0x10 | 0x01;
+        cpu.irq();
+        assert_eq!(cpu.sp, 0xFA);
+        assert_eq!(cpu.status & 0x04, 0x04);
+        assert_eq!(cpu.pc, 0x0000);     //EMPTY_MAP vector.
+        cpu.RTI();
+        assert_eq!(cpu.pc, 0x8123);
+        assert_eq!(cpu.status, 0x21);
+    }
+
     //~~~CPU~OPCODES~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
     #[test]
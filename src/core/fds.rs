@@ -0,0 +1,889 @@
+/* Famicom Disk System: the RAM adapter, its disk drive and wavetable audio.
+ * The RAM adapter plugs into the cart slot, so it's just another MAP, the
+ *  disk BIOS has to be supplied by the user (disksys.rom).
+ * Author: Spalynx
+ *--------------CPU Memory Map-----------------------------------------
+ * $4020-$4026  Timer IRQ, master I/O enable, disk data/control (write).
+ * $4030-$4033  Disk status, read data, drive status, battery (read).
+ * $4040-$407F  Wavetable RAM.
+ * $4080-$408A  Audio registers (write), $4090/$4092 gains (read).
+ * $6000-$DFFF  32kb PRG-RAM, where disk files are loaded.
+ * $E000-$FFFF  8kb BIOS ROM.
+ * PPU $0000-$1FFF is 8kb of CHR-RAM, nametable mirroring is in $4025.
+ *--------------Disk Images--------------------------------------------
+ * .fds files (fwNES) hold each 65500 byte side as bare blocks, without
+ *  the gaps and CRCs the drive actually sees. Sides are expanded into a
+ *  raw stream on insert, and folded back when written out.
+ * Writes never touch the original image. Modified disks are saved next to
+ *  it as <name>.fds.sav, a whole .fds image, which is loaded in its place.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+ * http://wiki.nesdev.com/w/index.php/FDS_audio
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use ::log::*;
+use crate::core::mapper::MAP;
+use crate::core::ppu::Mirroring;
+use crate::core::power::PowerSource;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./fds_test.rs"]
+pub mod fds_test;
+
+/// Bytes in a side of an .fds image.
+pub const SIDE_SIZE: usize = 65500;
+/// The BIOS is a single 8kb ROM.
+pub const BIOS_SIZE: usize = 8192;
+
+//Gaps the drive sees: ~28300 bits of lead-in, ~976 bits between blocks.
+const GAP_START: usize = 28300 / 8;
+const GAP_BLOCK: usize = 976 / 8;
+//The drive moves ~96.4kbit/s, a byte every ~149 CPU cycles.
+const BYTE_CYCLES: u32 = 149;
+//Time for the head to get back to the start of the disk.
+const REWIND_CYCLES: u32 = 50000;
+//How long a disk is left out when switching sides, about half a second.
+const SWAP_CYCLES: u32 = 900_000;
+
+fn bad(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+//~~~IMAGES~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// An .fds disk image, split into its sides.
+#[derive(Clone, Debug, Default)]
+pub struct FdsImage {
+    pub path: Option<PathBuf>,  //The original image, None from memory.
+    pub header: bool,           //True if the file had the 16 byte fwNES header.
+    pub sides: Vec<Vec<u8>>,    //SIDE_SIZE bytes each.
+}
+
+impl FdsImage {
+    /// Splits an .fds image, with or without its fwNES header, into sides.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<FdsImage> {
+        let header = bytes.starts_with(b"FDS\x1A");
+        let data = if header { &bytes[16.min(bytes.len())..] } else { bytes };
+        if data.is_empty() || data.len() % SIDE_SIZE != 0 {
+            return Err(bad(format!("FDS image is {} bytes, not a multiple of {}.",
+                                   data.len(), SIDE_SIZE)));
+        }
+
+        let sides: Vec<Vec<u8>> = data.chunks(SIDE_SIZE).map(|s| s.to_vec()).collect();
+        for (i, side) in sides.iter().enumerate() {
+            if side[0] != 1 || &side[1..15] != b"*NINTENDO-HVC*" {
+                warn!("FDS      -> Side {} has no disk info block.", i);
+            }
+        }
+        if header && bytes[4] as usize != sides.len() {
+            warn!("FDS      -> Header says {} sides, image has {}.", bytes[4], sides.len());
+        }
+        Ok(FdsImage { path: None, header, sides })
+    }
+
+    /// Reads an .fds image from a file, with the disk's saved writes on
+    ///  top if there's a .fds.sav next to it.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<FdsImage> {
+        let mut image = FdsImage::from_bytes(&fs::read(path.as_ref())?)?;
+        image.path = Some(path.as_ref().to_path_buf());
+
+        let sav = match image.sav_path() {
+            Some(sav) if sav.exists() => sav,
+            _ => return Ok(image),
+        };
+        let saved = FdsImage::from_bytes(&fs::read(&sav)?)?;
+        if saved.sides.len() != image.sides.len() {
+            return Err(bad(format!("{} has {} sides, the disk has {}.", sav.display(),
+                                   saved.sides.len(), image.sides.len())));
+        }
+        image.sides = saved.sides;
+        debug!("COMPLETE -> FDS save read from {}.", sav.display());
+        Ok(image)
+    }
+
+    /// Where modified sides are saved: the image's path, plus ".sav".
+    pub fn sav_path(&self) -> Option<PathBuf> {
+        self.path.as_ref().map(|path| {
+            let mut sav = path.clone().into_os_string();
+            sav.push(".sav");
+            PathBuf::from(sav)
+        })
+    }
+
+    /// The image as an .fds file, keeping the header if it had one.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + self.sides.len() * SIDE_SIZE);
+        if self.header {
+            out.extend_from_slice(b"FDS\x1A");
+            out.push(self.sides.len() as u8);
+            out.extend_from_slice(&[0; 11]);
+        }
+        for side in self.sides.iter() {
+            out.extend_from_slice(side);
+        }
+        out
+    }
+}
+
+/// The disk's CRC-16 (reflected $8408), fed a bit at a time like the
+///  RAM adapter does. Over a block and its CRC, it comes out as 0.
+fn crc_update(crc: u16, val: u8) -> u16 {
+    let mut crc = crc;
+    for bit in 0..8 {
+        let carry = crc & 1;
+        crc >>= 1;
+        if carry == 1 {
+            crc ^= 0x8408;
+        }
+        if val & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+/// Length of the block starting at data[0], given the size of the last
+///  file header seen. None for anything that isn't a block.
+fn block_len(data: &[u8], file_size: usize) -> Option<usize> {
+    match data.first() {
+        Some(1) => Some(56),    //Disk info.
+        Some(2) => Some(2),     //File amount.
+        Some(3) => Some(16),    //File header.
+        Some(4) => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+/// Expands a side into what passes under the head: a gap, then each block
+///  behind a $80 start mark, followed by its CRC and another gap.
+fn side_to_raw(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; GAP_START];
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(len) = block_len(&side[pos..], file_size) {
+        if pos + len > side.len() {
+            break;
+        }
+        let block = &side[pos..pos + len];
+        if block[0] == 3 {
+            file_size = block[13] as usize | (block[14] as usize) << 8;
+        }
+
+        let crc = block.iter().fold(crc_update(0, 0x80), |crc, &b| crc_update(crc, b));
+        let crc = crc_update(crc_update(crc, 0), 0);
+        raw.push(0x80);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        raw.extend_from_slice(&[0; GAP_BLOCK]);
+        pos += len;
+    }
+    //Room past the last file, for the BIOS to write new ones.
+    raw.resize(raw.len().max(GAP_START + SIDE_SIZE), 0);
+    raw
+}
+
+/// Folds a raw side back down into bare blocks.
+fn raw_to_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+    loop {
+        while pos < raw.len() && raw[pos] == 0 {
+            pos += 1;
+        }
+        if pos >= raw.len() || raw[pos] != 0x80 {
+            break;
+        }
+        pos += 1;
+        let len = match block_len(&raw[pos..], file_size) {
+            Some(len) if pos + len <= raw.len() => len,
+            _ => break,
+        };
+        let block = &raw[pos..pos + len];
+        if block[0] == 3 {
+            file_size = block[13] as usize | (block[14] as usize) << 8;
+        }
+        side.extend_from_slice(block);
+        pos += len + 2;
+    }
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+//~~~DRIVE~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// The disk drive's media: which side is in, and what's on every side.
+/// This is the side switching API, through NES::disk().
+#[derive(Default)]
+pub struct DiskDrive {
+    image: FdsImage,
+    raw: Vec<Vec<u8>>,          //Each side, as the head sees it.
+    modified: Vec<bool>,
+    inserted: Option<usize>,
+    pending: Option<(usize, u32)>,  //Side to insert, and cycles until then.
+    changed: bool,              //The side under the head changed, see take_changed.
+}
+
+impl DiskDrive {
+    /// A drive with side 0 of the image inserted.
+    pub fn new(image: FdsImage) -> DiskDrive {
+        let raw: Vec<Vec<u8>> = image.sides.iter().map(|s| side_to_raw(s)).collect();
+        DiskDrive {
+            modified: vec![false; raw.len()],
+            inserted: if raw.is_empty() { None } else { Some(0) },
+            pending:  None,
+            changed:  false,
+            image,
+            raw,
+        }
+    }
+
+    /// Number of disk sides, ie: 2 for a single disk.
+    pub fn sides(&self) -> usize {
+        self.raw.len()
+    }
+    /// The side in the drive, if any.
+    pub fn inserted(&self) -> Option<usize> {
+        self.inserted
+    }
+    /// Takes the disk out.
+    pub fn eject(&mut self) {
+        self.inserted = None;
+        self.pending  = None;
+        self.changed  = true;
+    }
+    /// Puts a side in the drive straight away. Games only notice a change
+    ///  if they see the drive empty in between, see switch_side.
+    pub fn insert(&mut self, side: usize) -> io::Result<()> {
+        if side >= self.sides() {
+            return Err(bad(format!("No side {}, the image has {}.", side, self.sides())));
+        }
+        self.inserted = Some(side);
+        self.pending  = None;
+        self.changed  = true;
+        Ok(())
+    }
+    /// Ejects the disk, and inserts a side once the game has had time to
+    ///  notice, like flipping a real disk over.
+    pub fn switch_side(&mut self, side: usize) -> io::Result<()> {
+        if side >= self.sides() {
+            return Err(bad(format!("No side {}, the image has {}.", side, self.sides())));
+        }
+        self.inserted = None;
+        self.pending  = Some((side, SWAP_CYCLES));
+        self.changed  = true;
+        Ok(())
+    }
+
+    /// True if the BIOS has written to any side since the disk was loaded.
+    pub fn is_modified(&self) -> bool {
+        self.modified.iter().any(|&m| m)
+    }
+    /// The disk as an .fds image, including any writes.
+    pub fn image(&self) -> FdsImage {
+        let mut image = self.image.clone();
+        for (i, raw) in self.raw.iter().enumerate() {
+            if self.modified[i] {
+                image.sides[i] = raw_to_side(raw);
+            }
+        }
+        image
+    }
+    /// Writes modified disks out to the image's .fds.sav, the original
+    ///  image is left as it was.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.image.sav_path() {
+            Some(ref path) if self.is_modified() => path.clone(),
+            _ => return Ok(()),
+        };
+        fs::write(&path, self.image().to_bytes())?;
+        debug!("COMPLETE -> FDS write: {}", path.display());
+        Ok(())
+    }
+
+    fn clock(&mut self) {
+        if let Some((side, cycles)) = self.pending {
+            self.pending = if cycles == 0 {
+                self.inserted = Some(side);
+                self.changed  = true;
+                None
+            } else {
+                Some((side, cycles - 1))
+            };
+        }
+    }
+    fn len(&self) -> usize {
+        self.inserted.map_or(0, |s| self.raw[s].len())
+    }
+    /// True once after the side under the head changes, so the adapter
+    ///  can send the head back to the start. Sides differ in length.
+    fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+    fn read(&self, pos: usize) -> u8 {
+        self.inserted.and_then(|s| self.raw[s].get(pos)).copied().unwrap_or(0)
+    }
+    fn write(&mut self, pos: usize, val: u8) {
+        if let Some(s) = self.inserted {
+            if let Some(b) = self.raw[s].get_mut(pos) {
+                *b = val;
+                self.modified[s] = true;
+            }
+        }
+    }
+}
+
+//~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// One of the two FDS envelopes, volume ($4080) or modulation ($4084).
+#[derive(Clone, Copy, Default)]
+pub struct FdsEnvelope {
+    pub speed:      u8,
+    pub gain:       u8,
+    pub increase:   bool,
+    pub disabled:   bool,   //Gain is set directly.
+    timer:          u32,
+}
+
+impl FdsEnvelope {
+    fn write(&mut self, val: u8, master: u8) {
+        self.speed    = val & 0x3F;
+        self.increase = val & 0x40 != 0;
+        self.disabled = val & 0x80 != 0;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset(master);
+    }
+    fn reset(&mut self, master: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master as u32;
+    }
+    /// Ticks the envelope, true if the gain changed.
+    fn clock(&mut self, master: u8) -> bool {
+        if self.disabled || master == 0 {
+            return false;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return false;
+        }
+        self.reset(master);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+            true
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The RAM adapter's single wavetable channel, with frequency modulation.
+pub struct FdsAudio {
+    pub wave:           [u8; 64],   //6-bit samples, $4040-$407F.
+    pub volume:         FdsEnvelope,
+    pub modulation:     FdsEnvelope,
+    pub frequency:      u16,        //12 bits, $4082/$4083.
+    pub mod_frequency:  u16,        //12 bits, $4086/$4087.
+    pub mod_table:      [u8; 64],   //3-bit steps, written in pairs by $4088.
+    pub mod_counter:    i8,         //7-bit signed, $4085.
+    pub master_volume:  u8,         //$4089 bits 0-1.
+    pub master_speed:   u8,         //$408A.
+    pub wave_halt:      bool,
+    pub envelope_halt:  bool,
+    pub mod_halt:       bool,
+    pub wave_write:     bool,
+    wave_pos:           usize,
+    wave_acc:           u32,
+    mod_pos:            usize,
+    mod_acc:            u32,
+    mod_output:         i32,
+    output:             u8,
+}
+
+impl Default for FdsAudio {
+    fn default() -> FdsAudio { FdsAudio::new() }
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave:           [0; 64],
+            volume:         FdsEnvelope::default(),
+            modulation:     FdsEnvelope::default(),
+            frequency:      0,
+            mod_frequency:  0,
+            mod_table:      [0; 64],
+            mod_counter:    0,
+            master_volume:  0,
+            master_speed:   0xE8,
+            wave_halt:      true,
+            envelope_halt:  true,
+            mod_halt:       true,
+            wave_write:     false,
+            wave_pos:       0,
+            wave_acc:       0,
+            mod_pos:        0,
+            mod_acc:        0,
+            mod_output:     0,
+            output:         0,
+        }
+    }
+
+    /// Reads $4040-$409F. Only the wave RAM and the gains are readable.
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x4040..=0x407F if self.wave_write => self.wave[(address & 0x3F) as usize] | 0x40,
+            0x4040..=0x407F => self.wave[self.wave_pos] | 0x40,
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.modulation.gain | 0x40,
+            _ => 0,
+        }
+    }
+
+    /// Writes $4040-$408A.
+    pub fn write(&mut self, address: u16, val: u8) {
+        match address {
+            0x4040..=0x407F if self.wave_write => self.wave[(address & 0x3F) as usize] = val & 0x3F,
+            0x4080 => self.volume.write(val, self.master_speed),
+            0x4082 => self.frequency = (self.frequency & 0xF00) | val as u16,
+            0x4083 => {
+                self.frequency     = (self.frequency & 0xFF) | ((val as u16 & 0x0F) << 8);
+                self.wave_halt     = val & 0x80 != 0;
+                self.envelope_halt = val & 0x40 != 0;
+                if self.wave_halt {
+                    self.wave_pos = 0;
+                }
+                if self.envelope_halt {
+                    self.volume.reset(self.master_speed);
+                    self.modulation.reset(self.master_speed);
+                }
+            },
+            0x4084 => self.modulation.write(val, self.master_speed),
+            0x4085 => self.mod_counter = ((val << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0xF00) | val as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0xFF) | ((val as u16 & 0x0F) << 8);
+                self.mod_halt      = val & 0x80 != 0;
+                if self.mod_halt {
+                    self.mod_acc = 0;
+                }
+            },
+            0x4088 if self.mod_halt => {
+                //Each write fills two entries, the table is 32 steps long.
+                self.mod_table[self.mod_pos]     = val & 7;
+                self.mod_table[self.mod_pos + 1] = val & 7;
+                self.mod_pos = (self.mod_pos + 2) & 0x3F;
+            },
+            0x4089 => {
+                self.wave_write    = val & 0x80 != 0;
+                self.master_volume = val & 3;
+            },
+            0x408A => self.master_speed = val,
+            _ => {},
+        }
+    }
+
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.wave_halt && !self.envelope_halt {
+            self.volume.clock(self.master_speed);
+            self.modulation.clock(self.master_speed);
+        }
+
+        //Modulation unit steps the counter through the mod table.
+        if !self.mod_halt && self.mod_frequency > 0 {
+            self.mod_acc += self.mod_frequency as u32;
+            if self.mod_acc > 0xFFFF {
+                self.mod_acc -= 0x10000;
+                let counter = self.mod_counter as i32 + match self.mod_table[self.mod_pos] {
+                    0 => 0, 1 => 1, 2 => 2, 3 => 4, 5 => -4, 6 => -2, 7 => -1,
+                    _ => -(self.mod_counter as i32),   //4 resets to 0.
+                };
+                //7-bit signed, wrapping.
+                self.mod_counter = (((counter as u8) << 1) as i8) >> 1;
+                self.mod_pos = (self.mod_pos + 1) & 0x3F;
+            }
+        }
+        self.mod_output = self.pitch_offset();
+
+        if self.wave_halt {
+            self.wave_acc = 0;
+        }
+        else if !self.wave_write {
+            let pitch = self.frequency as i32 + self.mod_output;
+            if pitch > 0 {
+                self.wave_acc += pitch as u32;
+                if self.wave_acc > 0xFFFF {
+                    self.wave_acc -= 0x10000;
+                    self.wave_pos = (self.wave_pos + 1) & 0x3F;
+                }
+            }
+        }
+        //Output holds while the wave RAM is being written.
+        if !self.wave_write {
+            self.output = self.wave[self.wave_pos];
+        }
+    }
+
+    /// How far the modulator bends the pitch, straight from nesdev's
+    ///  description of the hardware's rounding.
+    fn pitch_offset(&self) -> i32 {
+        let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        let temp = self.frequency as i32 * temp;
+        let remainder = temp & 0x3F;
+        (temp >> 6) + if remainder >= 32 { 1 } else { 0 }
+    }
+
+    /// Current level, 0.0 to 1.0.
+    /// Gain tops out at 32, the master volume scales by 2/2, 2/3, 2/4, 2/5.
+    pub fn output(&self) -> f32 {
+        const MASTER: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+        let gain = self.volume.gain.min(32) as f32;
+        self.output as f32 * gain * MASTER[self.master_volume as usize] / (63.0 * 32.0)
+    }
+    /// output(), at its level in the system mix: about 2.4 times an APU
+    ///  pulse at full volume.
+    pub fn mixed(&self) -> f32 {
+        self.output() * 0.36
+    }
+}
+
+//~~~RAM ADAPTER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// The RAM adapter, as a mapper.
+#[allow(non_snake_case)]
+pub struct Fds {
+    pub BIOS:       Vec<u8>,
+    pub PRG_RAM:    Vec<u8>,    //32kb.
+    pub CHR_RAM:    Vec<u8>,    //8kb.
+    pub drive:      DiskDrive,
+    pub audio:      FdsAudio,
+
+    //$4020-$4023, timer and master I/O.
+    pub timer_reload:   u16,
+    pub timer_counter:  u16,
+    pub timer_repeat:   bool,
+    pub timer_enabled:  bool,
+    pub timer_irq:      bool,
+    pub disk_io:        bool,
+    pub sound_io:       bool,
+
+    //$4024-$4026, disk control.
+    pub write_data:     u8,
+    pub motor_on:       bool,
+    pub reset_transfer: bool,
+    pub read_mode:      bool,
+    pub mirroring:      Mirroring,
+    pub crc_control:    bool,
+    pub disk_ready:     bool,
+    pub disk_irq_enabled: bool,
+    pub ext_out:        u8,
+
+    //Drive state.
+    pub read_data:      u8,
+    pub disk_irq:       bool,
+    pub transfer_complete: bool,
+    pub end_of_head:    bool,
+    pub scanning:       bool,
+    position:           usize,
+    delay:              u32,
+    gap_ended:          bool,
+    crc:                u16,
+    prev_crc_control:   bool,
+}
+
+impl Fds {
+    /// Builds the RAM adapter around a BIOS and a disk image.
+    pub fn new(bios: Vec<u8>, image: FdsImage) -> io::Result<Fds> {
+        if bios.len() != BIOS_SIZE {
+            return Err(bad(format!("FDS BIOS is {} bytes, should be {}.", bios.len(), BIOS_SIZE)));
+        }
+        Ok(Fds::build(bios, DiskDrive::new(image)))
+    }
+
+    /// The adapter as it comes up, with whatever disk is in the drive.
+    fn build(bios: Vec<u8>, drive: DiskDrive) -> Fds {
+        Fds {
+            BIOS:       bios,
+            PRG_RAM:    vec![0; 0x8000],
+            CHR_RAM:    vec![0; 0x2000],
+            drive,
+            audio:      FdsAudio::new(),
+
+            timer_reload:   0,
+            timer_counter:  0,
+            timer_repeat:   false,
+            timer_enabled:  false,
+            timer_irq:      false,
+            disk_io:        true,
+            sound_io:       true,
+
+            write_data:     0,
+            motor_on:       false,
+            reset_transfer: false,
+            read_mode:      true,
+            mirroring:      Mirroring::Horizontal,
+            crc_control:    false,
+            disk_ready:     false,
+            disk_irq_enabled: false,
+            ext_out:        0,
+
+            read_data:      0,
+            disk_irq:       false,
+            transfer_complete: false,
+            end_of_head:    true,
+            scanning:       false,
+            position:       0,
+            delay:          0,
+            gap_ended:      false,
+            crc:            0,
+            prev_crc_control: false,
+        }
+    }
+
+    /// One CPU cycle of the timer.
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.timer_counter = self.timer_reload;
+            self.timer_irq = true;
+            if !self.timer_repeat {
+                self.timer_enabled = false;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// One CPU cycle of the drive. Bytes pass under the head every
+    ///  BYTE_CYCLES while the motor runs, and the head goes back to the
+    ///  start once it reaches the end.
+    fn clock_drive(&mut self) {
+        self.drive.clock();
+        if self.drive.take_changed() {
+            self.end_of_head = true;
+            self.scanning    = false;
+        }
+        if self.drive.inserted().is_none() || !self.motor_on {
+            self.end_of_head = true;
+            self.scanning    = false;
+            return;
+        }
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay       = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position    = 0;
+            self.gap_ended   = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut need_irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let data = self.drive.read(self.position);
+            if !self.prev_crc_control {
+                self.crc = crc_update(self.crc, data);
+            }
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            }
+            else if data != 0 && !self.gap_ended {
+                //The $80 start mark, no IRQ for it.
+                self.gap_ended = true;
+                need_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= need_irq;
+            }
+        }
+        else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                self.disk_irq |= need_irq;
+            }
+            if !self.disk_ready {
+                data = 0;
+            }
+            if !self.crc_control {
+                self.crc = crc_update(self.crc, data);
+            }
+            else {
+                if !self.prev_crc_control {
+                    self.crc = crc_update(crc_update(self.crc, 0), 0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.drive.write(self.position, data);
+            self.gap_ended = false;
+        }
+        self.prev_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= self.drive.len() {
+            self.end_of_head = true;
+            self.scanning    = false;
+        } else {
+            self.delay = BYTE_CYCLES - 1;
+        }
+    }
+
+    /// $4030, without acknowledging anything.
+    fn status(&self) -> u8 {
+        (self.timer_irq as u8)
+            | (self.transfer_complete as u8) << 1
+            | ((self.crc_control && self.crc != 0) as u8) << 4
+            | (self.end_of_head as u8) << 6
+            | ((self.drive.inserted().is_some() && self.scanning) as u8) << 7
+    }
+    /// $4032.
+    fn drive_status(&self) -> u8 {
+        let empty = self.drive.inserted().is_none();
+        0x40 | (empty as u8)
+             | ((empty || !self.scanning) as u8) << 1
+             | (empty as u8) << 2
+    }
+}
+
+impl MAP for Fds {
+    fn get(&mut self, address: u16) -> u8 {
+        match address {
+            0x4030 if self.disk_io => {
+                let val = self.status();
+                self.timer_irq = false;
+                self.disk_irq  = false;
+                self.transfer_complete = false;
+                val
+            },
+            0x4031 if self.disk_io => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            },
+            _ => self.peek(address),
+        }
+    }
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x4030 if self.disk_io => self.status(),
+            0x4031 if self.disk_io => self.read_data,
+            0x4032 if self.disk_io => self.drive_status(),
+            0x4033 if self.disk_io => 0x80,    //Battery is good.
+            0x4040..=0x409F if self.sound_io => self.audio.read(address),
+            0x6000..=0xDFFF => self.PRG_RAM[address as usize - 0x6000],
+            0xE000..=0xFFFF => self.BIOS[address as usize - 0xE000],
+            _ => 0,
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        match address {
+            0x4023 => {
+                self.disk_io  = val & 1 != 0;
+                self.sound_io = val & 2 != 0;
+                if !self.disk_io {
+                    self.timer_enabled = false;
+                    self.timer_irq     = false;
+                    self.disk_irq      = false;
+                }
+            },
+            _ if !self.disk_io && (0x4020..=0x4026).contains(&address) => {},
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | val as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (val as u16) << 8,
+            0x4022 => {
+                self.timer_repeat  = val & 1 != 0;
+                self.timer_enabled = val & 2 != 0;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            },
+            0x4024 => {
+                self.write_data = val;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            },
+            0x4025 => {
+                self.motor_on       = val & 0x01 != 0;
+                self.reset_transfer = val & 0x02 != 0;
+                self.read_mode      = val & 0x04 != 0;
+                self.mirroring      = if val & 0x08 != 0 { Mirroring::Horizontal }
+                                      else { Mirroring::Vertical };
+                self.crc_control    = val & 0x10 != 0;
+                self.disk_ready     = val & 0x40 != 0;
+                self.disk_irq_enabled = val & 0x80 != 0;
+                self.disk_irq = false;
+            },
+            0x4026 => self.ext_out = val,
+            0x4040..=0x408A if self.sound_io => self.audio.write(address, val),
+            0x6000..=0xDFFF => self.PRG_RAM[address as usize - 0x6000] = val,
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        self.CHR_RAM[(address & 0x1FFF) as usize]
+    }
+    fn set_chr(&mut self, address: u16, val: u8) {
+        self.CHR_RAM[(address & 0x1FFF) as usize] = val;
+    }
+    fn clock(&mut self) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+    fn disk(&mut self) -> Option<&mut DiskDrive> {
+        Some(&mut self.drive)
+    }
+    fn audio(&self) -> f32 {
+        self.audio.mixed()
+    }
+    /// Registers, timer and drive come up like new(), RAM from the policy.
+    /// The disk stays in, writes and all.
+    fn power(&mut self, src: &mut PowerSource) {
+        let drive = std::mem::take(&mut self.drive);
+        *self = Fds::build(std::mem::take(&mut self.BIOS), drive);
+        src.fill(&mut self.PRG_RAM);
+        src.fill(&mut self.CHR_RAM);
+    }
+    /// There's no battery RAM, the disk's writes get saved instead.
+    fn flush_sav(&self) -> io::Result<()> {
+        self.drive.save()
+    }
+}
@@ -0,0 +1,373 @@
+/*  Unit test module of the Famicom Disk System (fds.rs).
+ *  Disks are built by hand, with a blank BIOS, so the drive and timer are
+ *   driven through their registers the way the BIOS would.
+ */
+use crate::core::fds::*;
+
+#[cfg(test)]
+pub mod fds_test {
+    use super::*;
+
+    /// A side with a disk info block, a file amount block, and one 4 byte
+    ///  file. The second side is the same, with a different file.
+    pub fn synthetic_image() -> Vec<u8> {
+        let mut image = b"FDS\x1A".to_vec();
+        image.push(2);
+        image.extend_from_slice(&[0; 11]);
+        for side in 0..2u8 {
+            let mut data = vec![1];
+            data.extend_from_slice(b"*NINTENDO-HVC*");
+            data.resize(56, side);
+            data.extend_from_slice(&[2, 1]);
+            let mut header = vec![3, 0, 0, b'F', b'I', b'L', b'E', b'0', b'0', b'0', b'0',
+                                  0x00, 0x60, 4, 0, 0];
+            header[7] += side;
+            data.extend_from_slice(&header);
+            data.extend_from_slice(&[4, 0xDE, 0xAD, 0xBE, side]);
+            data.resize(SIDE_SIZE, 0);
+            image.extend_from_slice(&data);
+        }
+        image
+    }
+
+    pub fn synthetic_fds() -> Fds {
+        let image = FdsImage::from_bytes(&synthetic_image()).unwrap();
+        Fds::new(vec![0; BIOS_SIZE], image).unwrap()
+    }
+
+    /// Clocks the drive until it hands over a byte, and reads it.
+    fn next_byte(fds: &mut Fds) -> u8 {
+        //The lead-in gap alone is ~3500 bytes.
+        for _ in 0..1_000_000 {
+            fds.clock();
+            if fds.transfer_complete {
+                return fds.get(0x4031);
+            }
+        }
+        panic!("Drive never transferred a byte.");
+    }
+
+    /// Motor on, read mode, and wait for the head to get to the start.
+    fn spin_up(fds: &mut Fds, control: u8) {
+        fds.set(0x4025, 0x25 | control);
+        while !fds.scanning {
+            fds.clock();
+        }
+    }
+
+    //~~~IMAGES~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_image(){
+        let bytes = synthetic_image();
+        let image = FdsImage::from_bytes(&bytes).unwrap();
+        assert!(image.header);
+        assert_eq!(image.sides.len(), 2);
+        assert_eq!(image.to_bytes(), bytes);
+
+        //Headerless images work too.
+        let image = FdsImage::from_bytes(&bytes[16..]).unwrap();
+        assert!(!image.header);
+        assert_eq!(image.to_bytes(), &bytes[16..]);
+
+        assert!(FdsImage::from_bytes(&bytes[..1000]).is_err());
+        assert!(Fds::new(vec![0; 100], image).is_err());
+
+        //Unmodified disks fold back to exactly what went in.
+        let fds = synthetic_fds();
+        assert!(!fds.drive.is_modified());
+        assert_eq!(fds.drive.image().to_bytes(), bytes);
+    }
+
+    //~~~DRIVE~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_drive_read(){
+        let mut fds = synthetic_fds();
+        assert_eq!(fds.peek(0x4032) & 0b111, 0b010);  //Inserted, not ready.
+        assert_eq!(fds.peek(0x4033), 0x80);
+
+        spin_up(&mut fds, 0x40);
+        assert_eq!(fds.peek(0x4032) & 0b111, 0b000);
+
+        //The start mark comes first, then the block.
+        assert_eq!(next_byte(&mut fds), 0x80);
+        let block: Vec<u8> = (0..56).map(|_| next_byte(&mut fds)).collect();
+        assert_eq!(&block[..15], b"\x01*NINTENDO-HVC*");
+
+        //Bytes come ~149 cycles apart.
+        let mut cycles = 0;
+        while !fds.transfer_complete {
+            fds.clock();
+            cycles += 1;
+        }
+        assert!((140..160).contains(&cycles), "{} cycles", cycles);
+
+        //Reading the CRC through leaves the check clean.
+        fds.get(0x4031);
+        next_byte(&mut fds);
+        fds.set(0x4025, 0x25 | 0x40 | 0x10);
+        assert_eq!(fds.peek(0x4030) & 0x10, 0);
+    }
+
+    #[test]
+    fn test_drive_crc_error(){
+        let mut fds = synthetic_fds();
+        //A bad byte on the disk itself, behind its CRC's back.
+        fds.drive.raw[0][GAP_START + 20] ^= 0xFF;
+
+        spin_up(&mut fds, 0x40);
+        assert_eq!(next_byte(&mut fds), 0x80);
+        for _ in 0..58 { next_byte(&mut fds); }
+        fds.set(0x4025, 0x25 | 0x40 | 0x10);
+        assert_eq!(fds.peek(0x4030) & 0x10, 0x10);
+    }
+
+    #[test]
+    fn test_drive_irq(){
+        let mut fds = synthetic_fds();
+        spin_up(&mut fds, 0xC0);
+        //No IRQ for the start mark.
+        assert_eq!(next_byte(&mut fds), 0x80);
+        assert!(!fds.irq());
+        while !fds.irq() {
+            fds.clock();
+        }
+        //Reading the data acknowledges it.
+        assert_eq!(fds.get(0x4031), 0x01);
+        assert!(!fds.irq());
+    }
+
+    #[test]
+    fn test_drive_write(){
+        let path = std::env::temp_dir().join("soliloquy_writeback.fds");
+        let sav = std::env::temp_dir().join("soliloquy_writeback.fds.sav");
+        std::fs::write(&path, synthetic_image()).unwrap();
+        let _ = std::fs::remove_file(&sav);
+        let image = FdsImage::from_path(&path).unwrap();
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], image).unwrap();
+
+        //Find the file data, then switch to writing over it.
+        spin_up(&mut fds, 0x40);
+        let mut last = [0u8; 3];
+        while last != [0xDE, 0xAD, 0xBE] {
+            last = [last[1], last[2], next_byte(&mut fds)];
+        }
+        fds.set(0x4024, 0x42);
+        fds.set(0x4025, 0x21 | 0x40);   //Write mode.
+        while !fds.transfer_complete {
+            fds.clock();
+        }
+        assert!(fds.drive.is_modified());
+        let side = &fds.drive.image().sides[0];
+        let file = 56 + 2 + 16 + 1;
+        assert_eq!(&side[file..file + 4], &[0xDE, 0xAD, 0xBE, 0x42]);
+
+        //Saved next to the image, header and all, the image is untouched.
+        fds.flush_sav().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), synthetic_image());
+        let saved = FdsImage::from_bytes(&std::fs::read(&sav).unwrap()).unwrap();
+        assert!(saved.header);
+        assert_eq!(saved.sides[0][file + 3], 0x42);
+
+        //And loaded on top of the image next time.
+        let back = FdsImage::from_path(&path).unwrap();
+        assert_eq!(back.sides[0][file + 3], 0x42);
+        assert_eq!(back.sides[1], fds.drive.image().sides[1]);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sav).unwrap();
+    }
+
+    #[test]
+    fn test_switch_side(){
+        let mut fds = synthetic_fds();
+        assert_eq!(fds.drive.sides(), 2);
+        assert_eq!(fds.drive.inserted(), Some(0));
+
+        fds.drive.switch_side(1).unwrap();
+        assert_eq!(fds.peek(0x4032) & 1, 1);    //Empty while flipping.
+        while fds.drive.inserted().is_none() {
+            fds.clock();
+        }
+        assert_eq!(fds.drive.inserted(), Some(1));
+        assert!(fds.drive.switch_side(2).is_err());
+
+        fds.drive.eject();
+        assert_eq!(fds.peek(0x4032) & 0b111, 0b111);
+        fds.drive.insert(1).unwrap();
+
+        //Side B's file is what comes off the disk.
+        spin_up(&mut fds, 0x40);
+        let mut last = [0u8; 3];
+        while last != [0xDE, 0xAD, 0xBE] {
+            last = [last[1], last[2], next_byte(&mut fds)];
+        }
+        assert_eq!(next_byte(&mut fds), 1);
+    }
+
+    #[test]
+    fn test_switch_to_shorter_side(){
+        //Side A's file grows to 65300 bytes, so its raw side outruns B's.
+        let mut image = synthetic_image();
+        let side_a = 16 + 56 + 2;
+        image[side_a + 13..side_a + 15].copy_from_slice(&65300u16.to_le_bytes());
+        let image = FdsImage::from_bytes(&image).unwrap();
+        let mut fds = Fds::new(vec![0; BIOS_SIZE], image).unwrap();
+        let long = fds.drive.len();
+        fds.drive.insert(1).unwrap();
+        assert!(fds.drive.len() < long);
+        fds.drive.insert(0).unwrap();
+
+        //Mid-read, near the end of side A, B goes in.
+        spin_up(&mut fds, 0x40);
+        fds.position = long - 2;
+        fds.drive.insert(1).unwrap();
+        for _ in 0..BYTE_CYCLES * 4 {
+            fds.clock();
+        }
+        assert!(fds.position < fds.drive.len());
+
+        //The head starts side B over.
+        let mut last = [0u8; 3];
+        while last != [0xDE, 0xAD, 0xBE] {
+            last = [last[1], last[2], next_byte(&mut fds)];
+        }
+        assert_eq!(next_byte(&mut fds), 1);
+    }
+
+    //~~~TIMER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_timer_irq(){
+        let mut fds = synthetic_fds();
+        fds.set(0x4020, 10);
+        fds.set(0x4021, 0);
+        fds.set(0x4022, 0b11);      //Enabled, repeating.
+        for _ in 0..10 { fds.clock(); }
+        assert!(!fds.irq());
+        fds.clock();
+        assert!(fds.irq());
+        assert_eq!(fds.get(0x4030) & 1, 1);
+        assert!(!fds.irq());
+
+        //Repeats.
+        for _ in 0..11 { fds.clock(); }
+        assert!(fds.irq());
+
+        //Turning off disk I/O stops it.
+        fds.set(0x4023, 0);
+        assert!(!fds.irq());
+        fds.set(0x4022, 0b11);
+        for _ in 0..50 { fds.clock(); }
+        assert!(!fds.irq());
+    }
+
+    //~~~MEMORY~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_memory(){
+        let mut bios = vec![0; BIOS_SIZE];
+        bios[0x1FFC] = 0x24;
+        let image = FdsImage::from_bytes(&synthetic_image()).unwrap();
+        let mut fds = Fds::new(bios, image).unwrap();
+
+        fds.set(0x6000, 1);
+        fds.set(0xDFFF, 2);
+        fds.set(0xFFFC, 9);     //BIOS is ROM.
+        assert_eq!((fds.peek(0x6000), fds.peek(0xDFFF), fds.peek(0xFFFC)), (1, 2, 0x24));
+
+        fds.set_chr(0x1FFF, 7);
+        assert_eq!(fds.get_chr(0x1FFF), 7);
+
+        fds.set(0x4025, 0x08);
+        assert_eq!(fds.mirroring(), Some(Mirroring::Horizontal));
+        fds.set(0x4025, 0x00);
+        assert_eq!(fds.mirroring(), Some(Mirroring::Vertical));
+    }
+
+    #[test]
+    fn test_power(){
+        use crate::core::power::PowerOn;
+        let mut fds = synthetic_fds();
+        fds.set(0x6000, 1);
+        fds.set_chr(0x0000, 2);
+        fds.set(0x4020, 0);
+        fds.set(0x4022, 0x03);      //Timer on, repeating, and fired.
+        fds.clock();
+        spin_up(&mut fds, 0x40);
+        fds.drive.insert(1).unwrap();
+        assert!(fds.irq());
+
+        //RAM from the policy, everything else back like new, same disk in.
+        fds.power(&mut PowerOn::Ones.source());
+        assert_eq!((fds.peek(0x6000), fds.peek(0xDFFF), fds.get_chr(0x0000)), (0xFF, 0xFF, 0xFF));
+        assert!(!fds.irq());
+        assert!(!fds.timer_enabled && !fds.motor_on && !fds.scanning && fds.end_of_head);
+        assert_eq!(fds.drive.inserted(), Some(1));
+        assert_eq!(fds.drive.sides(), 2);
+        fds.power(&mut PowerOn::Zeros.source());
+        assert_eq!(fds.peek(0x6000), 0x00);
+    }
+
+    //~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_audio(){
+        let mut fds = synthetic_fds();
+        //Wave RAM only takes writes with $4089.7 set.
+        fds.set(0x4040, 63);
+        assert_eq!(fds.audio.wave[0], 0);
+        fds.set(0x4089, 0x80);
+        for i in 0..64 {
+            fds.set(0x4040 + i, if i < 32 { 63 } else { 0 });
+        }
+        assert_eq!(fds.peek(0x4040), 63 | 0x40);
+        fds.set(0x4089, 0x00);
+
+        //Direct volume, gain 32.
+        fds.set(0x4080, 0x80 | 32);
+        assert_eq!(fds.peek(0x4090) & 0x3F, 32);
+        fds.set(0x4082, 0x00);
+        fds.set(0x4083, 0x08);      //Frequency $800, running.
+
+        //A square wave: both halves show up.
+        let mut levels = Vec::new();
+        for _ in 0..4096 {
+            fds.clock();
+            levels.push(fds.audio.output());
+        }
+        assert!(levels.iter().any(|&l| l > 0.9));
+        assert!(levels.contains(&0.0));
+
+        //Master volume scales it down.
+        fds.set(0x4089, 0x03);
+        for _ in 0..4096 {
+            fds.clock();
+            assert!(fds.audio.output() <= 0.41);
+        }
+
+        //Halting the wave resets it.
+        fds.set(0x4083, 0x80);
+        fds.clock();
+        assert!((fds.audio.output() - 0.4).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_audio_envelope_and_mod(){
+        let mut fds = synthetic_fds();
+        fds.set(0x408A, 1);             //Fastest master speed.
+        fds.set(0x4080, 0x40);          //Increase, speed 0.
+        fds.set(0x4083, 0x01);
+        for _ in 0..(8 * 40) { fds.clock(); }
+        assert_eq!(fds.peek(0x4090) & 0x3F, 32);
+
+        //Mod table goes in two entries at a time, while halted.
+        fds.set(0x4087, 0x80);
+        for v in [1, 2, 3, 4, 5, 6, 7, 0].iter().cycle().take(32) {
+            fds.set(0x4088, *v);
+        }
+        assert_eq!(&fds.audio.mod_table[..4], &[1, 1, 2, 2]);
+        fds.set(0x4085, 0x7F);          //-1, 7-bit signed.
+        assert_eq!(fds.audio.mod_counter, -1);
+        fds.set(0x4084, 0x80 | 10);
+        fds.set(0x4086, 0xFF);
+        fds.set(0x4087, 0x0F);          //Running, fastest.
+        for _ in 0..20 { fds.clock(); }
+        assert_ne!(fds.audio.mod_counter, -1);
+    }
+}
@@ -0,0 +1,74 @@
+/* First order audio filters, and the chain the NES puts its output
+ *  through on the way to the RF/AV jack.
+ * Author: Spalynx
+ *--------------NES Output Chain---------------------------------------
+ * High-pass     90 Hz
+ * High-pass    440 Hz
+ * Low-pass      14 kHz
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/APU_Mixer
+ */
+
+use std::f32::consts::PI;
+
+/// Removes DC and rumble below a cutoff.
+#[derive(Clone, Copy, Debug)]
+pub struct HighPass {
+    alpha:      f32,
+    prev_in:    f32,
+    prev_out:   f32,
+}
+
+impl HighPass {
+    pub fn new(cutoff: f32, sample_rate: f32) -> HighPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        HighPass { alpha: rc / (rc + dt), prev_in: 0.0, prev_out: 0.0 }
+    }
+    pub fn step(&mut self, x: f32) -> f32 {
+        self.prev_out = self.alpha * (self.prev_out + x - self.prev_in);
+        self.prev_in  = x;
+        self.prev_out
+    }
+}
+
+/// Rolls off everything above a cutoff.
+#[derive(Clone, Copy, Debug)]
+pub struct LowPass {
+    alpha:      f32,
+    prev_out:   f32,
+}
+
+impl LowPass {
+    pub fn new(cutoff: f32, sample_rate: f32) -> LowPass {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        LowPass { alpha: dt / (rc + dt), prev_out: 0.0 }
+    }
+    pub fn step(&mut self, x: f32) -> f32 {
+        self.prev_out += self.alpha * (x - self.prev_out);
+        self.prev_out
+    }
+}
+
+/// The NES' own output filters, run at the output sample rate.
+#[derive(Clone, Copy, Debug)]
+pub struct NesFilter {
+    high_90:    HighPass,
+    high_440:   HighPass,
+    low_14k:    LowPass,
+}
+
+impl NesFilter {
+    pub fn new(sample_rate: u32) -> NesFilter {
+        let rate = sample_rate as f32;
+        NesFilter {
+            high_90:    HighPass::new(90.0, rate),
+            high_440:   HighPass::new(440.0, rate),
+            low_14k:    LowPass::new(14000.0, rate),
+        }
+    }
+    pub fn step(&mut self, x: f32) -> f32 {
+        self.low_14k.step(self.high_440.step(self.high_90.step(x)))
+    }
+}
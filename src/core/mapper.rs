@@ -1,37 +1,140 @@
 pub use crate::core::{cartridge::*};
+use crate::core::ppu::Mirroring;
+use crate::core::fds::DiskDrive;
+use crate::core::power::PowerSource;
+use crate::core::registry::{find_mapper, MapperInfo};
+use std::io;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./mapper_test.rs"]
+pub mod mapper_test;
+#[cfg(test)]
+pub(crate) use mapper_test::mapper_test::banked_cart;
 
 /// new_map initializes a Boxed struct with the mapper trait to act as
 ///  a mapper in the memory map for the cpu.
-/// A mapper number is supplied, and a simple match selects the appropriate val
-pub fn new_map (map_num: u8, cart: Box<CART>) -> Box<dyn MAP> {
+/// A mapper number is supplied, and the registry finds the constructor for
+///  it and the cart's submapper, see registry.rs.
+/// Boards that aren't emulated are an Unsupported error, not a panic.
+pub fn new_map (map_num: u16, cart: Box<CART>) -> io::Result<Box<dyn MAP>> {
     debug!("START -> Mapper Initialization in mappper #{}.", map_num);
-    match map_num {
-        0 => Box::new(Nrom{cart}) as Box<dyn MAP>,
-        1 => Box::new(MMC1{cart}) as Box<dyn MAP>,
-        _ => panic!("Mapper {} has not been implemented!", map_num),
-    }
+    let msg = match find_mapper(map_num, cart.submapper) {
+        Some(MapperInfo { new: Some(new), .. }) => return Ok(new(cart)),
+        Some(info) => format!("Mapper {} ({}) has not been implemented!", map_num, info.name),
+        None => format!("Mapper {} has not been implemented!", map_num),
+    };
+    Err(io::Error::new(io::ErrorKind::Unsupported, msg))
 }
 
-/// Basic MAP trait, to be used in mappers.
-/// So far, only has a get and set function, but might eventually
-///  have bank switching functions.
+/// The MAP trait, everything the console can do to a cart.
+/// Only the CPU/CHR accessors are required. The rest are hooks with do-nothing
+///  defaults, so simple boards stay simple:
+///  CPU bus        get, peek, set
+///  PPU bus        get_chr, set_chr, fetch_chr, ppu_bus
+///  Nametables     mirroring, nametable, fetch_nametable, set_nametable
+///  Timing/IRQ     clock, irq
+///  Power          power, reset, flush_sav
+///  Extras         audio, disk
 pub trait MAP {
-    fn get(&self, address: u16) -> u8;
+    //~~~CPU bus~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// CPU read. Mappers with read side effects (IRQ acknowledges, latches)
+    ///  override this, everything else just peeks.
+    fn get(&mut self, address: u16) -> u8 { self.peek(address) }
+    /// CPU read without side effects, for debuggers and memory viewers.
+    fn peek(&self, address: u16) -> u8;
     fn set(&mut self, address: u16, val: u8);
+
+    //~~~PPU bus~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
     fn get_chr(&self, address: u16) -> u8;
     fn set_chr(&mut self, address: u16, val: u8);
+    /// A CHR read by the PPU itself, with the real fetch address. Latch
+    ///  boards (MMC2/MMC4) override this, everything else just reads.
+    fn fetch_chr(&mut self, address: u16) -> u8 { self.get_chr(address) }
+    /// Every address the PPU puts on its bus, for mappers that watch it,
+    ///  like MMC3's A12 scanline counter.
+    fn ppu_bus(&mut self, _address: u16) {}
+
+    //~~~Nametables~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// Nametable mirroring, for mappers that switch it. None leaves the
+    ///  PPU with whatever is soldered on the board. Mirroring::Mapped
+    ///  covers any arrangement of the two CIRAM pages.
+    fn mirroring(&self) -> Option<Mirroring> { None }
+    /// A nametable read ($2000-$2FFF) the cart answers itself, rather than
+    ///  CIRAM: MMC5's ExRAM and fill mode. None leaves it to CIRAM.
+    fn nametable(&self, _address: u16) -> Option<u8> { None }
+    /// nametable(), as a real PPU fetch, for carts that count them.
+    fn fetch_nametable(&mut self, address: u16) -> Option<u8> { self.nametable(address) }
+    /// A nametable write. True if the cart took it, instead of CIRAM.
+    fn set_nametable(&mut self, _address: u16, _val: u8) -> bool { false }
+
+    //~~~Timing/IRQ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// Called once per CPU cycle, for mappers with timers or drives.
+    fn clock(&mut self) {}
+    /// True while the mapper is pulling /IRQ low.
+    fn irq(&self) -> bool { false }
+
+    //~~~Power~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// The console was switched on: registers go to their power-up state,
+    ///  and work RAM is filled from the power-on policy.
+    fn power(&mut self, _src: &mut PowerSource) {}
+    /// The reset button. Carts don't have a reset line, so most ignore it,
+    ///  but the ones that watch M2 stop can tell.
+    fn reset(&mut self) {}
+    /// Writes battery backed PRG-RAM out to the cart's .sav file.
+    /// Mappers without a cart (or a battery) have nothing to flush.
+    fn flush_sav(&self) -> io::Result<()> { Ok(()) }
+
+    //~~~Extras~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// Expansion audio from the cart, added to the APU's output. Scaled so
+    ///  one of the APU's pulses at full volume is ~0.15.
+    fn audio(&self) -> f32 { 0.0 }
+    /// The disk drive, on the FDS.
+    fn disk(&mut self) -> Option<&mut DiskDrive> { None }
 }
 
 /// Compatability goes up the ladder, I'm afraid.
 /// EMPTY_MAP really only exists for the sake of cpu_test.rs
 /// This is a lesson on why proper planning/studying/unit test tooling should
 ///  be done before implementation.
+/// Open bus everywhere, and deaf to every hook.
 pub struct EMPTY_MAP; 
 impl MAP for EMPTY_MAP{
-    fn get(&self, address: u16) -> u8{ 0 }
-    fn set(&mut self, address: u16, val: u8){ }
-    fn get_chr(&self, address: u16) -> u8{ 0 }
-    fn set_chr(&mut self, address: u16, val: u8){ }
+    fn peek(&self, _address: u16) -> u8{ 0 }
+    fn set(&mut self, _address: u16, _val: u8){ }
+    fn get_chr(&self, _address: u16) -> u8{ 0 }
+    fn set_chr(&mut self, _address: u16, _val: u8){ }
+}
+
+/// MAP::power for cart backed mappers: the cart goes into a fresh mapper,
+///  so registers come up the way new() leaves them, and work RAM is filled
+///  from the power-on policy.
+/// ie: *self = power_cycle(&mut self.cart, src, MMC3::new);
+pub fn power_cycle<M: MAP>(cart: &mut Box<CART>, src: &mut PowerSource,
+                           new: impl FnOnce(Box<CART>) -> M) -> M {
+    let mut cart = std::mem::take(cart);
+    cart.power_on_ram(src);
+    new(cart)
+}
+
+/// Banked PRG-ROM read, index wraps on the ROM size.
+/// A truncated image has no PRG at all and reads 0, validation has
+///  already complained about it.
+pub fn prg_read(cart: &CART, index: usize) -> u8 {
+    if cart.PRG.is_empty() { 0 } else { cart.PRG[index % cart.PRG.len()] }
+}
+
+/// Banked CHR read, same wrapping and empty case as prg_read.
+pub fn chr_read(cart: &CART, index: usize) -> u8 {
+    if cart.CHR.is_empty() { 0 } else { cart.CHR[index % cart.CHR.len()] }
+}
+
+/// Banked CHR write, only CHR-RAM is writable.
+pub fn chr_write(cart: &mut CART, index: usize, val: u8) {
+    if cart.section_sizes[2] == 0 && !cart.CHR.is_empty() {
+        let len = cart.CHR.len();
+        cart.CHR[index % len] = val;
+    }
 }
 
 /// Mapper #00, NROM
@@ -43,7 +146,7 @@ pub struct Nrom {
     pub cart: Box<CART>,
 }
 impl MAP for Nrom {
-    fn get(&self, address: u16) -> u8 {
+    fn peek(&self, address: u16) -> u8 {
         // -- Check flags 6, bit 1 if PRG RAM Exists
         // ---- If so, check size with flags 8.
         // ---- Load $6000-7FFF with RAM data.
@@ -52,72 +155,1429 @@ impl MAP for Nrom {
         // ---- If ROM size is 256, load $C000-$FFFF with last half of ROM
         // ---- If ROM size is 128, load $C000-$FFFF with ROM.
         //if self.cart.head[6] & 2 > 0 { //   if 
-        if address < 0x8000 {
-            0 //PRG RAM function -- DNE on this mapper.
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            //PRG RAM -- Only Family Basic really uses this on NROM.
+            self.cart.get_ram(address)
+        }
+        else {
+            //128 (16kb) carts mirror $8000-$BFFF into $C000-$FFFF, 256 (32kb)
+            // carts fill the whole thing. The modulo covers both.
+            prg_read(&self.cart, address as usize - 0x8000)
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        //8kb of CHR-ROM, or CHR-RAM on some homebrew.
+        chr_read(&self.cart, address as usize)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, |cart| Nrom{cart});
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+}
+
+/// Mapper #01, MMC1 (SxROM)
+/// Registers are loaded a bit at a time, LSB first, through a 5-bit shift
+///  register at $8000-$FFFF. The fifth write picks the register by address:
+///  $8000 control, $A000 CHR bank 0, $C000 CHR bank 1, $E000 PRG bank.
+/// The bigger boards have 8kb of CHR-RAM, so they reuse CHR bank 0's bits:
+///  SNROM  bit 4 disables PRG-RAM.
+///  SOROM  bit 3 selects the 8kb PRG-RAM bank (16kb total).
+///  SUROM  bit 4 selects the 256kb PRG-ROM half (512kb total).
+///  SXROM  both, with bits 2-3 selecting PRG-RAM (32kb total).
+/// USED: Zelda, Metroid, Mega Man 2, Final Fantasy, etc.
+/// http://wiki.nesdev.com/w/index.php/MMC1
+pub struct MMC1 {
+    pub cart: Box<CART>,
+    pub shift:      u8,         //Bits loaded so far.
+    pub count:      u8,
+    pub control:    u8,         //Mirroring, PRG mode, CHR mode.
+    pub chr_bank:   [u8; 2],
+    pub prg_bank:   u8,         //Bit 4 disables PRG-RAM.
+    cycle:          u64,
+    last_write:     Option<u64>,
+}
+impl MMC1 {
+    pub fn new(cart: Box<CART>) -> MMC1 {
+        MMC1 {
+            cart,
+            shift:      0,
+            count:      0,
+            control:    0x0C,   //Powers up with the last bank fixed.
+            chr_bank:   [0; 2],
+            prg_bank:   0,
+            cycle:      0,
+            last_write: None,
+        }
+    }
+
+    /// One write to $8000-$FFFF.
+    fn write_serial(&mut self, address: u16, val: u8) {
+        //Only the first of two writes on back to back cycles counts, which
+        // games rely on with INC/ASL's dummy write.
+        let consecutive = matches!(self.last_write, Some(last) if self.cycle - last < 2);
+        self.last_write = Some(self.cycle);
+        if consecutive {
+            return;
+        }
+
+        if val & 0x80 != 0 {
+            self.shift    = 0;
+            self.count    = 0;
+            self.control |= 0x0C;
+            return;
+        }
+        self.shift |= (val & 1) << self.count;
+        self.count += 1;
+        if self.count == 5 {
+            match address & 0xE000 {
+                0x8000 => self.control     = self.shift,
+                0xA000 => self.chr_bank[0] = self.shift,
+                0xC000 => self.chr_bank[1] = self.shift,
+                _      => self.prg_bank    = self.shift,
+            }
+            self.shift = 0;
+            self.count = 0;
+        }
+    }
+
+    /// SUROM/SXROM: which 256kb half of PRG-ROM, in 16kb banks.
+    fn prg_outer(&self) -> usize {
+        if self.cart.PRG.len() > 0x40000 { (self.chr_bank[0] & 0x10) as usize } else { 0 }
+    }
+    fn prg_index(&self, address: u16) -> usize {
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let upper = address >= 0xC000;
+        let slot = match (self.control >> 2) & 3 {
+            0 | 1 => (bank & !1) | upper as usize,  //32kb.
+            2 => if upper { bank } else { 0 },      //First bank fixed.
+            _ => if upper { 15 } else { bank },     //Last bank fixed.
+        };
+        (self.prg_outer() + slot) * 0x4000 + (address as usize & 0x3FFF)
+    }
+
+    /// SNROM wires CHR bank 0's bit 4 to a PRG-RAM disable, on the boards
+    ///  that don't need it for PRG-ROM.
+    fn ram_enabled(&self) -> bool {
+        let snrom = self.cart.section_sizes[2] == 0 && self.cart.PRG.len() <= 0x40000;
+        self.prg_bank & 0x10 == 0 && !(snrom && self.chr_bank[0] & 0x10 != 0)
+    }
+    /// SOROM/SXROM: 8kb PRG-RAM banks, selected by CHR bank 0.
+    fn ram_index(&self, address: u16) -> usize {
+        let bank = match self.cart.PRG_RAM.len() {
+            0x8000 => (self.chr_bank[0] >> 2) & 3,
+            0x4000 => (self.chr_bank[0] >> 3) & 1,
+            _ => 0,
+        } as usize;
+        (bank * 0x2000 + (address as usize - 0x6000)) % self.cart.PRG_RAM.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let address = address as usize;
+        if self.control & 0x10 == 0 {
+            (self.chr_bank[0] & 0x1E) as usize * 0x1000 + address       //8kb.
+        }
+        else {
+            self.chr_bank[address >> 12] as usize * 0x1000 + (address & 0xFFF)
+        }
+    }
+}
+impl MAP for MMC1 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if self.ram_enabled() && !self.cart.PRG_RAM.is_empty() {
+                self.cart.PRG_RAM[self.ram_index(address)]
+            }
+            else {
+                0 //Open bus, for now.
+            }
+        }
+        else {
+            prg_read(&self.cart, self.prg_index(address))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            if self.ram_enabled() && !self.cart.PRG_RAM.is_empty() {
+                let index = self.ram_index(address);
+                self.cart.PRG_RAM[index] = val;
+            }
+        }
+        else if address >= 0x8000 {
+            self.write_serial(address, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, self.chr_index(address))
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn clock(&mut self) {
+        self.cycle += 1;
+    }
+    /// Only the control register's PRG mode is defined at power-up, the
+    ///  rest start cleared like MMC1::new leaves them.
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, MMC1::new);
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 3 {
+            0 => Mirroring::SingleLow,
+            1 => Mirroring::SingleHigh,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+}
+
+/// The discrete boards latch whatever is on the data bus. NES 2.0
+///  submapper 2 says the ROM drives it too, so the two get ANDed together.
+/// http://wiki.nesdev.com/w/index.php/Bus_conflict
+/// rom is whatever the CPU sees at the address being written.
+fn bus_conflict(cart: &CART, rom: u8, val: u8) -> u8 {
+    if cart.submapper == 2 { val & rom } else { val }
+}
+
+/// Mapper #02, UxROM (UNROM, UOROM)
+/// A switchable 16kb bank at $8000, the last bank fixed at $C000, and
+///  CHR-RAM. Any write to $8000-$FFFF selects the bank.
+/// USED: Mega Man, Castlevania, Contra, Duck Tales, etc.
+/// http://wiki.nesdev.com/w/index.php/UxROM
+pub struct Uxrom {
+    pub cart: Box<CART>,
+    pub bank: u8,
+}
+impl MAP for Uxrom {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
+        }
+        else {
+            let banks = self.cart.PRG.len().div_ceil(0x4000).max(1);
+            let bank = if address < 0xC000 { self.bank as usize % banks } else { banks - 1 };
+            prg_read(&self.cart, bank * 0x4000 + (address as usize & 0x3FFF))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+        }
+        else if address >= 0x8000 {
+            let rom = self.peek(address);
+            self.bank = bus_conflict(&self.cart, rom, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, address as usize)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, |cart| Uxrom{cart, bank: 0});
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+}
+
+/// Mapper #03, CNROM
+/// NROM's PRG layout, with the 8kb of CHR-ROM switched by any write to
+///  $8000-$FFFF.
+/// USED: Solomon's Key, Gradius, Arkanoid, Adventure Island, etc.
+/// http://wiki.nesdev.com/w/index.php/CNROM
+pub struct Cnrom {
+    pub cart: Box<CART>,
+    pub bank: u8,
+}
+impl MAP for Cnrom {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
+        }
+        else {
+            //16kb carts mirror into $C000, like NROM.
+            prg_read(&self.cart, address as usize - 0x8000)
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+        }
+        else if address >= 0x8000 {
+            let rom = self.peek(address);
+            self.bank = bus_conflict(&self.cart, rom, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        let index = self.bank as usize * 0x2000 + (address as usize & 0x1FFF);
+        chr_read(&self.cart, index)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        //A few homebrew boards swap in CHR-RAM, unbanked.
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, |cart| Cnrom{cart, bank: 0});
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+}
+
+/// Mapper #07, AxROM (ANROM, AMROM, AOROM)
+/// 32kb PRG banks, picked by bits 0-2 of any write to $8000-$FFFF, with
+///  bit 4 choosing which 1kb of CIRAM all four nametables show. CHR-RAM.
+/// USED: Battletoads, Marble Madness, Wizards & Warriors, Rare games.
+/// http://wiki.nesdev.com/w/index.php/AxROM
+pub struct Axrom {
+    pub cart: Box<CART>,
+    pub bank: u8,
+}
+impl MAP for Axrom {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
+        }
+        else {
+            let index = (self.bank & 0x07) as usize * 0x8000 + (address as usize - 0x8000);
+            prg_read(&self.cart, index)
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+        }
+        else if address >= 0x8000 {
+            let rom = self.peek(address);
+            self.bank = bus_conflict(&self.cart, rom, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, address as usize)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, |cart| Axrom{cart, bank: 0});
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.bank & 0x10 == 0 { Mirroring::SingleLow } else { Mirroring::SingleHigh })
+    }
+}
+
+/// Mapper #11, Color Dreams
+/// Like GxROM with the bits swapped and more of them: any write to
+///  $8000-$FFFF picks a 32kb PRG bank with bits 0-1 and an 8kb CHR bank
+///  with bits 4-7. The board has no lockout chip.
+/// USED: Crystal Mines, Bible Adventures, Menace Beach.
+/// http://wiki.nesdev.com/w/index.php/Color_Dreams
+pub struct ColorDreams {
+    pub cart: Box<CART>,
+    pub bank: u8,
+}
+impl MAP for ColorDreams {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
+        }
+        else {
+            let index = (self.bank & 0x03) as usize * 0x8000 + (address as usize - 0x8000);
+            prg_read(&self.cart, index)
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+        }
+        else if address >= 0x8000 {
+            let rom = self.peek(address);
+            self.bank = bus_conflict(&self.cart, rom, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        let index = (self.bank >> 4) as usize * 0x2000 + (address as usize & 0x1FFF);
+        chr_read(&self.cart, index)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, |cart| ColorDreams{cart, bank: 0});
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+}
+
+/// Mapper #34, BNROM and NINA-001
+/// Two unrelated boards that ended up under one number:
+///  BNROM (submapper 2): any write to $8000-$FFFF picks a 32kb PRG bank,
+///   with 8kb of CHR-RAM.
+///  NINA-001 (submapper 1): $7FFD picks the 32kb PRG bank, $7FFE and $7FFF
+///   4kb CHR-ROM banks at $0000 and $1000. The registers sit on top of
+///   8kb of PRG-RAM, which takes the writes too.
+/// Without a submapper, more than 8kb of CHR-ROM means NINA-001.
+/// USED: Deadly Towers (BNROM), Impossible Mission II (NINA-001).
+/// http://wiki.nesdev.com/w/index.php/INES_Mapper_034
+pub struct Bnrom {
+    pub cart: Box<CART>,
+    pub nina001:    bool,
+    pub bank:       u8,
+    pub chr_bank:   [u8; 2],
+}
+impl Bnrom {
+    pub fn new(cart: Box<CART>) -> Bnrom {
+        let nina001 = match cart.submapper {
+            1 => true,
+            2 => false,
+            _ => cart.section_sizes[2] > 0x2000,
+        };
+        Bnrom { cart, nina001, bank: 0, chr_bank: [0, 1] }
+    }
+}
+impl MAP for Bnrom {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
         }
-        else if self.cart.PRG.len() > 16384 {
-            self.cart.PRG[
-                if address > 0xBFFF {
-                    address & 0x3FFF
+        else {
+            let index = self.bank as usize * 0x8000 + (address as usize - 0x8000);
+            prg_read(&self.cart, index)
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+            if self.nina001 {
+                match address {
+                    0x7FFD => self.bank = val & 1,
+                    0x7FFE => self.chr_bank[0] = val & 0x0F,
+                    0x7FFF => self.chr_bank[1] = val & 0x0F,
+                    _ => {},
                 }
-                else {
-                    address & 0x7FFF
+            }
+        }
+        else if address >= 0x8000 && !self.nina001 {
+            let rom = self.peek(address);
+            self.bank = bus_conflict(&self.cart, rom, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        if !self.nina001 {
+            return chr_read(&self.cart, address as usize);
+        }
+        let bank = self.chr_bank[(address >> 12) as usize & 1] as usize;
+        chr_read(&self.cart, bank * 0x1000 + (address as usize & 0xFFF))
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        if !self.nina001 {
+            chr_write(&mut self.cart, address as usize, val);
+        }
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Bnrom::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+}
+
+/// Mapper #66, GxROM (GNROM, MHROM)
+/// Any write to $8000-$FFFF picks a 32kb PRG bank with bits 4-5, and an
+///  8kb CHR bank with bits 0-1.
+/// USED: Super Mario Bros. + Duck Hunt, Dragon Power, Gumshoe.
+/// http://wiki.nesdev.com/w/index.php/GxROM
+pub struct Gxrom {
+    pub cart: Box<CART>,
+    pub bank: u8,
+}
+impl MAP for Gxrom {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
+        }
+        else {
+            let index = ((self.bank >> 4) & 0x03) as usize * 0x8000 + (address as usize - 0x8000);
+            prg_read(&self.cart, index)
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if (0x6000..0x8000).contains(&address) {
+            self.cart.set_ram(address, val);
+        }
+        else if address >= 0x8000 {
+            let rom = self.peek(address);
+            self.bank = bus_conflict(&self.cart, rom, val);
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        let index = (self.bank & 0x03) as usize * 0x2000 + (address as usize & 0x1FFF);
+        chr_read(&self.cart, index)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, |cart| Gxrom{cart, bank: 0});
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+}
+
+/// Mapper #71, Camerica/Codemasters (BF909x)
+/// UxROM's layout, with the bank register at $C000-$FFFF only. No bus
+///  conflicts, and CHR-RAM.
+/// Fire Hawk's board (submapper 1) also has single-screen mirroring at
+///  $9000-$9FFF, bit 4. It's the only game that writes there, so without
+///  a submapper the first such write turns it on.
+/// USED: Micro Machines, Fire Hawk, Dizzy games, Bee 52.
+/// http://wiki.nesdev.com/w/index.php/INES_Mapper_071
+pub struct Camerica {
+    pub cart: Box<CART>,
+    pub bank:       u8,
+    pub fire_hawk:  bool,
+    pub single:     Option<Mirroring>,
+}
+impl Camerica {
+    pub fn new(cart: Box<CART>) -> Camerica {
+        Camerica { fire_hawk: cart.submapper == 1, cart, bank: 0, single: None }
+    }
+}
+impl MAP for Camerica {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            self.cart.get_ram(address)
+        }
+        else {
+            let banks = self.cart.PRG.len().div_ceil(0x4000).max(1);
+            let bank = if address < 0xC000 { self.bank as usize % banks } else { banks - 1 };
+            prg_read(&self.cart, bank * 0x4000 + (address as usize & 0x3FFF))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        match address {
+            0x6000..=0x7FFF => self.cart.set_ram(address, val),
+            0x9000..=0x9FFF if self.fire_hawk || self.cart.submapper == 0 => {
+                self.fire_hawk = true;
+                self.single = Some(if val & 0x10 == 0 { Mirroring::SingleLow } else { Mirroring::SingleHigh });
+            },
+            0xC000..=0xFFFF => self.bank = val & 0x0F,
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, address as usize)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        chr_write(&mut self.cart, address as usize, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Camerica::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        self.single
+    }
+}
+
+/// The MMC3 revisions that games can tell apart, from the NES 2.0 submapper.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mmc3Revision {
+    /// MMC3B/MMC3C (Sharp): IRQs whenever the counter is 0 after a clock,
+    ///  so a latch of 0 fires every scanline.
+    Sharp,
+    /// MMC3A (NEC), submapper 4: IRQs only when the counter gets to 0 by
+    ///  counting down, or by a reload that $C001 asked for.
+    Nec,
+    /// MMC6 (HKROM), submapper 1: Sharp IRQs, but 1kb of PRG-RAM at $7000
+    ///  with read/write enables for each 512 byte half.
+    Mmc6,
+}
+
+/// Mapper #04, MMC3 (TxROM) and MMC6 (HKROM)
+/// Eight bank registers, selected through $8000 and loaded through $8001:
+///  R0-R1 2kb CHR, R2-R5 1kb CHR, R6-R7 8kb PRG. $8000 bit 6 swaps which
+///  of $8000/$C000 is R6 (the other being the second to last bank), bit 7
+///  swaps the CHR halves.
+/// $A000 mirroring, $A001 PRG-RAM protect, $C000/$C001 IRQ latch/reload,
+///  $E000/$E001 IRQ disable/enable. Registers repeat every even/odd address.
+/// The IRQ counter is clocked by A12 rising on the PPU bus, after it has
+///  been low for a few CPU cycles; normally once per scanline.
+/// USED: Super Mario Bros. 3, Kirby's Adventure, Mega Man 3-6, etc.
+/// http://wiki.nesdev.com/w/index.php/MMC3
+pub struct MMC3 {
+    pub cart: Box<CART>,
+    pub revision:       Mmc3Revision,
+    pub select:         u8,         //$8000
+    pub regs:           [u8; 8],    //R0-R7
+    pub horizontal:     bool,       //$A000
+    pub ram_protect:    u8,         //$A001
+    pub irq_latch:      u8,
+    pub irq_counter:    u8,
+    pub irq_reload:     bool,
+    pub irq_enabled:    bool,
+    pub irq_pending:    bool,
+    a12:                bool,
+    a12_fell:           u64,        //CPU cycle A12 last went low.
+    cycle:              u64,
+}
+impl MMC3 {
+    pub fn new(cart: Box<CART>) -> MMC3 {
+        let revision = match cart.submapper {
+            1 => Mmc3Revision::Mmc6,
+            4 => Mmc3Revision::Nec,
+            _ => Mmc3Revision::Sharp,
+        };
+        MMC3 {
+            cart,
+            revision,
+            select:         0,
+            regs:           [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal:     false,
+            ram_protect:    0x80,   //Enabled and writable, like most boards boot.
+            irq_latch:      0,
+            irq_counter:    0,
+            irq_reload:     false,
+            irq_enabled:    false,
+            irq_pending:    false,
+            a12:            false,
+            a12_fell:       0,
+            cycle:          0,
+        }
+    }
+
+    fn prg_index(&self, address: u16) -> usize {
+        let banks = self.cart.PRG.len().div_ceil(0x2000).max(1);
+        let second_last = banks.saturating_sub(2);
+        let swapped = self.select & 0x40 != 0;
+        let bank = match (address >> 13) & 3 {
+            0 => if swapped { second_last } else { self.regs[6] as usize },
+            1 => self.regs[7] as usize,
+            2 => if swapped { self.regs[6] as usize } else { second_last },
+            _ => banks - 1,
+        };
+        (bank % banks) * 0x2000 + (address as usize & 0x1FFF)
+    }
+    fn chr_index(&self, address: u16) -> usize {
+        let address = if self.select & 0x80 != 0 { address ^ 0x1000 } else { address };
+        let bank = match (address >> 10) & 7 {
+            0 => self.regs[0] & 0xFE,
+            1 => self.regs[0] | 1,
+            2 => self.regs[1] & 0xFE,
+            3 => self.regs[1] | 1,
+            slot => self.regs[slot as usize - 2],
+        };
+        bank as usize * 0x400 + (address as usize & 0x3FF)
+    }
+
+    /// MMC6: 1kb at $7000-$7FFF, only while $8000 bit 5 is set, and then
+    ///  per half by $A001. None is open bus.
+    fn mmc6_ram(&self, address: u16, write: bool) -> Option<usize> {
+        if address < 0x7000 || self.select & 0x20 == 0 || self.cart.PRG_RAM.is_empty() {
+            return None;
+        }
+        let high = address & 0x200 != 0;
+        let bit = match (high, write) {
+            (false, false) => 0x20,
+            (false, true)  => 0x10,
+            (true, false)  => 0x80,
+            (true, true)   => 0x40,
+        };
+        //Writes also need the half to be readable.
+        let readable = if high { 0x80 } else { 0x20 };
+        if self.ram_protect & bit == 0 || self.ram_protect & readable == 0 {
+            return None;
+        }
+        Some((address as usize & 0x3FF) % self.cart.PRG_RAM.len())
+    }
+
+    /// One scanline, as far as the MMC3 can tell.
+    fn clock_counter(&mut self) {
+        let before = self.irq_counter;
+        let reload = self.irq_reload;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        }
+        else {
+            self.irq_counter -= 1;
+        }
+        self.irq_reload = false;
+
+        let fire = match self.revision {
+            Mmc3Revision::Nec => self.irq_counter == 0 && (before > 0 || reload),
+            _ => self.irq_counter == 0,
+        };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+impl MAP for MMC3 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if self.revision == Mmc3Revision::Mmc6 {
+                //A readable half next to an unreadable one reads 0.
+                match self.mmc6_ram(address, false) {
+                    Some(index) => self.cart.PRG_RAM[index],
+                    None => 0,
                 }
-            as usize]
+            }
+            else if self.ram_protect & 0x80 != 0 {
+                self.cart.get_ram(address)
+            }
+            else {
+                0 //Open bus, for now.
+            }
         }
         else {
-            self.cart.PRG[(address & 0x7FFF) as usize] //This should start it at 0.
+            prg_read(&self.cart, self.prg_index(address))
         }
     }
     fn set(&mut self, address: u16, val: u8) {
-        
+        match address {
+            0x6000..=0x7FFF => {
+                if self.revision == Mmc3Revision::Mmc6 {
+                    if let Some(index) = self.mmc6_ram(address, true) {
+                        self.cart.PRG_RAM[index] = val;
+                    }
+                }
+                else if self.ram_protect & 0xC0 == 0x80 {
+                    self.cart.set_ram(address, val);
+                }
+            },
+            0x8000..=0xFFFF => match address & 0xE001 {
+                0x8000 => self.select = val,
+                0x8001 => self.regs[(self.select & 7) as usize] = val,
+                0xA000 => self.horizontal = val & 1 != 0,
+                0xA001 => self.ram_protect = val,
+                0xC000 => self.irq_latch = val,
+                0xC001 => {
+                    self.irq_counter = 0;
+                    self.irq_reload  = true;
+                },
+                0xE000 => {
+                    self.irq_enabled = false;
+                    self.irq_pending = false;
+                },
+                _ => self.irq_enabled = true,
+            },
+            _ => {},
+        }
     }
     fn get_chr(&self, address: u16) -> u8 {
-        0
+        chr_read(&self.cart, self.chr_index(address))
     }
     fn set_chr(&mut self, address: u16, val: u8){
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, MMC3::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn clock(&mut self) {
+        self.cycle += 1;
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        //Four-screen boards (Rad Racer 2, Gauntlet) wire around it.
+        if self.cart.mirroring == Mirroring::FourScreen {
+            None
+        }
+        else if self.horizontal {
+            Some(Mirroring::Horizontal)
+        }
+        else {
+            Some(Mirroring::Vertical)
+        }
+    }
+    fn ppu_bus(&mut self, address: u16) {
+        let a12 = address & 0x1000 != 0;
+        //The counter ignores A12 blips shorter than ~3 CPU cycles, like
+        // the ones between background and sprite fetches in 8x16 mode.
+        if a12 && !self.a12 && self.cycle - self.a12_fell >= 3 {
+            self.clock_counter();
+        }
+        if !a12 && self.a12 {
+            self.a12_fell = self.cycle;
+        }
+        self.a12 = a12;
+    }
+}
 
+/// Mapper #09, MMC2 (PxROM) and #10, MMC4 (FxROM)
+/// Each 4kb half of CHR has two banks, one for tile $FD and one for $FE,
+///  and a latch that flips between them when the PPU fetches either tile.
+///  The fetch that flips a latch still comes from the old bank.
+/// $A000 PRG bank, $B000-$E000 the CHR banks ($FD/$0000, $FE/$0000,
+///  $FD/$1000, $FE/$1000), $F000 mirroring.
+/// MMC2: 8kb PRG at $8000, the last three banks fixed. Only exactly
+///  $0FD8/$0FE8 flip the low latch.
+/// MMC4: 16kb PRG at $8000, the last bank fixed, 8kb PRG-RAM. Both latches
+///  flip on the whole tile row ($xFD8-$xFDF/$xFE8-$xFEF).
+/// USED: Punch-Out!! (MMC2), Fire Emblem, Famicom Wars (MMC4).
+/// http://wiki.nesdev.com/w/index.php/MMC2
+/// http://wiki.nesdev.com/w/index.php/MMC4
+pub struct MMC2 {
+    pub cart: Box<CART>,
+    pub mmc4:       bool,
+    pub prg_bank:   u8,
+    pub chr_bank:   [[u8; 2]; 2],   //[half][$FD, $FE]
+    pub latch:      [u8; 2],        //$FD or $FE, per half.
+    pub horizontal: bool,
+}
+impl MMC2 {
+    pub fn new(cart: Box<CART>, mmc4: bool) -> MMC2 {
+        MMC2 {
+            cart,
+            mmc4,
+            prg_bank:   0,
+            chr_bank:   [[0; 2]; 2],
+            latch:      [0xFE; 2],
+            horizontal: false,
+        }
+    }
+
+    fn prg_index(&self, address: u16) -> usize {
+        let len = self.cart.PRG.len();
+        let offset = address as usize - 0x8000;
+        if self.mmc4 {
+            if address < 0xC000 { self.prg_bank as usize * 0x4000 + offset }
+            else { len.saturating_sub(0x4000) + (offset & 0x3FFF) }
+        }
+        else if address < 0xA000 {
+            self.prg_bank as usize * 0x2000 + offset
+        }
+        else {
+            //$A000-$FFFF is the last 24kb.
+            len.saturating_sub(0x6000) + (offset - 0x2000)
+        }
+    }
+    fn chr_index(&self, address: u16) -> usize {
+        let half = (address >> 12) as usize & 1;
+        let bank = self.chr_bank[half][(self.latch[half] - 0xFD) as usize];
+        bank as usize * 0x1000 + (address as usize & 0xFFF)
+    }
+}
+impl MAP for MMC2 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if self.mmc4 { self.cart.get_ram(address) } else { 0 }
+        }
+        else {
+            prg_read(&self.cart, self.prg_index(address))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        match address {
+            0x6000..=0x7FFF if self.mmc4 => self.cart.set_ram(address, val),
+            0xA000..=0xAFFF => self.prg_bank = val & 0x0F,
+            0xB000..=0xBFFF => self.chr_bank[0][0] = val & 0x1F,
+            0xC000..=0xCFFF => self.chr_bank[0][1] = val & 0x1F,
+            0xD000..=0xDFFF => self.chr_bank[1][0] = val & 0x1F,
+            0xE000..=0xEFFF => self.chr_bank[1][1] = val & 0x1F,
+            0xF000..=0xFFFF => self.horizontal = val & 1 != 0,
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, self.chr_index(address))
+    }
+    fn set_chr(&mut self, _address: u16, _val: u8){
+        //CHR-ROM only.
+    }
+    fn fetch_chr(&mut self, address: u16) -> u8 {
+        let val = self.get_chr(address);
+        let half = (address >> 12) as usize & 1;
+        //MMC2's low latch wants the exact address, not the whole row.
+        if !self.mmc4 && half == 0 && address & 7 != 0 {
+            return val;
+        }
+        match address & 0x0FF8 {
+            0x0FD8 => self.latch[half] = 0xFD,
+            0x0FE8 => self.latch[half] = 0xFE,
+            _ => {},
+        }
+        val
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        let mmc4 = self.mmc4;
+        *self = power_cycle(&mut self.cart, src, |cart| MMC2::new(cart, mmc4));
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.horizontal { Mirroring::Horizontal } else { Mirroring::Vertical })
     }
 }
 
-pub struct MMC1 {
+/// The IRQ counter shared by Konami's VRC4, VRC6 and VRC7.
+/// An 8-bit counter counting up from the latch, with an IRQ and a reload
+///  when it overflows. In cycle mode it counts every CPU cycle, in
+///  scanline mode a prescaler counts it every 341/3 CPU cycles.
+/// Control (bit 0 enable after acknowledge, bit 1 enable, bit 2 cycle
+///  mode) reloads the counter when enabling. Acknowledging copies bit 0
+///  back into the enable.
+/// http://wiki.nesdev.com/w/index.php/VRC_IRQ
+#[derive(Clone, Copy, Default)]
+pub struct VrcIrq {
+    pub latch:          u8,
+    pub counter:        u8,
+    pub prescaler:      i16,
+    pub enabled:        bool,
+    pub enable_on_ack:  bool,
+    pub cycle_mode:     bool,
+    pub pending:        bool,
+}
+impl VrcIrq {
+    pub fn control(&mut self, val: u8) {
+        self.enable_on_ack = val & 1 != 0;
+        self.enabled       = val & 2 != 0;
+        self.cycle_mode    = val & 4 != 0;
+        self.pending       = false;
+        if self.enabled {
+            self.counter   = self.latch;
+            self.prescaler = 341;
+        }
+    }
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_on_ack;
+    }
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += 341;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        }
+        else {
+            self.counter += 1;
+        }
+    }
+}
+
+/// Mappers #21, #22, #23, #25, Konami VRC2 and VRC4
+/// The same chip on every board, but each wires its two register select
+///  pins to different CPU address lines. NES 2.0 submappers say which;
+///  without one, both candidates are ORed together, since no game writes
+///  to an address that would tell the two apart:
+///  #21 VRC4a A1/A2 (1), VRC4c A6/A7 (2).
+///  #22 VRC2a A1/A0, with CHR banks in 2kb units (the 1kb line is unused).
+///  #23 VRC4f A0/A1 (1), VRC4e A2/A3 (2), VRC2b A0/A1 (3).
+///  #25 VRC4b A1/A0 (1), VRC4d A3/A2 (2), VRC2c A1/A0 (3).
+/// $8000 PRG bank 0, $A000 PRG bank 1, the last two 8kb banks fixed.
+/// $9000 mirroring, $9002 bit 1 (VRC4) swaps $8000 with the fixed $C000.
+/// $B000-$E003 eight 1kb CHR banks, written a nibble at a time.
+/// $F000-$F003 (VRC4) IRQ latch low/high, control and acknowledge.
+/// VRC2 has no IRQ, and boards without PRG-RAM have a 1 bit latch there
+///  that a few games check for.
+/// USED: Gradius II, Tiny Toon Adventures (VRC4), Contra (J) (VRC2).
+/// http://wiki.nesdev.com/w/index.php/VRC2_and_VRC4
+pub struct Vrc4 {
     pub cart: Box<CART>,
+    pub vrc2:       bool,
+    pub pins:       [u16; 2],   //Address lines of the low/high select pins.
+    pub prg_bank:   [u8; 2],
+    pub prg_swap:   bool,
+    pub chr_bank:   [u16; 8],
+    pub mirroring:  u8,
+    pub irq:        VrcIrq,
+    pub latch:      u8,         //VRC2's $6000-$7FFF bit.
 }
-impl MAP for MMC1 {
-    fn get(&self, address: u16) -> u8 {
-        // -- Check flags 6, bit 1 if PRG RAM Exists
-        // ---- If so, check size with flags 8.
-        // ---- Load $6000-7FFF with RAM data.
-        // -- Check flags 4, for PRG ROM size (in 16 kb units)
-        // ---- Load $8000-$BFFF with ROM.
-        // ---- If ROM size is 256, load $C000-$FFFF with last half of ROM
-        // ---- If ROM size is 128, load $C000-$FFFF with ROM.
-        //if self.cart.head[6] & 2 > 0 { //   if 
-//        bfc tech
+impl Vrc4 {
+    pub fn new(cart: Box<CART>) -> Vrc4 {
+        let (pins, vrc2) = match (cart.mapper, cart.submapper) {
+            (21, 1) => ([0x02, 0x04], false),
+            (21, 2) => ([0x40, 0x80], false),
+            (21, _) => ([0x42, 0x84], false),
+            (22, _) => ([0x02, 0x01], true),
+            (23, 1) => ([0x01, 0x02], false),
+            (23, 2) => ([0x04, 0x08], false),
+            (23, 3) => ([0x01, 0x02], true),
+            (23, _) => ([0x05, 0x0A], false),
+            (25, 1) => ([0x02, 0x01], false),
+            (25, 2) => ([0x08, 0x04], false),
+            (25, 3) => ([0x02, 0x01], true),
+            _       => ([0x0A, 0x05], false),
+        };
+        Vrc4 {
+            cart,
+            vrc2,
+            pins,
+            prg_bank:   [0; 2],
+            prg_swap:   false,
+            chr_bank:   [0; 8],
+            mirroring:  0,
+            irq:        VrcIrq::default(),
+            latch:      0,
+        }
+    }
+
+    /// The register an address selects, as $x000-$x003.
+    fn register(&self, address: u16) -> u16 {
+        let low  = (address & self.pins[0] != 0) as u16;
+        let high = (address & self.pins[1] != 0) as u16;
+        (address & 0xF000) | (high << 1) | low
+    }
+    fn prg_index(&self, address: u16) -> usize {
+        let banks = self.cart.PRG.len().div_ceil(0x2000).max(1);
+        let second_last = banks.saturating_sub(2);
+        let bank = match (address >> 13) & 3 {
+            0 => if self.prg_swap { second_last } else { self.prg_bank[0] as usize },
+            1 => self.prg_bank[1] as usize,
+            2 => if self.prg_swap { self.prg_bank[0] as usize } else { second_last },
+            _ => banks - 1,
+        };
+        (bank % banks) * 0x2000 + (address as usize & 0x1FFF)
+    }
+    fn chr_index(&self, address: u16) -> usize {
+        let mut bank = self.chr_bank[(address >> 10) as usize & 7] as usize;
+        if self.cart.mapper == 22 {
+            bank >>= 1;
+        }
+        bank * 0x400 + (address as usize & 0x3FF)
+    }
+}
+impl MAP for Vrc4 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if !self.cart.PRG_RAM.is_empty() { self.cart.get_ram(address) }
+            else if self.vrc2 { self.latch }
+            else { 0 }
+        }
+        else {
+            prg_read(&self.cart, self.prg_index(address))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if address < 0x6000 {
+            return;
+        }
         if address < 0x8000 {
-            0 //PRG RAM function.
+            if !self.cart.PRG_RAM.is_empty() { self.cart.set_ram(address, val) }
+            else if self.vrc2 { self.latch = val & 1 }
+            return;
         }
-        else if self.cart.PRG.len() > 16384 {
-            self.cart.PRG[
-                if address > 0xBFFF {
-                    address & 0x3FFF
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_bank[0] = val & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = val & 1,
+            0x9000 | 0x9001 => self.mirroring = val & 3,
+            0x9002 | 0x9003 => self.prg_swap = val & 2 != 0,
+            0xA000..=0xA003 => self.prg_bank[1] = val & 0x1F,
+            0xB000..=0xEFFF => {
+                let slot = (((register >> 12) - 0xB) * 2 + ((register >> 1) & 1)) as usize;
+                let bank = &mut self.chr_bank[slot];
+                if register & 1 == 0 {
+                    *bank = (*bank & 0x1F0) | (val & 0x0F) as u16;
                 }
                 else {
-                    address & 0x7FFF
+                    let high = if self.vrc2 { val & 0x0F } else { val & 0x1F };
+                    *bank = (*bank & 0x0F) | (high as u16) << 4;
                 }
-            as usize]
+            },
+            _ if self.vrc2 => {},
+            0xF000 => self.irq.latch = (self.irq.latch & 0xF0) | (val & 0x0F),
+            0xF001 => self.irq.latch = (self.irq.latch & 0x0F) | (val << 4),
+            0xF002 => self.irq.control(val),
+            0xF003 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, self.chr_index(address))
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Vrc4::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleLow,
+            _ => Mirroring::SingleHigh,
+        })
+    }
+}
+
+/// Sunsoft 5B audio: the FME-7 with a YM2149F (AY-3-8910) PSG inside.
+/// Three square channels, a noise LFSR and an envelope, all counting off
+///  the CPU clock / 16. Volumes go through a logarithmic DAC, 3dB a
+///  step, with the envelope filling in the half steps.
+/// $C000 register select, $E000 data.
+///  $00-$05 tone periods A/B/C (12 bit), $06 noise period, $07 mixer
+///  (tone then noise disables), $08-$0A volumes (bit 4 uses the envelope),
+///  $0B-$0C envelope period, $0D envelope shape.
+/// http://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+#[derive(Clone)]
+pub struct Sunsoft5b {
+    pub address:        u8,
+    pub regs:           [u8; 16],
+    pub tone:           [bool; 3],
+    tone_counter:       [u16; 3],
+    pub noise:          u32,        //17 bit LFSR, bit 0 out.
+    noise_counter:      u8,
+    noise_phase:        bool,
+    pub envelope:       u8,         //0-31.
+    envelope_counter:   u32,
+    envelope_attack:    bool,
+    envelope_holding:   bool,
+    divider:            u8,
+}
+impl Default for Sunsoft5b {
+    fn default() -> Sunsoft5b { Sunsoft5b::new() }
+}
+impl Sunsoft5b {
+    pub fn new() -> Sunsoft5b {
+        Sunsoft5b {
+            address:            0,
+            regs:               [0; 16],
+            tone:               [false; 3],
+            tone_counter:       [0; 3],
+            noise:              1,
+            noise_counter:      0,
+            noise_phase:        false,
+            envelope:           0,
+            envelope_counter:   0,
+            envelope_attack:    false,
+            envelope_holding:   false,
+            divider:            0,
+        }
+    }
+
+    /// $C000-$FFFF, select or data.
+    pub fn write(&mut self, address: u16, val: u8) {
+        if address < 0xE000 {
+            self.address = val;
+            return;
+        }
+        if self.address > 0x0F {
+            return;
+        }
+        self.regs[self.address as usize] = val;
+        //A new shape restarts the envelope.
+        if self.address == 0x0D {
+            self.envelope_attack  = val & 0x04 != 0;
+            self.envelope_holding = false;
+            self.envelope         = if self.envelope_attack { 0 } else { 31 };
+            self.envelope_counter = 0;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        (self.regs[channel * 2] as u16 | ((self.regs[channel * 2 + 1] & 0x0F) as u16) << 8).max(1)
+    }
+
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        let last = if self.envelope_attack { 31 } else { 0 };
+        if self.envelope != last {
+            if self.envelope_attack { self.envelope += 1 } else { self.envelope -= 1 }
+            return;
+        }
+        //End of a ramp: continue, alternate, hold.
+        let shape = self.regs[0x0D];
+        if shape & 0x08 == 0 {
+            self.envelope         = 0;
+            self.envelope_holding = true;
+        }
+        else if shape & 0x01 != 0 {
+            if shape & 0x02 != 0 {
+                self.envelope = 31 - self.envelope;
+            }
+            self.envelope_holding = true;
+        }
+        else if shape & 0x02 != 0 {
+            self.envelope_attack = !self.envelope_attack;
         }
         else {
-            self.cart.PRG[(address & 0x7FFF) as usize] //This should start it at 0.
+            self.envelope = 31 - last;
+        }
+    }
+
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        self.divider = (self.divider + 1) & 15;
+        if self.divider != 0 {
+            return;
+        }
+        for channel in 0..3 {
+            self.tone_counter[channel] += 1;
+            if self.tone_counter[channel] >= self.tone_period(channel) {
+                self.tone_counter[channel] = 0;
+                self.tone[channel] = !self.tone[channel];
+            }
+        }
+        //The LFSR shifts every other noise period.
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.regs[6] & 0x1F).max(1) {
+            self.noise_counter = 0;
+            self.noise_phase = !self.noise_phase;
+            if self.noise_phase {
+                let bit = (self.noise ^ (self.noise >> 3)) & 1;
+                self.noise = (self.noise >> 1) | (bit << 16);
+            }
+        }
+        self.envelope_counter += 1;
+        let period = (self.regs[0x0B] as u32 | (self.regs[0x0C] as u32) << 8).max(1);
+        if self.envelope_counter >= period {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    /// The level of one channel, 0-31 on the DAC's scale.
+    pub fn level(&self, channel: usize) -> u8 {
+        let mixer = self.regs[7];
+        let tone  = self.tone[channel] || mixer & (1 << channel) != 0;
+        let noise = self.noise & 1 != 0 || mixer & (8 << channel) != 0;
+        if !(tone && noise) {
+            return 0;
+        }
+        let volume = self.regs[8 + channel];
+        if volume & 0x10 != 0 {
+            self.envelope
+        }
+        else if volume & 0x0F == 0 {
+            0
+        }
+        else {
+            (volume & 0x0F) * 2 + 1
+        }
+    }
+
+    /// Each channel at full volume is about as loud as one of the APU's
+    ///  pulses, 1.5dB down per level.
+    pub fn output(&self) -> f32 {
+        (0..3).map(|channel| match self.level(channel) {
+            0 => 0.0,
+            level => 0.15 * 10f32.powf((level as f32 - 31.0) * 1.5 / 20.0),
+        }).sum()
+    }
+}
+
+/// Mapper #69, Sunsoft FME-7 (and 5B)
+/// $8000 selects a command, $A000 gives it its parameter:
+///  $0-$7 1kb CHR banks.
+///  $8 $6000 bank: bits 0-5 the bank, bit 6 RAM instead of ROM, bit 7
+///   enables the RAM.
+///  $9-$B 8kb PRG at $8000, $A000, $C000. The last bank is fixed at $E000.
+///  $C mirroring.
+///  $D IRQ control, bit 0 IRQ enable, bit 7 counter enable. Acknowledges.
+///  $E-$F IRQ counter low/high. It counts down every CPU cycle and fires
+///   when it wraps from $0000 to $FFFF.
+/// The 5B adds its PSG at $C000/$E000.
+/// USED: Batman: Return of the Joker, Gimmick! (5B), Hebereke.
+/// http://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
+pub struct Fme7 {
+    pub cart: Box<CART>,
+    pub command:        u8,
+    pub chr_bank:       [u8; 8],
+    pub ram_bank:       u8,         //Command $8.
+    pub prg_bank:       [u8; 3],
+    pub mirroring:      u8,
+    pub irq_enabled:    bool,
+    pub counter_enabled: bool,
+    pub irq_counter:    u16,
+    pub irq_pending:    bool,
+    pub audio:          Sunsoft5b,
+}
+impl Fme7 {
+    pub fn new(cart: Box<CART>) -> Fme7 {
+        Fme7 {
+            cart,
+            command:        0,
+            chr_bank:       [0; 8],
+            ram_bank:       0,
+            prg_bank:       [0; 3],
+            mirroring:      0,
+            irq_enabled:    false,
+            counter_enabled: false,
+            irq_counter:    0,
+            irq_pending:    false,
+            audio:          Sunsoft5b::new(),
+        }
+    }
+
+    fn prg_index(&self, bank: u8, address: u16) -> usize {
+        bank as usize * 0x2000 + (address as usize & 0x1FFF)
+    }
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr_bank[(address >> 10) as usize & 7] as usize;
+        bank * 0x400 + (address as usize & 0x3FF)
+    }
+    fn ram_index(&self, address: u16) -> Option<usize> {
+        let ram = self.ram_bank & 0xC0 == 0xC0 && !self.cart.PRG_RAM.is_empty();
+        let bank = (self.ram_bank & 0x3F) as usize;
+        if ram { Some((bank * 0x2000 + (address as usize & 0x1FFF)) % self.cart.PRG_RAM.len()) } else { None }
+    }
+}
+impl MAP for Fme7 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if self.ram_bank & 0x40 == 0 {
+                prg_read(&self.cart, self.prg_index(self.ram_bank & 0x3F, address))
+            }
+            else {
+                self.ram_index(address).map_or(0, |index| self.cart.PRG_RAM[index])
+            }
+        }
+        else {
+            let bank = match address {
+                0x8000..=0xDFFF => self.prg_bank[(address as usize - 0x8000) >> 13],
+                _ => (self.cart.PRG.len().div_ceil(0x2000).max(1) - 1) as u8,
+            };
+            prg_read(&self.cart, self.prg_index(bank, address))
         }
     }
     fn set(&mut self, address: u16, val: u8) {
-        
+        match address {
+            0x6000..=0x7FFF => if let Some(index) = self.ram_index(address) {
+                self.cart.PRG_RAM[index] = val;
+            },
+            0x8000..=0x9FFF => self.command = val & 0x0F,
+            0xA000..=0xBFFF => match self.command {
+                0x0..=0x7 => self.chr_bank[self.command as usize] = val,
+                0x8 => self.ram_bank = val,
+                0x9..=0xB => self.prg_bank[self.command as usize - 9] = val & 0x3F,
+                0xC => self.mirroring = val & 3,
+                0xD => {
+                    self.irq_enabled     = val & 0x01 != 0;
+                    self.counter_enabled = val & 0x80 != 0;
+                    self.irq_pending     = false;
+                },
+                0xE => self.irq_counter = (self.irq_counter & 0xFF00) | val as u16,
+                _ => self.irq_counter = (self.irq_counter & 0x00FF) | (val as u16) << 8,
+            },
+            0xC000..=0xFFFF => self.audio.write(address, val),
+            _ => {},
+        }
     }
     fn get_chr(&self, address: u16) -> u8 {
-        0
+        chr_read(&self.cart, self.chr_index(address))
     }
     fn set_chr(&mut self, address: u16, val: u8){
-
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Fme7::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
     }
-}
\ No newline at end of file
+    fn clock(&mut self) {
+        if self.counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleLow,
+            _ => Mirroring::SingleHigh,
+        })
+    }
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
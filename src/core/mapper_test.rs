@@ -0,0 +1,1012 @@
+/*  Unit test module of the cartridge mappers (mapper.rs).
+ *  Carts are built by hand, rather than read from a file, so that each
+ *   test can lay out the exact banks it wants to look at.
+ */
+use crate::core::mapper::*;
+
+#[cfg(test)]
+pub mod mapper_test {
+    use super::*;
+
+    /// Builds a synthetic iNES cart with the given number of 16kb PRG and
+    ///  8kb CHR banks. Each PRG bank is filled with its bank number.
+    pub fn synthetic_cart(prg_banks: u8, chr_banks: u8, flags6: u8) -> CART {
+        let mut cart = CART::new("test/synthetic.nes");
+        cart.HEAD = [0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, flags6,
+                     0, 0, 0, 0, 0, 0, 0, 0, 0];
+        cart.parse_header();
+        for bank in 0..prg_banks as usize {
+            cart.PRG.extend(vec![bank as u8; 16384]);
+        }
+        cart.CHR = vec![0; chr_banks as usize * 8192];
+        cart
+    }
+
+    /// 128kb of PRG and chr_banks x 8kb of CHR, cut into prg_bank and
+    ///  chr_bank sized banks that are each filled with their bank number.
+    /// Shared by the other mappers' tests, see mapper.rs.
+    pub(crate) fn banked_cart(mapper: u16, submapper: u8, prg_bank: usize, chr_bank: usize,
+                              chr_banks: u8) -> CART {
+        let mut cart = synthetic_cart(8, chr_banks, 0);
+        cart.mapper = mapper;
+        cart.submapper = submapper;
+        cart.PRG = (0..0x20000 / prg_bank).flat_map(|b| vec![b as u8; prg_bank]).collect();
+        cart.CHR = (0..chr_banks as usize * 0x2000 / chr_bank).flat_map(|b| vec![b as u8; chr_bank]).collect();
+        cart
+    }
+
+    //~~~PRG-RAM~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_prg_ram(){
+        for &map_num in [0, 1, 2, 3, 7].iter() {
+            let mut map = new_map(map_num, Box::new(synthetic_cart(2, 1, 0))).unwrap();
+
+            map.set(0x6000, 0xAB);
+            map.set(0x7FFF, 0xCD);
+            assert_eq!(map.get(0x6000), 0xAB, "Mapper {} RAM start", map_num);
+            assert_eq!(map.get(0x7FFF), 0xCD, "Mapper {} RAM end", map_num);
+        }
+    }
+
+    #[test]
+    fn test_sav_roundtrip(){
+        let path = std::env::temp_dir().join("soliloquy_sav_roundtrip.nes");
+
+        //Battery flag set, write something, and flush it out.
+        let mut cart = synthetic_cart(1, 1, 0b10);
+        cart.filename = Some(path.clone());
+        let mut map = new_map(0, Box::new(cart)).unwrap();
+        map.set(0x6010, 42);
+        map.flush_sav().unwrap();
+
+        //A new cart with the same name should load it right back.
+        let mut cart = synthetic_cart(1, 1, 0b10);
+        cart.filename = Some(path);
+        cart.load_sav().unwrap();
+        assert_eq!(cart.get_ram(0x6010), 42);
+
+        std::fs::remove_file(cart.sav_path().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_sav_needs_battery(){
+        let mut cart = synthetic_cart(1, 1, 0);
+        cart.filename = Some("test/no_battery.nes".into());
+        cart.save_sav().unwrap();
+        assert!(!cart.sav_path().unwrap().exists());
+    }
+
+    //~~~NROM/POWER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    use crate::core::power::PowerOn;
+
+    #[test]
+    fn test_nrom_chr(){
+        let mut map = load_image(&synthetic_image(0, 2, 1, 0, None));
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x1FFF)), (0xC0, 0xC0));
+        assert_eq!(map.fetch_chr(0x1000), 0xC0);
+        //CHR-ROM isn't writable, CHR-RAM is.
+        map.set_chr(0x0000, 0);
+        assert_eq!(map.get_chr(0x0000), 0xC0);
+        let mut map = load_image(&synthetic_image(0, 2, 0, 0, None));
+        map.set_chr(0x1234, 0x5A);
+        assert_eq!(map.get_chr(0x1234), 0x5A);
+    }
+
+    #[test]
+    fn test_power_hooks(){
+        //Work RAM follows the power-on policy, battery RAM doesn't.
+        let mut map = new_map(0, Box::new(synthetic_cart(1, 1, 0))).unwrap();
+        map.set(0x6000, 42);
+        map.power(&mut PowerOn::Ones.source());
+        assert_eq!(map.peek(0x6000), 0xFF);
+        let mut map = new_map(0, Box::new(synthetic_cart(1, 1, 0b10))).unwrap();
+        map.set(0x6000, 42);
+        map.power(&mut PowerOn::Ones.source());
+        assert_eq!(map.peek(0x6000), 42);
+
+        //MMC1 goes back to the last bank fixed at $C000.
+        let mut map = new_map(1, Box::new(synthetic_cart(8, 1, 0))).unwrap();
+        mmc1_write(&mut *map, 0x8000, 0x00);
+        mmc1_write(&mut *map, 0xE000, 0x04);
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (4, 5));
+        map.set(0x8000, 1);                     //Half loaded shift register.
+        map.power(&mut PowerOn::Zeros.source());
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (0, 7));
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleLow));
+        //The reset button doesn't reach the cart.
+        mmc1_write(&mut *map, 0xE000, 0x02);
+        map.reset();
+        assert_eq!(map.peek(0x8000), 2);
+
+        //Every mapper comes back up the way it started, whatever was written.
+        let state = |map: &dyn MAP| {
+            let prg: Vec<u8> = (0x6000..=0xFFFFu32).step_by(0x80).map(|a| map.peek(a as u16)).collect();
+            let chr: Vec<u8> = (0..0x2000u16).step_by(0x100).map(|a| map.get_chr(a)).collect();
+            (prg, chr, map.mirroring(), map.irq())
+        };
+        for &num in &[0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 19, 21, 22, 23, 24, 25, 26, 34, 66, 69, 71, 85] {
+            let mut fresh = new_map(num, Box::new(vrc_cart(num, 0))).unwrap();
+            fresh.power(&mut PowerOn::Ones.source());
+            let mut map = new_map(num, Box::new(vrc_cart(num, 0))).unwrap();
+            for address in (0x4020..=0xFFFFu32).step_by(0x101) {
+                map.set(address as u16, (address >> 4) as u8 | 1);
+            }
+            map.power(&mut PowerOn::Ones.source());
+            assert!(state(&*map) == state(&*fresh), "mapper {}", num);
+        }
+
+        //EMPTY_MAP ignores all of it.
+        let mut map = EMPTY_MAP;
+        map.power(&mut PowerOn::Ones.source());
+        map.reset();
+        map.clock();
+        assert_eq!((map.peek(0x8000), map.irq(), map.mirroring()), (0, false, None));
+
+        //And the console passes them on.
+        let mut cart = synthetic_cart(1, 1, 0);
+        cart.PRG[0x3FFC] = 0x34;                //Reset vector, $8234.
+        cart.PRG[0x3FFD] = 0x82;
+        cart.PRG[0x0234] = 0xEA;                //NOP.
+        let mut nes = crate::core::nes::NES::from_cart(cart).unwrap();
+        assert_eq!(nes.pc(), 0x8234);
+        nes.power_on(PowerOn::Ones);
+        assert_eq!(nes.peek(0x6000), 0xFF);
+        nes.power_on(PowerOn::Zeros);
+        assert_eq!(nes.peek(0x6000), 0x00);
+        //Resetting leaves RAM alone.
+        nes.power_on(PowerOn::Ones);
+        nes.reset();
+        assert_eq!(nes.peek(0x6000), 0xFF);
+        //The CPU starts over from the reset vector.
+        assert_eq!(nes.pc(), 0x8234);
+        //So does a power cycle mid-run.
+        nes.step();
+        assert_eq!(nes.pc(), 0x8235);
+        nes.power_on(PowerOn::Zeros);
+        assert_eq!(nes.pc(), 0x8234);
+    }
+
+    //~~~MMC1~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// Loads an MMC1 register the way games do, a bit per write, with the
+    ///  CPU cycles of an STA in between.
+    fn mmc1_write(map: &mut dyn MAP, address: u16, val: u8) {
+        for bit in 0..5 {
+            map.set(address, (val >> bit) & 1);
+            for _ in 0..4 {
+                map.clock();
+            }
+        }
+    }
+
+    #[test]
+    fn test_mmc1_prg_modes(){
+        let mut map = new_map(1, Box::new(synthetic_cart(8, 1, 0))).unwrap();
+        //Powers up in mode 3, last bank fixed at $C000.
+        assert_eq!(map.peek(0x8000), 0);
+        assert_eq!(map.peek(0xC000), 7);
+        mmc1_write(&mut *map, 0xE000, 5);
+        assert_eq!(map.peek(0x8000), 5);
+        assert_eq!(map.peek(0xFFFF), 7);
+
+        //Mode 2, first bank fixed at $8000.
+        mmc1_write(&mut *map, 0x8000, 0x08);
+        assert_eq!(map.peek(0x8000), 0);
+        assert_eq!(map.peek(0xC000), 5);
+
+        //32kb mode ignores the low bit.
+        mmc1_write(&mut *map, 0x8000, 0x00);
+        assert_eq!(map.peek(0x8000), 4);
+        assert_eq!(map.peek(0xC000), 5);
+    }
+
+    #[test]
+    fn test_mmc1_shift_register(){
+        let mut map = new_map(1, Box::new(synthetic_cart(8, 1, 0))).unwrap();
+        mmc1_write(&mut *map, 0x8000, 0x00);
+
+        //A write with bit 7 set throws away a half loaded value, and puts
+        // the PRG mode back to 3.
+        map.set(0xE000, 1);
+        map.clock(); map.clock();
+        map.set(0xE000, 0x80);
+        map.clock(); map.clock();
+        assert_eq!(map.peek(0xC000), 7);
+        mmc1_write(&mut *map, 0xE000, 2);
+        assert_eq!(map.peek(0x8000), 2);
+
+        //Back to back writes (INC $FFFF) only count the first.
+        map.set(0xE000, 1);
+        map.set(0xE000, 0);
+        for bit in 1..5 {
+            map.clock(); map.clock();
+            map.set(0xE000, (3 >> bit) & 1);
+        }
+        map.clock(); map.clock();
+        assert_eq!(map.peek(0x8000), 3);
+    }
+
+    #[test]
+    fn test_mmc1_mirroring(){
+        let mut map = new_map(1, Box::new(synthetic_cart(2, 1, 0))).unwrap();
+        let modes = [Mirroring::SingleLow, Mirroring::SingleHigh,
+                     Mirroring::Vertical, Mirroring::Horizontal];
+        for (bits, &mode) in modes.iter().enumerate() {
+            mmc1_write(&mut *map, 0x8000, 0x0C | bits as u8);
+            assert_eq!(map.mirroring(), Some(mode));
+        }
+    }
+
+    #[test]
+    fn test_mmc1_chr_banks(){
+        //16kb of CHR-ROM, each 4kb filled with its bank number.
+        let mut cart = synthetic_cart(2, 2, 0);
+        cart.CHR = (0..4u8).flat_map(|b| vec![b; 4096]).collect();
+        let mut map = new_map(1, Box::new(cart)).unwrap();
+
+        //8kb mode ignores the low bit, and bank 1.
+        mmc1_write(&mut *map, 0xA000, 3);
+        mmc1_write(&mut *map, 0xC000, 0);
+        assert_eq!(map.get_chr(0x0000), 2);
+        assert_eq!(map.get_chr(0x1000), 3);
+
+        //4kb mode.
+        mmc1_write(&mut *map, 0x8000, 0x1C);
+        assert_eq!(map.get_chr(0x0000), 3);
+        assert_eq!(map.get_chr(0x1FFF), 0);
+
+        //CHR-ROM can't be written.
+        map.set_chr(0x0000, 0xFF);
+        assert_eq!(map.get_chr(0x0000), 3);
+    }
+
+    #[test]
+    fn test_mmc1_prg_ram_disable(){
+        //SNROM: CHR-RAM, and CHR bank 0's bit 4 gates PRG-RAM.
+        let mut cart = synthetic_cart(16, 0, 0b10);
+        cart.CHR = vec![0; 8192];
+        let mut map = new_map(1, Box::new(cart)).unwrap();
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0x42);
+
+        mmc1_write(&mut *map, 0xA000, 0x10);
+        assert_eq!(map.peek(0x6000), 0);
+        map.set(0x6000, 0x99);
+        mmc1_write(&mut *map, 0xA000, 0x00);
+        assert_eq!(map.peek(0x6000), 0x42);
+
+        //PRG bank bit 4 disables it on every board.
+        mmc1_write(&mut *map, 0xE000, 0x10);
+        assert_eq!(map.peek(0x6000), 0);
+        mmc1_write(&mut *map, 0xE000, 0x00);
+
+        //CHR-RAM is writable.
+        map.set_chr(0x0123, 0x77);
+        assert_eq!(map.get_chr(0x0123), 0x77);
+    }
+
+    #[test]
+    fn test_mmc1_sxrom(){
+        //512kb of PRG and 32kb of PRG-RAM: SXROM, which covers SUROM's
+        // PRG banking and SOROM's RAM banking.
+        let mut cart = synthetic_cart(32, 0, 0b10);
+        cart.CHR = vec![0; 8192];
+        cart.PRG_RAM = vec![0; 0x8000];
+        let mut map = new_map(1, Box::new(cart)).unwrap();
+
+        //The last bank of the first 256kb is fixed, until bit 4 flips it.
+        mmc1_write(&mut *map, 0xE000, 3);
+        assert_eq!(map.peek(0x8000), 3);
+        assert_eq!(map.peek(0xC000), 15);
+        mmc1_write(&mut *map, 0xA000, 0x10);
+        assert_eq!(map.peek(0x8000), 19);
+        assert_eq!(map.peek(0xC000), 31);
+
+        //Bits 2-3 pick the 8kb of RAM. Bit 4 doesn't disable it here.
+        for bank in 0..4u8 {
+            mmc1_write(&mut *map, 0xA000, 0x10 | bank << 2);
+            map.set(0x6000, 0xA0 | bank);
+        }
+        for bank in 0..4u8 {
+            mmc1_write(&mut *map, 0xA000, bank << 2);
+            assert_eq!(map.peek(0x6000), 0xA0 | bank);
+        }
+    }
+
+    //~~~DISCRETE~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// A whole iNES image, PRG banks filled with their 16kb bank number and
+    ///  CHR banks with $C0 + their 8kb bank number. A submapper makes it
+    ///  NES 2.0.
+    pub fn synthetic_image(mapper: u8, prg_banks: u8, chr_banks: u8, flags6: u8,
+                           submapper: Option<u8>) -> Vec<u8> {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks,
+                           (mapper << 4) | flags6, mapper & 0xF0,
+                           0, 0, 0, 0, 0, 0, 0, 0];
+        if let Some(sub) = submapper {
+            rom[7] |= 0x08;
+            rom[8]  = sub << 4;
+            rom[11] = if chr_banks == 0 { 7 } else { 0 };   //8kb CHR-RAM.
+        }
+        for bank in 0..prg_banks {
+            rom.extend(vec![bank; 16384]);
+        }
+        for bank in 0..chr_banks {
+            rom.extend(vec![0xC0 + bank; 8192]);
+        }
+        rom
+    }
+
+    fn load_image(image: &[u8]) -> Box<dyn MAP> {
+        let opts = LoadOptions { database: None, ..LoadOptions::default() };
+        let cart = CART::from_bytes_with(image, &opts).unwrap();
+        new_map(cart.mapper, Box::new(cart)).unwrap()
+    }
+
+    #[test]
+    fn test_uxrom(){
+        let mut map = load_image(&synthetic_image(2, 8, 0, 0, None));
+        assert_eq!(map.peek(0x8000), 0);
+        assert_eq!(map.peek(0xC000), 7);
+        map.set(0x8000, 3);
+        assert_eq!(map.peek(0xBFFF), 3);
+        assert_eq!(map.peek(0xFFFF), 7);
+        //Banks past the end wrap around.
+        map.set(0xFFFF, 9);
+        assert_eq!(map.peek(0x8000), 1);
+
+        //CHR-RAM.
+        map.set_chr(0x1234, 0x5A);
+        assert_eq!(map.get_chr(0x1234), 0x5A);
+
+        //Submapper 2: the ROM's byte ($C000 holds 7) is ANDed in.
+        let mut map = load_image(&synthetic_image(2, 8, 0, 0, Some(2)));
+        map.set(0xC000, 0x0A);
+        assert_eq!(map.peek(0x8000), 2);
+    }
+
+    #[test]
+    fn test_cnrom(){
+        let mut map = load_image(&synthetic_image(3, 1, 4, 1, None));
+        assert_eq!(map.get_chr(0x0000), 0xC0);
+        map.set(0x8000, 2);
+        assert_eq!(map.get_chr(0x0000), 0xC2);
+        assert_eq!(map.get_chr(0x1FFF), 0xC2);
+        map.set(0x8000, 5);
+        assert_eq!(map.get_chr(0x0000), 0xC1);
+
+        //16kb of PRG shows up twice, and CHR-ROM isn't writable.
+        assert_eq!(map.peek(0xC000), map.peek(0x8000));
+        map.set_chr(0x0000, 0);
+        assert_eq!(map.get_chr(0x0000), 0xC1);
+        assert_eq!(map.mirroring(), None);
+    }
+
+    #[test]
+    fn test_axrom(){
+        //128kb, four 32kb banks.
+        let mut map = load_image(&synthetic_image(7, 8, 0, 0, None));
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (0, 1));
+        map.set(0x8000, 2);
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (4, 5));
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleLow));
+
+        map.set(0x8000, 0x13);
+        assert_eq!((map.peek(0x8000), map.peek(0xFFFF)), (6, 7));
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleHigh));
+
+        map.set_chr(0x0042, 0x24);
+        assert_eq!(map.get_chr(0x0042), 0x24);
+    }
+
+    #[test]
+    fn test_color_dreams(){
+        let mut map = load_image(&synthetic_image(11, 8, 4, 0, None));
+        assert_eq!((map.peek(0x8000), map.get_chr(0x0000)), (0, 0xC0));
+        map.set(0x8000, 0x31);
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (2, 3));
+        assert_eq!(map.get_chr(0x1FFF), 0xC3);
+        //CHR-ROM isn't writable.
+        map.set_chr(0x0000, 0);
+        assert_eq!(map.get_chr(0x0000), 0xC3);
+    }
+
+    #[test]
+    fn test_gxrom(){
+        let mut map = load_image(&synthetic_image(66, 8, 4, 0, None));
+        map.set(0x8000, 0x21);
+        assert_eq!((map.peek(0x8000), map.peek(0xFFFF)), (4, 5));
+        assert_eq!(map.get_chr(0x0000), 0xC1);
+        map.set(0x8000, 0x32);
+        assert_eq!((map.peek(0x8000), map.peek(0xFFFF)), (6, 7));
+        assert_eq!(map.get_chr(0x1000), 0xC2);
+    }
+
+    #[test]
+    fn test_bnrom(){
+        //No submapper and CHR-RAM: BNROM.
+        let mut map = load_image(&synthetic_image(34, 8, 0, 0, None));
+        map.set(0x8000, 3);
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (6, 7));
+        map.set_chr(0x0123, 0x77);
+        assert_eq!(map.get_chr(0x0123), 0x77);
+        //The NINA-001 registers are just RAM here.
+        map.set(0x7FFD, 0);
+        assert_eq!(map.peek(0x8000), 6);
+
+        //Submapper 2 has bus conflicts: $8000 holds 6, so 1 & 6 = 0.
+        let mut map = load_image(&synthetic_image(34, 8, 0, 0, Some(2)));
+        map.set(0x8000, 3);
+        map.set(0x8000, 1);
+        assert_eq!(map.peek(0x8000), 0);
+    }
+
+    #[test]
+    fn test_nina001(){
+        //16kb of CHR-ROM, so NINA-001 without a submapper.
+        let mut map = load_image(&synthetic_image(34, 4, 2, 0, None));
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x1000)), (0xC0, 0xC0));
+        map.set(0x7FFD, 1);
+        map.set(0x7FFE, 2);
+        map.set(0x7FFF, 3);
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (2, 3));
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x1000)), (0xC1, 0xC1));
+        //The registers write through to PRG-RAM.
+        assert_eq!(map.peek(0x7FFF), 3);
+        //$8000 writes don't bank.
+        map.set(0x8000, 0);
+        assert_eq!(map.peek(0x8000), 2);
+
+        //Submapper 1 with 8kb of CHR-ROM.
+        let mut map = load_image(&synthetic_image(34, 4, 1, 0, Some(1)));
+        map.set(0x7FFD, 1);
+        assert_eq!(map.peek(0x8000), 2);
+    }
+
+    #[test]
+    fn test_camerica(){
+        let mut map = load_image(&synthetic_image(71, 8, 0, 1, None));
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (0, 7));
+        map.set(0xC000, 2);
+        assert_eq!((map.peek(0xBFFF), map.peek(0xFFFF)), (2, 7));
+        //$8000-$BFFF is ignored apart from the Fire Hawk register.
+        map.set(0x8000, 5);
+        assert_eq!(map.peek(0x8000), 2);
+        assert_eq!(map.mirroring(), None);
+        //Writing $9000 turns on Fire Hawk's mirroring.
+        map.set(0x9000, 0x10);
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleHigh));
+        map.set(0x9000, 0x00);
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleLow));
+        map.set_chr(0x0042, 0x24);
+        assert_eq!(map.get_chr(0x0042), 0x24);
+
+        //Submapper 1 is Fire Hawk; submapper 2 never is.
+        let map = load_image(&synthetic_image(71, 8, 0, 0, Some(1)));
+        assert_eq!(map.mirroring(), None);
+        let mut map = load_image(&synthetic_image(71, 8, 0, 0, Some(2)));
+        map.set(0x9000, 0x10);
+        assert_eq!(map.mirroring(), None);
+    }
+
+    //~~~MMC3~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// 8kb PRG banks, and 16kb of CHR in 1kb banks.
+    fn mmc3_cart() -> CART {
+        banked_cart(4, 0, 0x2000, 0x400, 2)
+    }
+
+    /// What a rendered scanline looks like to the counter: background
+    ///  fetches from $0000, then sprites from $1000.
+    fn scanline(map: &mut dyn MAP) {
+        map.ppu_bus(0x0000);
+        for _ in 0..80 { map.clock(); }
+        map.ppu_bus(0x1000);
+        for _ in 0..30 { map.clock(); }
+    }
+
+    #[test]
+    fn test_mmc3_banks(){
+        let mut map = new_map(4, Box::new(mmc3_cart())).unwrap();
+        assert_eq!((map.peek(0x8000), map.peek(0xA000)), (0, 1));
+        assert_eq!((map.peek(0xC000), map.peek(0xE000)), (14, 15));
+
+        map.set(0x8000, 6);
+        map.set(0x8001, 4);
+        map.set(0x8000, 7);
+        map.set(0x8001, 5);
+        assert_eq!((map.peek(0x8000), map.peek(0xA000)), (4, 5));
+        //PRG mode 1 swaps $8000 and $C000.
+        map.set(0x8000, 0x46);
+        assert_eq!((map.peek(0x8000), map.peek(0xC000)), (14, 4));
+        assert_eq!(map.peek(0xFFFF), 15);
+
+        //R0 is 2kb, so the low bit is ignored. R2 is 1kb.
+        map.set(0x8000, 0);
+        map.set(0x8001, 3);
+        map.set(0x8000, 2);
+        map.set(0x8001, 9);
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x0400)), (2, 3));
+        assert_eq!(map.get_chr(0x1000), 9);
+        //CHR inversion swaps the halves.
+        map.set(0x8000, 0x80);
+        assert_eq!((map.get_chr(0x1000), map.get_chr(0x1400)), (2, 3));
+        assert_eq!(map.get_chr(0x0000), 9);
+        //Registers repeat every other address.
+        map.set(0x9FFE, 0x46);
+        assert_eq!(map.peek(0x8000), 14);
+    }
+
+    #[test]
+    fn test_mmc3_mirroring_ram(){
+        let mut map = new_map(4, Box::new(mmc3_cart())).unwrap();
+        map.set(0xA000, 1);
+        assert_eq!(map.mirroring(), Some(Mirroring::Horizontal));
+        map.set(0xA000, 0);
+        assert_eq!(map.mirroring(), Some(Mirroring::Vertical));
+
+        map.set(0x6000, 0x11);
+        assert_eq!(map.peek(0x6000), 0x11);
+        //Write protected.
+        map.set(0xA001, 0xC0);
+        map.set(0x6000, 0x22);
+        assert_eq!(map.peek(0x6000), 0x11);
+        //Disabled.
+        map.set(0xA001, 0x00);
+        assert_eq!(map.peek(0x6000), 0);
+
+        //Four-screen carts ignore $A000.
+        let mut cart = mmc3_cart();
+        cart.mirroring = Mirroring::FourScreen;
+        let mut map = new_map(4, Box::new(cart)).unwrap();
+        map.set(0xA000, 1);
+        assert_eq!(map.mirroring(), None);
+    }
+
+    #[test]
+    fn test_mmc3_irq(){
+        let mut map = new_map(4, Box::new(mmc3_cart())).unwrap();
+        map.set(0xC000, 3);
+        map.set(0xC001, 0);
+        map.set(0xE001, 0);
+
+        //Reload to 3, then 2, 1, 0.
+        for line in 0..3 {
+            scanline(&mut *map);
+            assert!(!map.irq(), "IRQ early, on line {}", line);
+        }
+        scanline(&mut *map);
+        assert!(map.irq());
+
+        //$E000 acknowledges and disables.
+        map.set(0xE000, 0);
+        assert!(!map.irq());
+        for _ in 0..8 {
+            scanline(&mut *map);
+        }
+        assert!(!map.irq());
+
+        //A12 blips too quick for the filter don't count.
+        map.set(0xE001, 0);
+        map.set(0xC001, 0);
+        scanline(&mut *map);    //Reloaded to 3.
+        for _ in 0..10 {
+            map.ppu_bus(0x0000);
+            map.clock();
+            map.ppu_bus(0x1000);
+        }
+        assert!(!map.irq());
+        for _ in 0..3 {
+            scanline(&mut *map);
+        }
+        assert!(map.irq());
+    }
+
+    #[test]
+    fn test_mmc3_revisions(){
+        //A latch of 0: Sharp chips fire every scanline, NEC ones only for
+        // the reload.
+        for &(submapper, lines) in [(0, 4), (4, 1)].iter() {
+            let mut cart = mmc3_cart();
+            cart.submapper = submapper;
+            let mut map = MMC3::new(Box::new(cart));
+            map.set(0xC000, 0);
+            map.set(0xC001, 0);
+            map.set(0xE001, 0);
+            let mut fired = 0;
+            for _ in 0..4 {
+                scanline(&mut map);
+                if map.irq() {
+                    fired += 1;
+                    map.set(0xE000, 0);
+                    map.set(0xE001, 0);
+                }
+            }
+            assert_eq!(fired, lines, "Submapper {}", submapper);
+        }
+    }
+
+    #[test]
+    fn test_mmc6_ram(){
+        let mut cart = mmc3_cart();
+        cart.submapper = 1;
+        cart.PRG_RAM = vec![0; 1024];
+        let mut map = new_map(4, Box::new(cart)).unwrap();
+        assert_eq!(map.peek(0x7000), 0);
+
+        //$8000 bit 5 turns it on, $A001 opens both halves.
+        map.set(0x8000, 0x20);
+        map.set(0xA001, 0xF0);
+        map.set(0x7000, 1);
+        map.set(0x7200, 2);
+        assert_eq!((map.peek(0x7400), map.peek(0x7600)), (1, 2));
+        //Nothing below $7000.
+        assert_eq!(map.peek(0x6000), 0);
+
+        //Just the low half, the high one reads 0 and ignores writes.
+        map.set(0xA001, 0x30);
+        map.set(0x7200, 3);
+        assert_eq!((map.peek(0x7000), map.peek(0x7200)), (1, 0));
+        map.set(0xA001, 0xF0);
+        assert_eq!(map.peek(0x7200), 2);
+    }
+
+    //~~~MMC2/MMC4~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// 8kb PRG banks, and 32kb of CHR in 4kb banks.
+    fn latch_cart(mapper: u16) -> CART {
+        banked_cart(mapper, 0, 0x2000, 0x1000, 4)
+    }
+
+    #[test]
+    fn test_mmc2_prg(){
+        let mut map = new_map(9, Box::new(latch_cart(9))).unwrap();
+        map.set(0xA000, 5);
+        assert_eq!(map.peek(0x8000), 5);
+        assert_eq!((map.peek(0xA000), map.peek(0xC000), map.peek(0xE000)), (13, 14, 15));
+        assert_eq!(map.peek(0x6000), 0);
+
+        //MMC4 switches 16kb, and has PRG-RAM.
+        let mut map = new_map(10, Box::new(latch_cart(10))).unwrap();
+        map.set(0xA000, 2);
+        assert_eq!((map.peek(0x8000), map.peek(0xA000)), (4, 5));
+        assert_eq!((map.peek(0xC000), map.peek(0xE000)), (14, 15));
+        map.set(0x6000, 0x33);
+        assert_eq!(map.peek(0x6000), 0x33);
+
+        map.set(0xF000, 1);
+        assert_eq!(map.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_mmc2_latches(){
+        let mut map = new_map(9, Box::new(latch_cart(9))).unwrap();
+        map.set(0xB000, 1);     //$FD/$0000
+        map.set(0xC000, 2);     //$FE/$0000
+        map.set(0xD000, 3);     //$FD/$1000
+        map.set(0xE000, 4);     //$FE/$1000
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x1000)), (2, 4));
+
+        //The fetch of tile $FD still comes from the $FE bank, the next
+        // one doesn't.
+        assert_eq!(map.fetch_chr(0x0FD8), 2);
+        assert_eq!(map.fetch_chr(0x0000), 1);
+        assert_eq!(map.get_chr(0x1000), 4);
+        map.fetch_chr(0x1FDF);
+        assert_eq!(map.get_chr(0x1000), 3);
+        map.fetch_chr(0x1FE8);
+        assert_eq!(map.get_chr(0x1000), 4);
+
+        //MMC2's low latch only takes the exact address, MMC4's the row.
+        map.fetch_chr(0x0FEA);
+        assert_eq!(map.get_chr(0x0000), 1);
+        map.fetch_chr(0x0FE8);
+        assert_eq!(map.get_chr(0x0000), 2);
+
+        let mut map = new_map(10, Box::new(latch_cart(10))).unwrap();
+        map.set(0xB000, 1);
+        map.set(0xC000, 2);
+        map.fetch_chr(0x0FDD);
+        assert_eq!(map.get_chr(0x0000), 1);
+
+        //Plain reads never move a latch.
+        map.get_chr(0x0FE8);
+        assert_eq!(map.get_chr(0x0000), 1);
+    }
+
+    #[test]
+    fn test_mmc2_ppu_fetch(){
+        //PPUDATA reads reach the cart as fetches, so $2007 moves latches.
+        let mut map = new_map(9, Box::new(latch_cart(9))).unwrap();
+        map.set(0xB000, 1);
+        map.set(0xC000, 2);
+        let mut ppu = crate::core::ppu::PPU::new();
+        ppu.write_register(0x2006, 0x0F, &mut *map);
+        ppu.write_register(0x2006, 0xD8, &mut *map);
+        ppu.read_register(0x2007, &mut *map);
+        assert_eq!(map.get_chr(0x0000), 1);
+    }
+
+    //~~~VRC2/VRC4~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// 8kb PRG and 1kb CHR banks.
+    fn vrc_cart(mapper: u16, submapper: u8) -> CART {
+        banked_cart(mapper, submapper, 0x2000, 0x400, 16)
+    }
+
+    #[test]
+    fn test_vrc4_prg(){
+        let mut map = new_map(21, Box::new(vrc_cart(21, 1))).unwrap();
+        map.set(0x8000, 3);
+        map.set(0xA000, 4);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![3, 4, 14, 15]);
+
+        //$9002 is A2 on VRC4a.
+        map.set(0x9004, 0x02);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![14, 4, 3, 15]);
+
+        map.set(0x9000, 3);
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleHigh));
+        map.set(0x6000, 0x5A);
+        assert_eq!(map.peek(0x6000), 0x5A);
+    }
+
+    #[test]
+    fn test_vrc_pins(){
+        //(mapper, submapper, low pin, high pin)
+        let boards = [
+            (21, 1, 0x02, 0x04), (21, 2, 0x40, 0x80), (21, 0, 0x40, 0x80),
+            (22, 0, 0x02, 0x01),
+            (23, 1, 0x01, 0x02), (23, 2, 0x04, 0x08), (23, 3, 0x01, 0x02), (23, 0, 0x04, 0x08),
+            (25, 1, 0x02, 0x01), (25, 2, 0x08, 0x04), (25, 3, 0x02, 0x01), (25, 0, 0x08, 0x04),
+        ];
+        for &(mapper, sub, low, high) in boards.iter() {
+            let mut map = new_map(mapper, Box::new(vrc_cart(mapper, sub))).unwrap();
+            //CHR bank 1 is $B002 (low nibble) and $B003 (high nibble).
+            map.set(0xB000 | high, 0x05);
+            map.set(0xB000 | high | low, 0x01);
+            //VRC2a drops the bottom bit.
+            let expected = if mapper == 22 { 0x0A } else { 0x15 };
+            assert_eq!(map.get_chr(0x0400), expected, "Mapper {}.{}", mapper, sub);
+            assert_eq!(map.get_chr(0x0000), 0, "Mapper {}.{}", mapper, sub);
+        }
+    }
+
+    #[test]
+    fn test_vrc2(){
+        let mut cart = vrc_cart(23, 3);
+        cart.PRG_RAM = Vec::new();
+        let mut map = new_map(23, Box::new(cart)).unwrap();
+        //One bit at $6000-$7FFF.
+        map.set(0x6000, 0xFF);
+        assert_eq!(map.peek(0x7000), 1);
+
+        //Only horizontal/vertical, no PRG swap.
+        map.set(0x9000, 3);
+        assert_eq!(map.mirroring(), Some(Mirroring::Horizontal));
+        map.set(0x9002, 0x02);
+        assert_eq!(map.peek(0x8000), 0);
+
+        //And no IRQ.
+        map.set(0xF002, 0x06);
+        map.clock();
+        map.clock();
+        assert!(!map.irq());
+    }
+
+    #[test]
+    fn test_vrc_irq(){
+        let mut map = new_map(23, Box::new(vrc_cart(23, 1))).unwrap();
+        //Cycle mode, latch $FD.
+        map.set(0xF000, 0x0D);
+        map.set(0xF001, 0x0F);
+        map.set(0xF002, 0x06);
+        map.clock();
+        map.clock();
+        assert!(!map.irq());
+        map.clock();
+        assert!(map.irq());
+
+        //Acknowledge copies bit 0 (clear) into the enable.
+        map.set(0xF003, 0);
+        assert!(!map.irq());
+        for _ in 0..10 {
+            map.clock();
+        }
+        assert!(!map.irq());
+
+        //Scanline mode: the prescaler counts 341 PPU dots, 3 per CPU cycle.
+        map.set(0xF000, 0x0F);
+        map.set(0xF002, 0x03);
+        for _ in 0..113 {
+            map.clock();
+        }
+        assert!(!map.irq());
+        map.clock();
+        assert!(map.irq());
+        //Stays enabled this time.
+        map.set(0xF003, 0);
+        assert!(!map.irq());
+        for _ in 0..114 {
+            map.clock();
+        }
+        assert!(map.irq());
+    }
+
+    //~~~FME-7~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    fn fme7_command(map: &mut Box<dyn MAP>, command: u8, val: u8) {
+        map.set(0x8000, command);
+        map.set(0xA000, val);
+    }
+
+    #[test]
+    fn test_fme7_banks(){
+        let mut map = new_map(69, Box::new(vrc_cart(69, 0))).unwrap();
+        fme7_command(&mut map, 0x9, 3);
+        fme7_command(&mut map, 0xA, 4);
+        fme7_command(&mut map, 0xB, 5);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![3, 4, 5, 15]);
+        for slot in 0..8 {
+            fme7_command(&mut map, slot, 40 + slot);
+        }
+        let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+        assert_eq!(banks, vec![40, 41, 42, 43, 44, 45, 46, 47]);
+        fme7_command(&mut map, 0xC, 2);
+        assert_eq!(map.mirroring(), Some(Mirroring::SingleLow));
+
+        //$6000: ROM, then RAM, then RAM disabled.
+        fme7_command(&mut map, 0x8, 0x07);
+        assert_eq!(map.peek(0x6000), 7);
+        fme7_command(&mut map, 0x8, 0xC0);
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0x42);
+        fme7_command(&mut map, 0x8, 0x40);
+        map.set(0x6000, 0x11);
+        assert_eq!(map.peek(0x6000), 0);
+        fme7_command(&mut map, 0x8, 0xC0);
+        assert_eq!(map.peek(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_fme7_irq(){
+        let mut map = new_map(69, Box::new(vrc_cart(69, 0))).unwrap();
+        fme7_command(&mut map, 0xE, 2);
+        fme7_command(&mut map, 0xF, 0);
+        fme7_command(&mut map, 0xD, 0x81);
+        map.clock();
+        map.clock();
+        assert!(!map.irq());
+        //$0000 to $FFFF.
+        map.clock();
+        assert!(map.irq());
+        fme7_command(&mut map, 0xD, 0x81);
+        assert!(!map.irq());
+
+        //Counting, but not firing.
+        fme7_command(&mut map, 0xD, 0x80);
+        for _ in 0..0x10000 {
+            map.clock();
+        }
+        assert!(!map.irq());
+    }
+
+    #[test]
+    fn test_sunsoft_5b(){
+        let mut map = new_map(69, Box::new(vrc_cart(69, 0))).unwrap();
+        let psg = |map: &mut Box<dyn MAP>, reg: u8, val: u8| {
+            map.set(0xC000, reg);
+            map.set(0xE000, val);
+        };
+        //Channel A only, period 1, full volume.
+        psg(&mut map, 0x00, 1);
+        psg(&mut map, 0x07, 0x3E);
+        psg(&mut map, 0x08, 0x0F);
+        let mut changes = 0;
+        let mut last = map.audio();
+        for _ in 0..320 {
+            map.clock();
+            if map.audio() != last {
+                changes += 1;
+                last = map.audio();
+            }
+        }
+        //Toggling every 16 CPU cycles.
+        assert_eq!(changes, 20);
+        assert!((0..32).map(|_| { map.clock(); map.audio() }).any(|level| (level - 0.15).abs() < 1e-6));
+
+        //The envelope ramps up to full, and holds there.
+        psg(&mut map, 0x07, 0x3F);
+        psg(&mut map, 0x08, 0x10);
+        psg(&mut map, 0x0B, 1);
+        psg(&mut map, 0x0D, 0x0D);
+        for _ in 0..16 * 40 {
+            map.clock();
+        }
+        assert!((map.audio() - 0.15).abs() < 1e-6);
+        //Ramps down, and holds at 0.
+        psg(&mut map, 0x0D, 0x00);
+        for _ in 0..16 * 40 {
+            map.clock();
+        }
+        assert_eq!(map.audio(), 0.0);
+
+        //Noise alone still makes a sound.
+        psg(&mut map, 0x07, 0x37);
+        psg(&mut map, 0x08, 0x0F);
+        let levels: Vec<f32> = (0..16 * 200).map(|_| { map.clock(); map.audio() }).collect();
+        assert!(levels.contains(&0.0) && levels.iter().any(|&l| l > 0.1));
+    }
+
+    //~~~REGISTRY~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    use crate::core::registry::*;
+
+    #[test]
+    fn test_registry(){
+        assert_eq!(mapper_name(4, 0), Some("MMC3/MMC6 (TxROM)"));
+        assert_eq!(mapper_name(3000, 0), None);
+        //Submapper specific entries win over the catch-all.
+        assert_eq!(mapper_name(34, 1), Some("NINA-001"));
+        assert_eq!(mapper_name(34, 2), Some("BNROM"));
+        assert_eq!(mapper_name(34, 0), Some("BNROM/NINA-001"));
+        assert_eq!(mapper_name(1, 5), Some("MMC1 (SEROM)"));
+        assert_eq!(mapper_name(1, 3), Some("MMC1 (SxROM)"));
+
+        //Board names carry their submapper.
+        assert_eq!(find_board("NES-SEROM").map(|m| (m.number, m.submapper())), Some((1, 5)));
+        assert_eq!(find_board("hvc-gnrom").map(|m| m.number), Some(66));
+        assert!(find_board("NES-NOPE").is_none());
+
+        //Known by name, not emulated.
+        assert!(find_mapper(206, 0).unwrap().new.is_none());
+        //Everything new_map used to build still builds.
+        for &num in &[0, 1, 2, 3, 4, 5, 7, 9, 10, 11, 19, 21, 22, 23, 24, 25, 26, 34, 66, 69, 71, 85] {
+            assert!(find_mapper(num, 0).unwrap().new.is_some(), "mapper {}", num);
+        }
+    }
+
+    /// A homebrew board, as a downstream crate would write it.
+    struct Homebrew {
+        cart: Box<CART>,
+        latch: u8,
+    }
+    impl MAP for Homebrew {
+        fn peek(&self, address: u16) -> u8 {
+            if address >= 0x8000 { self.latch } else { 0 }
+        }
+        fn set(&mut self, _address: u16, val: u8) { self.latch = val; }
+        fn get_chr(&self, address: u16) -> u8 { self.cart.CHR[address as usize % self.cart.CHR.len()] }
+        fn set_chr(&mut self, _address: u16, _val: u8) {}
+    }
+
+    #[test]
+    fn test_register_mapper(){
+        register_mapper(MapperInfo { number: 3840, submappers: &[], boards: &["HOMEBREW"],
+                                     name: "Homebrew", new: Some(|cart| Box::new(Homebrew{cart, latch: 0x42})) });
+        assert_eq!(mapper_name(3840, 7), Some("Homebrew"));
+        assert_eq!(crate::core::unif::board_mapper("UNL-HOMEBREW"), Some((3840, 0)));
+
+        let mut map = new_map(3840, Box::new(synthetic_cart(1, 1, 0))).unwrap();
+        assert_eq!(map.peek(0x8000), 0x42);
+        map.set(0x8000, 0x24);
+        assert_eq!(map.peek(0xFFFF), 0x24);
+
+        //Registering the number again replaces it.
+        register_mapper(MapperInfo { number: 3840, submappers: &[], boards: &[],
+                                     name: "Homebrew rev. B", new: Some(|cart| Box::new(Homebrew{cart, latch: 0})) });
+        assert_eq!(mapper_name(3840, 0), Some("Homebrew rev. B"));
+        assert_eq!(new_map(3840, Box::new(synthetic_cart(1, 1, 0))).unwrap().peek(0x8000), 0);
+    }
+
+    #[test]
+    fn test_unemulated_mapper(){
+        //Known but not emulated, and unknown, are both errors, not panics.
+        let err = new_map(206, Box::new(synthetic_cart(1, 1, 0))).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert_eq!(err.to_string(), "Mapper 206 (Namco 118) has not been implemented!");
+        let err = new_map(3000, Box::new(synthetic_cart(1, 1, 0))).err().unwrap();
+        assert_eq!(err.to_string(), "Mapper 3000 has not been implemented!");
+
+        //And the console passes the error on.
+        let mut cart = synthetic_cart(1, 1, 0);
+        cart.mapper = 206;
+        assert!(crate::core::nes::NES::from_cart(cart).is_err());
+    }
+}
@@ -2,6 +2,9 @@
  * Author: Spalynx
  *--------------Memory Map---------------------------------------------
  * $0000-$07FF     =      Internal CPU RAM
+ * $0800-$1FFF     =      Mirrors of $0000-$07FF
+ * $2000-$2007     =      PPU registers (mirrored every 8 bytes to $3FFF)
+ * $4000-$4017     =      APU and I/O registers (controllers on $4016/$4017)
  * $4020-$FFFF     =      Cartridge Space and Misc (Interrupt Vectors).
  *---------------------------------------------------------------------
  */
@@ -15,9 +18,9 @@ pub use crate::core::*;
 pub struct MEM {
     RAM:	[u8; 0x800],        //2kb internal RAM.
     pub CART:   Box<MAP>,    //Cartridge Space
-    PPU:    u8,         //TODO
-    APU:    u8,         //TODO
-    INPUT:  u8,         //TODO
+    pub PPU:    PPU,
+    pub APU:    APU,
+    pub INPUT:  [Controller; 2],
 
 }
 
@@ -29,14 +32,13 @@ impl MEM {
         return MEM {
             RAM:	    [0; 0x800],
             CART:	    Box::new(EMPTY_MAP),
-            PPU:        0,
-            APU:        0,
-            INPUT:      0,
+            PPU:        PPU::new(),
+            APU:        APU::new(),
+            INPUT:      [Controller::new(); 2],
         }
     }
     //Initializes the full memory map of the NES.
-    // TODO: PPU, APU, and INPUT registers are unimplemented!
-    pub fn new(mapper: Box<MAP>, ppu: u8, apu: u8, input: u8) -> MEM {
+    pub fn new(mapper: Box<dyn MAP>, ppu: PPU, apu: APU, input: [Controller; 2]) -> MEM {
         return MEM {
             RAM:	    [0; 0x800],
             CART:	    mapper,
@@ -45,22 +47,93 @@ impl MEM {
             INPUT:      input,
         }
     }
-    
+
+    /// Fills RAM and the PPU from the power-on policy, puts the APU in its
+    ///  fixed power-up state, then lets the cart know.
+    pub fn power_on(&mut self, src: &mut PowerSource) {
+        src.fill(&mut self.RAM);
+        self.PPU.power_on(src);
+        self.APU.power_on();
+        self.CART.power(src);
+    }
+    /// The reset button, as far as the rest of the bus is concerned.
+    pub fn reset(&mut self) {
+        self.CART.reset();
+    }
+
+    /// One CPU cycle for everything else on the bus: the DMC gets its
+    ///  sample byte if it wants one, then the APU and cart tick.
+    pub fn clock(&mut self) {
+        if let Some(address) = self.APU.dmc_request() {
+            let byte = self.CART.get(address);
+            self.APU.dmc_fill(byte);
+        }
+        self.APU.clock();
+        self.CART.clock();
+    }
+
+    /// The system mix: the APU, plus whatever the cart adds.
+    pub fn output(&self) -> f32 {
+        self.APU.output() + self.CART.audio()
+    }
+
+    /// True while anything is pulling /IRQ low.
+    pub fn irq(&self) -> bool {
+        self.APU.irq() || self.CART.irq()
+    }
 
     //Obtains values from full memory map.
-    pub fn get(&self, address: u16) -> u8 {
-        if address <= 0x800{
-            //2kb internal ram
-            self.RAM[address as usize]
+    pub fn get(&mut self, address: u16) -> u8 {
+        if address < 0x2000 {
+            //2kb internal ram, mirrored four times.
+            self.RAM[(address & 0x7FF) as usize]
+        }
+        else if address < 0x4000 {
+            self.PPU.read_register(address, &mut *self.CART)
+        }
+        else if address == 0x4015 {
+            self.APU.read_register(address)
+        }
+        else if address == 0x4016 || address == 0x4017 {
+            self.INPUT[(address - 0x4016) as usize].read()
         }
         else if address >= 0x4020 {
             self.CART.get(address) 
         }
         else {
-            panic!("Other values in the memory map not implemented yet!");
+            0 //Open bus.
+        }
+    }
+
+    /// Returns what a read of the address would, without any of the side
+    ///  effects (VBlank clearing, PPUDATA buffering, controller shifting...).
+    /// Debuggers, RAM watches and memory viewers should go through here.
+    pub fn peek(&self, address: u16) -> u8 {
+        if address < 0x2000 {
+            self.RAM[(address & 0x7FF) as usize]
+        }
+        else if address < 0x4000 {
+            self.PPU.peek_register(address, &*self.CART)
+        }
+        else if address == 0x4015 {
+            self.APU.peek_register(address)
+        }
+        else if address == 0x4016 || address == 0x4017 {
+            self.INPUT[(address - 0x4016) as usize].peek()
+        }
+        else if address >= 0x4020 {
+            self.CART.peek(address)
+        }
+        else {
+            0
         }
     }
 
+    /// Peeks a block of memory, wrapping at $FFFF. For memory viewers.
+    pub fn peek_range(&self, start: u16, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.peek(start.wrapping_add(i as u16))).collect()
+    }
+
     //Much faster, only has to access the first page of memory.
     pub fn get_zp(&self, address: u8) -> u8 {
         let zp = address & 255;
@@ -69,17 +142,34 @@ impl MEM {
 
     // block any illegal storing.
     pub fn set(&mut self, address: u16, val: u8){
-        if address <= 0x800 {
-            //2kb internal ram
-            self.RAM[address as usize] = val;
+        if address < 0x2000 {
+            //2kb internal ram, mirrored four times.
+            self.RAM[(address & 0x7FF) as usize] = val;
+        }
+        else if address < 0x4000 {
+            self.PPU.write_register(address, val, &mut *self.CART);
+            //The cart edge sees every write, MMC5 snoops PPUCTRL/PPUMASK.
+            self.CART.set(address, val);
+        }
+        else if address == 0x4014 {
+            //OAMDMA, copies page $XX00-$XXFF into OAM through OAMDATA.
+            for i in 0..256 {
+                let byte = self.get(((val as u16) << 8) | i);
+                self.PPU.write_register(0x2004, byte, &mut *self.CART);
+            }
+        }
+        else if address == 0x4016 {
+            //One strobe line runs to both ports.
+            self.INPUT[0].write(val);
+            self.INPUT[1].write(val);
+        }
+        else if address < 0x4018 {
+            self.APU.write_register(address, val);
         }
         else if address >= 0x4020 {
             //~6kb Cartridge space.
             self.CART.set(address, val);
         }
-        else {
-            panic!("Other values in the memory map not implemented yet!");
-        }
     }
     //Sets a value in the zero page.
     //Much faster, only has to access the first page of memory.
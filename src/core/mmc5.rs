@@ -0,0 +1,546 @@
+/* Nintendo's MMC5 (ExROM), mapper #5.
+ * The most capable licensed board: four PRG modes mixing ROM and RAM,
+ *  four CHR modes with separate sprite/background sets for 8x16 sprites,
+ *  1kb of ExRAM, a fill-mode nametable, a vertical split, a scanline IRQ,
+ *  a multiplier, and two extra pulses with an 8-bit PCM channel.
+ * It finds scanlines by watching the PPU's fetches, and snoops PPUCTRL and
+ *  PPUMASK writes to know the sprite size and whether it's rendering.
+ * Author: Spalynx
+ *--------------Registers-----------------------------------------------
+ * $5000-$5015  Audio: pulses at $5000/$5004, PCM $5010/$5011, $5015 status.
+ * $5100        PRG mode: 32kb, 16kb+16kb, 16kb+8kb+8kb, 8kb x4.
+ * $5101        CHR mode: 8kb, 4kb, 2kb, 1kb.
+ * $5102-$5103  PRG-RAM protect, writes need $02 and $01.
+ * $5104        ExRAM mode: nametable, extended attributes, RAM, ROM.
+ * $5105        Nametable mapping, 2 bits each: CIRAM 0/1, ExRAM, fill.
+ * $5106-$5107  Fill mode tile and attribute.
+ * $5113-$5117  PRG banks: $6000 RAM, then $8000-$E000. Bit 7 ROM/RAM.
+ * $5120-$512B  CHR banks: A (sprites, everything in 8x8), B (8x16 BGs).
+ * $5130        Upper CHR bank bits.
+ * $5200-$5202  Vertical split: control, scroll, CHR bank.
+ * $5203-$5204  Scanline IRQ: target, enable/status.
+ * $5205-$5206  8x8 multiplier.
+ * $5C00-$5FFF  ExRAM.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/MMC5
+ * http://wiki.nesdev.com/w/index.php/MMC5_audio
+ */
+
+use std::io;
+use crate::core::*;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./mmc5_test.rs"]
+pub mod mmc5_test;
+
+//Quarter and half frames both come at a fixed ~240Hz.
+const FRAME_CYCLES: u32 = 7457;
+
+//~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// Two copies of the APU's pulses, less the sweep, and a raw PCM channel
+///  that can be fed from $5011 or from PRG reads.
+#[derive(Clone, Default)]
+pub struct Mmc5Audio {
+    pub pulse:          [Pulse; 2],
+    pub pcm:            u8,
+    pub pcm_read:       bool,   //$5010 bit 0, PCM from reads of $8000-$BFFF.
+    pub pcm_irq_enabled: bool,
+    pub pcm_irq:        bool,
+    frame:              u32,
+    cycle:              u64,
+}
+
+impl Mmc5Audio {
+    pub fn new() -> Mmc5Audio {
+        let mut audio = Mmc5Audio::default();
+        audio.pulse[0].sweepless = true;
+        audio.pulse[1].sweepless = true;
+        audio
+    }
+
+    /// $5010 and $5015, what read() would return.
+    pub fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x5010 => ((self.pcm_irq as u8) << 7) | self.pcm_read as u8,
+            0x5015 => ((self.pulse[1].length > 0) as u8) << 1 | (self.pulse[0].length > 0) as u8,
+            _ => 0,
+        }
+    }
+    /// Reading $5010 acknowledges the PCM IRQ.
+    pub fn read(&mut self, address: u16) -> u8 {
+        let val = self.peek(address);
+        if address == 0x5010 {
+            self.pcm_irq = false;
+        }
+        val
+    }
+
+    pub fn write(&mut self, address: u16, val: u8) {
+        match address {
+            0x5001 | 0x5005 => {},      //No sweep units.
+            0x5000..=0x5003 => self.pulse[0].write(address & 3, val),
+            0x5004..=0x5007 => self.pulse[1].write(address & 3, val),
+            0x5010 => {
+                self.pcm_read        = val & 0x01 != 0;
+                self.pcm_irq_enabled = val & 0x80 != 0;
+            },
+            //A 0 can't be written, it's what signals the IRQ in read mode.
+            0x5011 if !self.pcm_read && val != 0 => self.pcm = val,
+            0x5015 => {
+                for (i, pulse) in self.pulse.iter_mut().enumerate() {
+                    pulse.enabled = val & (1 << i) != 0;
+                    if !pulse.enabled {
+                        pulse.length = 0;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// A CPU read of $8000-$BFFF, which the PCM samples in read mode.
+    pub fn pcm_fetch(&mut self, val: u8) {
+        if !self.pcm_read {
+            return;
+        }
+        if val == 0 {
+            self.pcm_irq = self.pcm_irq_enabled;
+        }
+        else {
+            self.pcm = val;
+        }
+    }
+
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle & 1 == 0 {
+            self.pulse[0].clock();
+            self.pulse[1].clock();
+        }
+        self.frame += 1;
+        if self.frame == FRAME_CYCLES {
+            self.frame = 0;
+            for pulse in self.pulse.iter_mut() {
+                pulse.envelope.quarter();
+                pulse.half();
+            }
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.pcm_irq
+    }
+
+    /// Mixed the way the APU mixes its own pulses, with the PCM at about
+    ///  the DMC's level. 0.0 to ~0.6.
+    pub fn output(&self) -> f32 {
+        let p = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let pulse = if p == 0.0 { 0.0 } else { 95.88 / (8128.0 / p + 100.0) };
+        let t = self.pcm as f32 / 2.0 / 22638.0;
+        let pcm = if t == 0.0 { 0.0 } else { 159.79 / (1.0 / t + 100.0) };
+        pulse + pcm
+    }
+}
+
+//~~~MAPPER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// Where the MMC5 is within a scanline, counted in nametable fetches since
+///  it last spotted a new one. The PPU has already fetched tiles 0-1 at the
+///  end of the line before, so the line starts at tile 2:
+///  0-29 tiles 2-31, 30-45 sprites, 46-47 next line's tiles 0-1, 48+ dummies.
+const FETCH_SPRITES: u8 = 30;
+const FETCH_PREFETCH: u8 = 46;
+const FETCH_DUMMY: u8 = 48;
+
+#[allow(non_snake_case)]
+pub struct Mmc5 {
+    pub cart: Box<CART>,
+    pub EXRAM:          [u8; 1024],
+    pub prg_mode:       u8,         //$5100
+    pub chr_mode:       u8,         //$5101
+    pub ram_protect:    [u8; 2],    //$5102, $5103
+    pub exram_mode:     u8,         //$5104
+    pub nametables:     u8,         //$5105
+    pub fill_tile:      u8,         //$5106
+    pub fill_attr:      u8,         //$5107
+    pub prg_banks:      [u8; 5],    //$5113-$5117
+    pub chr_a:          [u16; 8],   //$5120-$5127
+    pub chr_b:          [u16; 4],   //$5128-$512B
+    pub chr_upper:      u8,         //$5130
+    pub chr_b_last:     bool,       //Which set was written last.
+    pub split_ctrl:     u8,         //$5200
+    pub split_scroll:   u8,         //$5201
+    pub split_bank:     u8,         //$5202
+    pub irq_target:     u8,         //$5203
+    pub irq_enabled:    bool,
+    pub irq_pending:    bool,
+    pub in_frame:       bool,
+    pub scanline:       u8,
+    pub multiplicand:   u8,         //$5205
+    pub multiplier:     u8,         //$5206
+    pub sprites_8x16:   bool,       //Snooped from PPUCTRL.
+    pub rendering:      bool,       //Snooped from PPUMASK.
+    pub audio:          Mmc5Audio,
+    last_read:          u16,        //Scanline detection: the same nametable
+    repeats:            u8,         // address read three times in a row.
+    fetches:            u8,         //Nametable fetches this line.
+    idle:               u8,         //CPU cycles since the PPU last read.
+    ex_tile:            u8,         //ExRAM byte for the tile being fetched.
+    split_line:         Option<u16>,//Split Y, if the tile is in the split.
+}
+
+impl Mmc5 {
+    pub fn new(cart: Box<CART>) -> Mmc5 {
+        Mmc5 {
+            cart,
+            EXRAM:          [0; 1024],
+            prg_mode:       3,
+            chr_mode:       0,
+            ram_protect:    [0; 2],
+            exram_mode:     0,
+            nametables:     0,
+            fill_tile:      0,
+            fill_attr:      0,
+            prg_banks:      [0, 0, 0, 0, 0xFF],
+            chr_a:          [0; 8],
+            chr_b:          [0; 4],
+            chr_upper:      0,
+            chr_b_last:     false,
+            split_ctrl:     0,
+            split_scroll:   0,
+            split_bank:     0,
+            irq_target:     0,
+            irq_enabled:    false,
+            irq_pending:    false,
+            in_frame:       false,
+            scanline:       0,
+            multiplicand:   0xFF,
+            multiplier:     0xFF,
+            sprites_8x16:   false,
+            rendering:      false,
+            audio:          Mmc5Audio::new(),
+            last_read:      0,
+            repeats:        0,
+            fetches:        0,
+            idle:           0,
+            ex_tile:        0,
+            split_line:     None,
+        }
+    }
+
+    /// Which register banks a $8000-$FFFF address, as (ROM?, byte index).
+    fn prg_target(&self, address: u16) -> (bool, usize) {
+        let slot = ((address - 0x8000) >> 13) as usize;
+        //Register index into prg_banks, and the window size in 8kb.
+        let (reg, size) = match self.prg_mode & 3 {
+            0 => (4, 4),
+            1 => (if slot < 2 { 2 } else { 4 }, 2),
+            2 => ([2, 2, 3, 4][slot], if slot < 2 { 2 } else { 1 }),
+            _ => (slot + 1, 1),
+        };
+        let val = self.prg_banks[reg];
+        //$5117 is always ROM.
+        let rom = reg == 4 || val & 0x80 != 0;
+        let bank = (val & 0x7F) as usize & !(size - 1);
+        (rom, bank * 0x2000 + (address as usize & (size * 0x2000 - 1)))
+    }
+    /// RAM index from an 8kb bank and offset, RAM banks are 3 bits.
+    fn ram_index(&self, index: usize) -> usize {
+        let bank = (index >> 13) & 7;
+        (bank * 0x2000 + (index & 0x1FFF)) % self.cart.PRG_RAM.len()
+    }
+    fn ram_writable(&self) -> bool {
+        self.ram_protect == [2, 1] && !self.cart.PRG_RAM.is_empty()
+    }
+
+    /// Set A is everything in 8x8 mode, and sprites in 8x16. Set B is 8x16
+    ///  backgrounds. Outside of rendering, whichever was written last.
+    fn use_set_b(&self) -> bool {
+        if self.in_frame && self.rendering {
+            self.sprites_8x16 && !self.sprite_fetch()
+        }
+        else {
+            self.chr_b_last
+        }
+    }
+    fn sprite_fetch(&self) -> bool {
+        (FETCH_SPRITES..FETCH_PREFETCH).contains(&self.fetches)
+    }
+    fn chr_index(&self, address: u16) -> usize {
+        let a = address as usize & 0x1FFF;
+        let (bank, size) = if self.use_set_b() {
+            match self.chr_mode & 3 {
+                0 => (self.chr_b[3], 8),
+                1 => (self.chr_b[3], 4),
+                2 => (self.chr_b[[1, 3][(a >> 11) & 1]], 2),
+                _ => (self.chr_b[(a >> 10) & 3], 1),
+            }
+        }
+        else {
+            match self.chr_mode & 3 {
+                0 => (self.chr_a[7], 8),
+                1 => (self.chr_a[if a < 0x1000 { 3 } else { 7 }], 4),
+                2 => (self.chr_a[[1, 3, 5, 7][a >> 11]], 2),
+                _ => (self.chr_a[a >> 10], 1),
+            }
+        };
+        let size = size * 0x400;
+        bank as usize * size + (a & (size - 1))
+    }
+
+    /// Spotted the start of a scanline.
+    fn new_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_target {
+                self.irq_pending = true;
+            }
+        }
+        else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.fetches = 0;
+    }
+    /// Every PPU read goes through here, for the scanline detector.
+    fn ppu_read(&mut self, address: u16) -> bool {
+        self.idle = 0;
+        if address == self.last_read {
+            self.repeats += 1;
+        }
+        else {
+            self.last_read = address;
+            self.repeats = 0;
+        }
+        self.repeats == 2
+    }
+
+    /// Where the split region's tile column and line come from, if the
+    ///  tile being fetched is inside it.
+    fn split_tile(&self) -> Option<(u16, u16)> {
+        if self.split_ctrl & 0x80 == 0 || self.exram_mode > 1 || !self.in_frame {
+            return None;
+        }
+        let (column, line) = match self.fetches {
+            n if n < FETCH_SPRITES => (n as u16 + 2, self.scanline as u16),
+            n if (FETCH_PREFETCH..FETCH_DUMMY).contains(&n) =>
+                ((n - FETCH_PREFETCH) as u16, self.scanline as u16 + 1),
+            _ => return None,
+        };
+        let edge = (self.split_ctrl & 0x1F) as u16;
+        let inside = if self.split_ctrl & 0x40 != 0 { column >= edge } else { column < edge };
+        if inside {
+            Some((column, (self.split_scroll as u16 + line) % 240))
+        } else {
+            None
+        }
+    }
+}
+
+impl MAP for Mmc5 {
+    fn get(&mut self, address: u16) -> u8 {
+        let val = self.peek(address);
+        match address {
+            0x5010 => return self.audio.read(address),
+            0x5204 => self.irq_pending = false,
+            0x8000..=0xBFFF => self.audio.pcm_fetch(val),
+            //The NMI vector being fetched means VBlank.
+            0xFFFA | 0xFFFB => self.in_frame = false,
+            _ => {},
+        }
+        val
+    }
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x5010 | 0x5015 => self.audio.peek(address),
+            0x5204 => ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6),
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.EXRAM[address as usize - 0x5C00],
+            0x6000..=0x7FFF if !self.cart.PRG_RAM.is_empty() => {
+                let index = (self.prg_banks[0] as usize & 7) * 0x2000 + (address as usize & 0x1FFF);
+                self.cart.PRG_RAM[self.ram_index(index)]
+            },
+            0x8000..=0xFFFF => {
+                let (rom, index) = self.prg_target(address);
+                if rom {
+                    prg_read(&self.cart, index)
+                }
+                else if !rom && !self.cart.PRG_RAM.is_empty() {
+                    self.cart.PRG_RAM[self.ram_index(index)]
+                }
+                else {
+                    0
+                }
+            },
+            _ => 0, //Open bus, for now.
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        match address {
+            //Snooped off the CPU bus.
+            0x2000 => self.sprites_8x16 = val & 0x20 != 0,
+            0x2001 => {
+                self.rendering = val & 0x18 != 0;
+                if !self.rendering {
+                    self.in_frame = false;
+                }
+            },
+            0x5000..=0x5015 => self.audio.write(address, val),
+            0x5100 => self.prg_mode = val & 3,
+            0x5101 => self.chr_mode = val & 3,
+            0x5102 => self.ram_protect[0] = val & 3,
+            0x5103 => self.ram_protect[1] = val & 3,
+            0x5104 => self.exram_mode = val & 3,
+            0x5105 => self.nametables = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attr = val & 3,
+            0x5113..=0x5117 => self.prg_banks[(address - 0x5113) as usize] = val,
+            0x5120..=0x5127 => {
+                self.chr_a[(address - 0x5120) as usize] = val as u16 | (self.chr_upper as u16) << 8;
+                self.chr_b_last = false;
+            },
+            0x5128..=0x512B => {
+                self.chr_b[(address - 0x5128) as usize] = val as u16 | (self.chr_upper as u16) << 8;
+                self.chr_b_last = true;
+            },
+            0x5130 => self.chr_upper = val & 3,
+            0x5200 => self.split_ctrl = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_target = val,
+            0x5204 => self.irq_enabled = val & 0x80 != 0,
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            //Nametable modes only take writes while rendering, 0 otherwise.
+            0x5C00..=0x5FFF => match self.exram_mode {
+                0 | 1 => self.EXRAM[address as usize - 0x5C00] = if self.in_frame { val } else { 0 },
+                2 => self.EXRAM[address as usize - 0x5C00] = val,
+                _ => {},
+            },
+            0x6000..=0x7FFF if self.ram_writable() => {
+                let index = (self.prg_banks[0] as usize & 7) * 0x2000 + (address as usize & 0x1FFF);
+                let index = self.ram_index(index);
+                self.cart.PRG_RAM[index] = val;
+            },
+            0x8000..=0xFFFF => {
+                let (rom, index) = self.prg_target(address);
+                if !rom && self.ram_writable() {
+                    let index = self.ram_index(index);
+                    self.cart.PRG_RAM[index] = val;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, self.chr_index(address))
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn fetch_chr(&mut self, address: u16) -> u8 {
+        self.ppu_read(address);
+        let background = !self.sprite_fetch();
+        let fine = address as usize & 0xFFF;
+        if let (Some(line), true) = (self.split_line, background) {
+            //The split has its own 4kb bank, and its own fine Y.
+            let offset = (fine & 0xFF8) | (line as usize & 7);
+            chr_read(&self.cart, self.split_bank as usize * 0x1000 + offset)
+        }
+        else if self.exram_mode == 1 && background && self.in_frame {
+            //Extended attributes: 4kb bank from the tile's ExRAM byte.
+            let bank = (self.ex_tile & 0x3F) as usize | (self.chr_upper as usize) << 6;
+            chr_read(&self.cart, bank * 0x1000 + fine)
+        }
+        else {
+            self.get_chr(address)
+        }
+    }
+
+    fn nametable(&self, address: u16) -> Option<u8> {
+        let offset = address as usize & 0x3FF;
+        let table = (address >> 10) & 3;
+        match (self.nametables >> (table * 2)) & 3 {
+            2 => Some(if self.exram_mode <= 1 { self.EXRAM[offset] } else { 0 }),
+            3 => Some(if offset >= 0x3C0 { self.fill_attr * 0x55 } else { self.fill_tile }),
+            _ => None,  //CIRAM, through mirroring().
+        }
+    }
+    fn fetch_nametable(&mut self, address: u16) -> Option<u8> {
+        let offset = address as usize & 0x3FF;
+        let attribute = offset >= 0x3C0;
+        let scanline = self.ppu_read(address);
+        if !attribute {
+            if scanline {
+                self.new_scanline();
+            }
+            else {
+                self.fetches = self.fetches.saturating_add(1);
+            }
+            self.split_line = self.split_tile().map(|(_, line)| line);
+            self.ex_tile = self.EXRAM[offset];
+        }
+
+        let background = !self.sprite_fetch() && self.fetches < FETCH_DUMMY;
+        if let (Some((column, line)), true) = (self.split_tile(), background) {
+            let row = (line / 8) as usize;
+            let column = column as usize;
+            return Some(if attribute {
+                let at = self.EXRAM[0x3C0 + (row / 4) * 8 + column / 4];
+                let shift = ((row & 2) << 1) | (column & 2);
+                ((at >> shift) & 3) * 0x55
+            }
+            else {
+                self.EXRAM[row * 32 + column]
+            });
+        }
+        if attribute && self.exram_mode == 1 && self.in_frame {
+            return Some((self.ex_tile >> 6) * 0x55);
+        }
+        self.nametable(address)
+    }
+    fn set_nametable(&mut self, address: u16, val: u8) -> bool {
+        let table = (address >> 10) & 3;
+        match (self.nametables >> (table * 2)) & 3 {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.EXRAM[address as usize & 0x3FF] = val;
+                }
+                true
+            },
+            3 => true,
+            _ => false,
+        }
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        let page = |table: u8| (self.nametables >> (table * 2)) & 1;
+        Some(Mirroring::Mapped([page(0), page(1), page(2), page(3)]))
+    }
+
+    fn clock(&mut self) {
+        self.audio.clock();
+        //No PPU reads for a few cycles: rendering has stopped.
+        self.idle = self.idle.saturating_add(1);
+        if self.idle >= 3 {
+            self.in_frame = false;
+        }
+    }
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || self.audio.irq()
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Mmc5::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
@@ -0,0 +1,358 @@
+/*  Unit test module of the MMC5 (mmc5.rs).
+ *  The PPU's side is played by hand: render_line() makes the same reads,
+ *   in the same order, that a rendered scanline would.
+ */
+use crate::core::mmc5::*;
+
+#[cfg(test)]
+pub mod mmc5_test {
+    use super::*;
+    use crate::core::mapper::*;
+    use crate::core::ppu::Mirroring;
+
+    /// 128kb PRG in 8kb banks and 128kb CHR in 1kb banks, each filled with
+    ///  its bank number, and 32kb of PRG-RAM.
+    fn cart() -> Box<CART> {
+        let mut cart = banked_cart(5, 0, 0x2000, 0x400, 16);
+        cart.PRG_RAM = vec![0; 0x8000];
+        Box::new(cart)
+    }
+
+    /// What the MMC5 answered for one background tile.
+    #[derive(Debug, PartialEq)]
+    struct Tile {
+        column: u16,
+        name: Option<u8>,
+        attribute: Option<u8>,
+        pattern: u8,
+    }
+
+    fn fetch_tile(map: &mut Mmc5, y: u16, column: u16) -> Tile {
+        let row = (y / 8) % 30;
+        let name = map.fetch_nametable(0x2000 + row * 32 + column);
+        let attribute = map.fetch_nametable(0x23C0 + (row / 4) * 8 + column / 4);
+        //Tiles from CIRAM are numbered by column.
+        let tile = name.unwrap_or(column as u8) as u16;
+        let pattern = map.fetch_chr(tile * 16 + (y & 7));
+        map.fetch_chr(tile * 16 + 8 + (y & 7));
+        Tile { column, name, attribute, pattern }
+    }
+
+    /// One scanline: tiles 2-31 of line y, eight sprites, tiles 0-1 of the
+    ///  next line, then the two dummy reads. Returns the background tiles
+    ///  and the sprite pattern bytes.
+    fn render_line(map: &mut Mmc5, y: u16) -> (Vec<Tile>, Vec<u8>) {
+        let next = y.wrapping_add(1);
+        let mut tiles: Vec<Tile> = (2..32).map(|column| fetch_tile(map, y, column)).collect();
+        let mut sprites = Vec::new();
+        for _ in 0..8 {
+            map.fetch_nametable(0x2000);
+            map.fetch_nametable(0x2000);
+            sprites.push(map.fetch_chr(0x1000));
+            map.fetch_chr(0x1008);
+        }
+        tiles.push(fetch_tile(map, next, 0));
+        tiles.push(fetch_tile(map, next, 1));
+        let dummy = 0x2000 + ((next / 8) % 30) * 32 + 2;
+        map.fetch_nametable(dummy);
+        map.fetch_nametable(dummy);
+        (tiles, sprites)
+    }
+
+    /// The pre-render line, after which line 0 is spotted.
+    fn prerender(map: &mut Mmc5) {
+        render_line(map, 0xFFFF);
+    }
+
+    //~~~PRG~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_mmc5_prg_modes(){
+        let mut map = Mmc5::new(cart());
+        //Powers on in 8kb mode with the last bank at $E000.
+        assert_eq!(map.peek(0xE000), 15);
+        map.set(0x5114, 0x83);
+        map.set(0x5115, 0x84);
+        map.set(0x5116, 0x85);
+        assert_eq!((map.peek(0x8000), map.peek(0xA000), map.peek(0xC000)), (3, 4, 5));
+
+        //32kb, low bits ignored.
+        map.set(0x5100, 0);
+        map.set(0x5117, 0x85);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![4, 5, 6, 7]);
+
+        //16kb + 16kb.
+        map.set(0x5100, 1);
+        map.set(0x5115, 0x83);
+        map.set(0x5117, 0x87);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![2, 3, 6, 7]);
+
+        //16kb + 8kb + 8kb.
+        map.set(0x5100, 2);
+        map.set(0x5115, 0x82);
+        map.set(0x5116, 0x89);
+        map.set(0x5117, 0x8A);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![2, 3, 9, 10]);
+    }
+
+    #[test]
+    fn test_mmc5_prg_ram(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5113, 1);
+        map.set(0x5114, 0x01);      //RAM bank 1 at $8000 too.
+
+        //Locked until $5102/$5103 hold $02/$01.
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0);
+        map.set(0x5102, 2);
+        map.set(0x5103, 1);
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0x42);
+        assert_eq!(map.peek(0x8000), 0x42);
+        map.set(0x8001, 0x43);
+        assert_eq!(map.peek(0x6001), 0x43);
+
+        //Other RAM banks are separate.
+        map.set(0x5113, 2);
+        assert_eq!(map.peek(0x6000), 0);
+
+        //$E000 is always ROM.
+        map.set(0x5117, 0x01);
+        assert_eq!(map.peek(0xE000), 1);
+        map.set(0x5103, 0);
+        map.set(0x8000, 0x11);
+        assert_eq!(map.peek(0x8000), 0x42);
+    }
+
+    //~~~CHR~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_mmc5_chr(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5101, 3);
+        for i in 0..8 {
+            map.set(0x5120 + i, 10 + i as u8);
+        }
+        for i in 0..4 {
+            map.set(0x5128 + i, 20 + i as u8);
+        }
+        //Not rendering, the set written last is used.
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x1C00)), (20, 23));
+        map.set(0x5120, 10);
+        assert_eq!((map.get_chr(0x0400), map.get_chr(0x1C00)), (11, 17));
+
+        //4kb mode takes $5123/$5127.
+        map.set(0x5101, 1);
+        assert_eq!((map.get_chr(0x0000), map.get_chr(0x1000)), (13 * 4, 17 * 4));
+
+        //$5130 supplies the upper bits as banks are written.
+        map.set(0x5130, 1);
+        map.set(0x5127, 2);
+        assert_eq!(map.chr_a[7], 0x102);
+    }
+
+    #[test]
+    fn test_mmc5_chr_sets(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5101, 3);
+        for i in 0..8 {
+            map.set(0x5120 + i, 10 + i as u8);
+        }
+        for i in 0..4 {
+            map.set(0x5128 + i, 20 + i as u8);
+        }
+        map.set(0x2001, 0x18);
+
+        //8x16 sprites: backgrounds from B, sprites from A.
+        map.set(0x2000, 0x20);
+        prerender(&mut map);
+        let (tiles, sprites) = render_line(&mut map, 0);
+        assert!(tiles.iter().all(|t| t.pattern == 20));
+        assert!(sprites.iter().all(|&p| p == 14));
+
+        //8x8 sprites: A for everything.
+        map.set(0x2000, 0x00);
+        let (tiles, sprites) = render_line(&mut map, 1);
+        assert!(tiles.iter().all(|t| t.pattern == 10));
+        assert!(sprites.iter().all(|&p| p == 14));
+    }
+
+    //~~~EXRAM~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_mmc5_exram(){
+        let mut map = Mmc5::new(cart());
+        //Nametable mode: 0 is written outside of rendering, and no reads.
+        map.EXRAM[0] = 0x99;
+        map.set(0x5C00, 5);
+        assert_eq!((map.EXRAM[0], map.peek(0x5C00)), (0, 0));
+
+        map.set(0x5104, 2);
+        map.set(0x5C00, 5);
+        assert_eq!(map.peek(0x5C00), 5);
+
+        //Read-only.
+        map.set(0x5104, 3);
+        map.set(0x5C00, 6);
+        assert_eq!(map.peek(0x5C00), 5);
+    }
+
+    #[test]
+    fn test_mmc5_nametables(){
+        let mut map = Mmc5::new(cart());
+        //CIRAM A, CIRAM B, ExRAM, fill.
+        map.set(0x5105, 0b11_10_01_00);
+        assert_eq!(map.mirroring(), Some(Mirroring::Mapped([0, 1, 0, 1])));
+        assert_eq!((map.nametable(0x2000), map.nametable(0x2400)), (None, None));
+
+        map.EXRAM[3] = 0x33;
+        assert_eq!(map.nametable(0x2803), Some(0x33));
+        assert!(map.set_nametable(0x2801, 9));
+        assert_eq!(map.EXRAM[1], 9);
+        assert!(!map.set_nametable(0x2001, 9));
+        //Not a nametable in the RAM modes.
+        map.set(0x5104, 2);
+        assert_eq!(map.nametable(0x2803), Some(0));
+
+        map.set(0x5106, 0x77);
+        map.set(0x5107, 2);
+        assert_eq!(map.nametable(0x2C00), Some(0x77));
+        assert_eq!(map.nametable(0x2FC0), Some(0xAA));
+    }
+
+    #[test]
+    fn test_mmc5_extended_attributes(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5104, 2);
+        map.set(0x5C02, 0xC5);      //Palette 3, 4kb bank 5.
+        map.set(0x5104, 1);
+        map.set(0x2001, 0x18);
+        prerender(&mut map);
+        let (tiles, _) = render_line(&mut map, 0);
+        assert_eq!(tiles[0], Tile { column: 2, name: None, attribute: Some(0xFF), pattern: 20 });
+        assert_eq!(tiles[1], Tile { column: 3, name: None, attribute: Some(0x00), pattern: 0 });
+    }
+
+    #[test]
+    fn test_mmc5_split(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5104, 2);
+        for column in 0..64 {
+            map.set(0x5C00 + column, 0x40 + column as u8);
+        }
+        map.set(0x5FC0, 0b1100);    //Columns 2-3 of the top rows, palette 3.
+        map.set(0x5104, 0);
+        map.set(0x2001, 0x18);
+
+        //Left four tiles, from 4kb bank 2.
+        map.set(0x5200, 0x80 | 4);
+        map.set(0x5202, 2);
+        prerender(&mut map);
+        let (tiles, _) = render_line(&mut map, 0);
+        assert_eq!(tiles[0], Tile { column: 2, name: Some(0x42), attribute: Some(0xFF), pattern: 9 });
+        assert_eq!(tiles[1].name, Some(0x43));
+        assert_eq!(tiles[2], Tile { column: 4, name: None, attribute: None, pattern: 0 });
+        //Next line's prefetched tiles.
+        assert_eq!((tiles[30].column, tiles[30].name), (0, Some(0x40)));
+
+        //Right side, scrolled down a row.
+        map.set(0x5200, 0xC0 | 30);
+        map.set(0x5201, 8);
+        let (tiles, _) = render_line(&mut map, 1);
+        assert_eq!(tiles[0].name, None);
+        assert_eq!((tiles[28].column, tiles[28].name), (30, Some(0x40 + 32 + 30)));
+        assert_eq!(tiles[30].name, None);
+    }
+
+    //~~~IRQ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_mmc5_scanline_irq(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5203, 2);
+        map.set(0x5204, 0x80);
+        map.set(0x2001, 0x18);
+        prerender(&mut map);
+        assert_eq!(map.peek(0x5204), 0x00);
+        render_line(&mut map, 0);
+        assert_eq!(map.peek(0x5204), 0x40);
+        render_line(&mut map, 1);
+        assert!(!map.irq());
+        render_line(&mut map, 2);
+        assert!(map.irq());
+        assert_eq!(map.scanline, 2);
+
+        //Reading $5204 acknowledges.
+        assert_eq!(map.get(0x5204), 0xC0);
+        assert!(!map.irq());
+
+        //The NMI vector fetch, or a quiet PPU, ends the frame.
+        map.get(0xFFFA);
+        assert!(!map.in_frame);
+        render_line(&mut map, 3);
+        assert!(map.in_frame);
+        for _ in 0..3 {
+            map.clock();
+        }
+        assert!(!map.in_frame);
+
+        //Disabled, but still pending.
+        map.set(0x5204, 0);
+        prerender(&mut map);
+        for y in 0..3 {
+            render_line(&mut map, y);
+        }
+        assert!(!map.irq());
+        assert_eq!(map.peek(0x5204), 0xC0);
+    }
+
+    #[test]
+    fn test_mmc5_multiplier(){
+        let mut map = Mmc5::new(cart());
+        assert_eq!((map.peek(0x5205), map.peek(0x5206)), (0x01, 0xFE));
+        map.set(0x5205, 200);
+        map.set(0x5206, 100);
+        assert_eq!((map.peek(0x5205), map.peek(0x5206)), (0x20, 0x4E));
+    }
+
+    //~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_mmc5_pulses(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5015, 0x01);
+        map.set(0x5000, 0xBF);      //Duty 2, halted, constant volume 15.
+        map.set(0x5001, 0x87);      //No sweep to enable.
+        map.set(0x5002, 0x03);      //A period the APU's would mute.
+        map.set(0x5003, 0x08);
+        assert_eq!(map.peek(0x5015), 0x01);
+
+        let mut loudest: f32 = 0.0;
+        for _ in 0..100 {
+            map.clock();
+            loudest = loudest.max(map.audio.output());
+        }
+        assert!(loudest > 0.0);
+        assert_eq!(map.audio.pulse[0].period, 3);
+
+        map.set(0x5015, 0x00);
+        assert_eq!(map.peek(0x5015), 0x00);
+    }
+
+    #[test]
+    fn test_mmc5_pcm(){
+        let mut map = Mmc5::new(cart());
+        map.set(0x5011, 0x80);
+        map.set(0x5011, 0x00);      //Ignored.
+        assert_eq!(map.audio.pcm, 0x80);
+        assert!(map.audio.output() > 0.0);
+
+        //Read mode, with $8000 as RAM holding 0 to raise the IRQ.
+        map.set(0x5010, 0x81);
+        assert_eq!(map.get(0x8000), 0);
+        assert!(map.irq());
+        assert_eq!(map.get(0x5010), 0x81);
+        assert!(!map.irq());
+        map.set(0x5114, 0x83);
+        map.get(0x8000);
+        assert_eq!(map.audio.pcm, 3);
+    }
+}
@@ -3,11 +3,45 @@ pub mod memory;
 pub mod nes;
 pub mod cartridge;
 pub mod mapper;
+pub mod ppu;
+pub mod apu;
+pub mod power;
+pub mod controller;
+pub mod validate;
+pub mod hash;
+pub mod romdb;
+pub mod patch;
+pub mod unif;
+pub mod fds;
+pub mod filter;
+pub mod nsf;
+pub mod mmc5;
+pub mod vrc6;
+pub mod vrc7;
+pub mod n163;
+pub mod registry;
 
 pub use crate::core::cartridge::*;
 pub use crate::core::cpu::*;
 pub use crate::core::memory::*;
 pub use crate::core::nes::*;
 pub use crate::core::mapper::*;
+pub use crate::core::ppu::*;
+pub use crate::core::apu::*;
+pub use crate::core::power::*;
+pub use crate::core::controller::*;
+pub use crate::core::validate::*;
+pub use crate::core::hash::*;
+pub use crate::core::romdb::*;
+pub use crate::core::patch::*;
+pub use crate::core::unif::*;
+pub use crate::core::fds::*;
+pub use crate::core::filter::*;
+pub use crate::core::nsf::*;
+pub use crate::core::mmc5::*;
+pub use crate::core::vrc6::*;
+pub use crate::core::vrc7::*;
+pub use crate::core::n163::*;
+pub use crate::core::registry::*;
 
 //A note to make: When casting u16 to usize, that means that as long as this is running on a 16-bit architecture, this should run? It is pointer sized...    Odd.
\ No newline at end of file
@@ -0,0 +1,310 @@
+/* Namco 163, mapper #19.
+ * 8kb PRG banks, 1kb CHR banks for the pattern tables and all four
+ *  nametables (any of which can be CIRAM instead), a 15 bit IRQ counter,
+ *  and a wavetable synth with 128 bytes of its own RAM.
+ * Nametables: the cart answers them all, keeping CIRAM here, so that it
+ *  can be banked into the pattern tables as well.
+ * Sound: up to eight channels, but one DAC. Every 15 CPU cycles the next
+ *  channel is updated and put out, so with more channels each is heard
+ *  less often, and the switching whine sits at 1.79MHz / (15 * channels).
+ *  That is what output() gives, one channel at a time, as the chip does.
+ * Author: Spalynx
+ *--------------Registers-----------------------------------------------
+ * $4800        Sound RAM data port.
+ * $5000-$5800  IRQ counter low, high (bits 0-6) and enable (bit 7).
+ * $8000-$B800  CHR banks for $0000-$1FFF. $E0-$FF is CIRAM (bit 0 the
+ *              page), unless $E800 bit 6/7 turns that off for the half.
+ * $C000-$D800  Nametables, $E0-$FF CIRAM, anything else CHR-ROM.
+ * $E000        PRG at $8000, bit 6 silences the sound.
+ * $E800        PRG at $A000, bits 6-7 CIRAM off in either pattern table.
+ * $F000        PRG at $C000. $E000 is the last bank.
+ * $F800        PRG-RAM write protect ($4x), and sound RAM address (bit 7
+ *              auto-increments).
+ *--------------Sound RAM, channel n at $40+8n-------------------------
+ * +0 +2 +4     Frequency (18 bits, top 2 in +4).
+ * +1 +3 +5     Phase (24 bits, 16 fractional).
+ * +4           Wave length, 256 - (bits 2-7) samples.
+ * +6           Wave address, in 4 bit samples, low nibble first.
+ * +7           Volume. $7F bits 4-6 are the channel count - 1.
+ *---------------------------------------------------------------------
+ * USED: Rolling Thunder, Megami Tensei II, Final Lap, King of Kings.
+ * http://wiki.nesdev.com/w/index.php/INES_Mapper_019
+ * http://wiki.nesdev.com/w/index.php/Namco_163_audio
+ */
+
+use std::io;
+use crate::core::*;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./n163_test.rs"]
+pub mod n163_test;
+
+/// CPU cycles per channel update.
+pub const N163_CYCLES: u8 = 15;
+
+//~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[allow(non_snake_case)]
+#[derive(Clone)]
+pub struct N163Audio {
+    pub RAM:        [u8; 128],
+    pub address:    u8,
+    pub increment:  bool,
+    pub disabled:   bool,
+    pub channel:    u8,     //The one on the DAC.
+    pub level:      i16,    //Its sample * volume, -120 to 105.
+    cycle:          u8,
+}
+impl Default for N163Audio {
+    fn default() -> N163Audio { N163Audio::new() }
+}
+impl N163Audio {
+    pub fn new() -> N163Audio {
+        N163Audio {
+            RAM:        [0; 128],
+            address:    0,
+            increment:  false,
+            disabled:   false,
+            channel:    0,      //So the first update is channel 7.
+            level:      0,
+            cycle:      0,
+        }
+    }
+
+    /// $F800's low 7 bits and auto-increment.
+    pub fn set_address(&mut self, val: u8) {
+        self.address   = val & 0x7F;
+        self.increment = val & 0x80 != 0;
+    }
+    pub fn peek(&self) -> u8 {
+        self.RAM[self.address as usize]
+    }
+    /// $4800 reads.
+    pub fn read(&mut self) -> u8 {
+        let val = self.peek();
+        self.step_address();
+        val
+    }
+    /// $4800 writes.
+    pub fn write(&mut self, val: u8) {
+        self.RAM[self.address as usize] = val;
+        self.step_address();
+    }
+    fn step_address(&mut self) {
+        if self.increment {
+            self.address = (self.address + 1) & 0x7F;
+        }
+    }
+
+    /// Channels 7 down to 8 - count are playing.
+    pub fn channels(&self) -> u8 {
+        ((self.RAM[0x7F] >> 4) & 7) + 1
+    }
+
+    /// Steps one channel's phase, and returns its level.
+    fn update(&mut self, channel: u8) -> i16 {
+        let base = 0x40 + channel as usize * 8;
+        let ram = &mut self.RAM;
+        let frequency = ram[base] as u32 | (ram[base + 2] as u32) << 8 | ((ram[base + 4] & 3) as u32) << 16;
+        let mut phase = ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = (256 - (ram[base + 4] & 0xFC) as u32) << 16;
+
+        phase = (phase + frequency) % length;
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        let sample = ((phase >> 16) as usize + ram[base + 6] as usize) & 0xFF;
+        let nibble = (ram[sample >> 1] >> ((sample & 1) * 4)) & 0x0F;
+        (nibble as i16 - 8) * (ram[base + 7] & 0x0F) as i16
+    }
+
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+        self.cycle += 1;
+        if self.cycle < N163_CYCLES {
+            return;
+        }
+        self.cycle = 0;
+        let last = 8 - self.channels();
+        self.channel = if self.channel <= last { 7 } else { self.channel - 1 };
+        self.level = self.update(self.channel);
+    }
+
+    /// Whichever channel is on the DAC. A channel at full volume swings
+    ///  about as far as one of the APU's pulses.
+    pub fn output(&self) -> f32 {
+        if self.disabled { 0.0 } else { self.level as f32 * 0.00125 }
+    }
+}
+
+//~~~MAPPER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[allow(non_snake_case)]
+pub struct N163 {
+    pub cart: Box<CART>,
+    pub CIRAM:          [u8; 2048],
+    pub chr_bank:       [u8; 12],   //Patterns 0-7, nametables 8-11.
+    pub prg_bank:       [u8; 3],
+    pub ciram_off:      [bool; 2],  //$E800 bits 6-7.
+    pub ram_protect:    u8,         //$F800
+    pub irq_counter:    u16,
+    pub irq_enabled:    bool,
+    pub irq_pending:    bool,
+    pub audio:          N163Audio,
+}
+
+impl N163 {
+    pub fn new(cart: Box<CART>) -> N163 {
+        let mut audio = N163Audio::new();
+        //The sound RAM is battery backed too, after PRG-RAM in the .sav.
+        if let Some(extra) = cart.sav_extra() {
+            let n = extra.len().min(128);
+            audio.RAM[..n].copy_from_slice(&extra[..n]);
+        }
+        N163 {
+            cart,
+            CIRAM:          [0; 2048],
+            chr_bank:       [0; 12],
+            prg_bank:       [0; 3],
+            ciram_off:      [false; 2],
+            ram_protect:    0,
+            irq_counter:    0,
+            irq_enabled:    false,
+            irq_pending:    false,
+            audio,
+        }
+    }
+
+    fn prg_index(&self, address: u16) -> usize {
+        let len = self.cart.PRG.len();
+        let offset = address as usize & 0x1FFF;
+        match address {
+            0x8000..=0xDFFF => self.prg_bank[(address as usize - 0x8000) >> 13] as usize * 0x2000 + offset,
+            _ => len.saturating_sub(0x2000) + offset,
+        }
+    }
+
+    /// Where a CHR bank register points: CIRAM, or CHR.
+    fn chr_target(&self, slot: usize, address: u16) -> (bool, usize) {
+        let bank = self.chr_bank[slot];
+        let ciram = bank >= 0xE0 && (slot >= 8 || !self.ciram_off[slot / 4]);
+        let offset = address as usize & 0x3FF;
+        if ciram {
+            (true, (bank as usize & 1) * 0x400 + offset)
+        }
+        else {
+            (false, bank as usize * 0x400 + offset)
+        }
+    }
+    fn pattern_read(&self, slot: usize, address: u16) -> u8 {
+        match self.chr_target(slot, address) {
+            (true, index) => self.CIRAM[index],
+            (false, index) => chr_read(&self.cart, index),
+        }
+    }
+    fn pattern_write(&mut self, slot: usize, address: u16, val: u8) {
+        match self.chr_target(slot, address) {
+            (true, index) => self.CIRAM[index] = val,
+            (false, index) => chr_write(&mut self.cart, index, val),
+        }
+    }
+    fn ram_writable(&self, address: u16) -> bool {
+        let block = (address as usize - 0x6000) >> 11;
+        self.ram_protect & 0xF0 == 0x40 && self.ram_protect & (1 << block) == 0
+    }
+}
+
+impl MAP for N163 {
+    fn get(&mut self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.audio.read(),
+            _ => self.peek(address),
+        }
+    }
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.audio.peek(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7FFF => self.cart.get_ram(address),
+            0x8000..=0xFFFF => prg_read(&self.cart, self.prg_index(address)),
+            _ => 0,
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        match address {
+            0x4800..=0x4FFF => self.audio.write(val),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | val as u16;
+                self.irq_pending = false;
+            },
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | ((val & 0x7F) as u16) << 8;
+                self.irq_enabled = val & 0x80 != 0;
+                self.irq_pending = false;
+            },
+            0x6000..=0x7FFF if self.ram_writable(address) => self.cart.set_ram(address, val),
+            0x8000..=0xDFFF => self.chr_bank[(address as usize - 0x8000) >> 11] = val,
+            0xE000..=0xE7FF => {
+                self.prg_bank[0]    = val & 0x3F;
+                self.audio.disabled = val & 0x40 != 0;
+            },
+            0xE800..=0xEFFF => {
+                self.prg_bank[1] = val & 0x3F;
+                self.ciram_off   = [val & 0x40 != 0, val & 0x80 != 0];
+            },
+            0xF000..=0xF7FF => self.prg_bank[2] = val & 0x3F,
+            0xF800..=0xFFFF => {
+                self.ram_protect = val;
+                self.audio.set_address(val);
+            },
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        self.pattern_read((address >> 10) as usize & 7, address)
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        self.pattern_write((address >> 10) as usize & 7, address, val);
+    }
+    fn nametable(&self, address: u16) -> Option<u8> {
+        Some(self.pattern_read(8 + ((address >> 10) as usize & 3), address))
+    }
+    fn set_nametable(&mut self, address: u16, val: u8) -> bool {
+        self.pattern_write(8 + ((address >> 10) as usize & 3), address, val);
+        true
+    }
+    /// The sound RAM is battery backed with PRG-RAM, so it outlives a
+    ///  power cycle on those carts.
+    fn power(&mut self, src: &mut PowerSource) {
+        let ram = self.audio.RAM;
+        *self = power_cycle(&mut self.cart, src, N163::new);
+        if self.cart.battery {
+            self.audio.RAM = ram;
+        }
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav_with(&self.audio.RAM)
+    }
+    fn clock(&mut self) {
+        //Counts up to $7FFF and stops there.
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
@@ -0,0 +1,182 @@
+/*  Unit test module of the Namco 163 (n163.rs).
+ *  Carts are built by hand with every 8kb PRG and 1kb CHR bank filled
+ *   with its own bank number.
+ */
+use crate::core::n163::*;
+
+#[cfg(test)]
+pub mod n163_test {
+    use super::*;
+    use crate::core::mapper::*;
+
+    fn cart(battery: bool) -> Box<CART> {
+        let mut cart = banked_cart(19, 0, 0x2000, 0x400, 16);
+        cart.battery = battery;
+        Box::new(cart)
+    }
+
+    //~~~BANKING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_n163_prg(){
+        let mut map = N163::new(cart(false));
+        map.set(0xE000, 3);
+        map.set(0xE800, 4);
+        map.set(0xF000, 5);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![3, 4, 5, 15]);
+
+        //Writes need $4x in $F800, and the 2kb block's bit clear.
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0);
+        map.set(0xF800, 0x41);
+        map.set(0x6000, 0x42);
+        map.set(0x6800, 0x43);
+        assert_eq!((map.peek(0x6000), map.peek(0x6800)), (0, 0x43));
+    }
+
+    #[test]
+    fn test_n163_chr(){
+        let mut map = N163::new(cart(false));
+        for slot in 0..8 {
+            map.set(0x8000 + slot * 0x800, 10 + slot as u8);
+        }
+        let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+        assert_eq!(banks, vec![10, 11, 12, 13, 14, 15, 16, 17]);
+
+        //$E0 and up is CIRAM, writable, and shared with the nametables.
+        map.set(0x8000, 0xE1);
+        map.set_chr(0x0005, 0x99);
+        assert_eq!(map.get_chr(0x0005), 0x99);
+        map.set(0xC000, 0xE1);
+        assert_eq!(map.nametable(0x2005), Some(0x99));
+        //Unless $E800 keeps the low pattern table on CHR-ROM.
+        map.set(0xE800, 0x40);
+        assert_eq!(map.get_chr(0x0005), 0xE1 % 128);
+        map.set_chr(0x0005, 0x11);
+        assert_eq!(map.nametable(0x2005), Some(0x99));
+    }
+
+    #[test]
+    fn test_n163_nametables(){
+        let mut map = N163::new(cart(false));
+        //Vertical from CIRAM, with a CHR-ROM page in the last.
+        for (i, &bank) in [0xE0, 0xE1, 0xE0, 0x22].iter().enumerate() {
+            map.set(0xC000 + i as u16 * 0x800, bank);
+        }
+        assert!(map.set_nametable(0x2000, 0x12));
+        assert!(map.set_nametable(0x2400, 0x34));
+        assert_eq!(map.nametable(0x2800), Some(0x12));
+        assert_eq!(map.nametable(0x2400), Some(0x34));
+        assert_eq!(map.nametable(0x2C00), Some(0x22));
+        //CHR-ROM isn't written.
+        map.set_nametable(0x2C00, 0x56);
+        assert_eq!(map.nametable(0x2C00), Some(0x22));
+    }
+
+    #[test]
+    fn test_n163_irq(){
+        let mut map = N163::new(cart(false));
+        map.set(0x5000, 0xFD);
+        map.set(0x5800, 0xFF);
+        assert_eq!((map.peek(0x5000), map.peek(0x5800)), (0xFD, 0xFF));
+        map.clock();
+        assert!(!map.irq());
+        map.clock();
+        assert!(map.irq());
+        //It stops at $7FFF.
+        map.clock();
+        assert_eq!(map.peek(0x5000), 0xFF);
+        map.set(0x5000, 0x00);
+        assert!(!map.irq());
+    }
+
+    //~~~SOUND~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_n163_sound_ram(){
+        let mut map = N163::new(cart(false));
+        map.set(0xF800, 0x80);
+        for val in 1..4 {
+            map.set(0x4800, val);
+        }
+        map.set(0xF800, 0x80);
+        assert_eq!(map.peek(0x4800), 1);
+        let read: Vec<u8> = (0..3).map(|_| map.get(0x4800)).collect();
+        assert_eq!(read, vec![1, 2, 3]);
+        //Without the increment, the address stays put.
+        map.set(0xF800, 0x01);
+        map.get(0x4800);
+        assert_eq!(map.get(0x4800), 2);
+    }
+
+    #[test]
+    fn test_n163_sound_battery(){
+        let path = std::env::temp_dir().join("soliloquy_n163_battery.nes");
+        let mut cart = cart(true);
+        cart.filename = Some(path.clone());
+        let mut map = N163::new(cart);
+        map.set(0xF800, 0x40);
+        map.set(0x6000, 0x42);
+        map.set(0x4800, 0x24);
+        map.flush_sav().unwrap();
+
+        let mut cart = self::cart(true);
+        cart.filename = Some(path);
+        cart.load_sav().unwrap();
+        let map = N163::new(cart);
+        assert_eq!(map.peek(0x6000), 0x42);
+        assert_eq!(map.audio.RAM[0x40], 0x24);
+        std::fs::remove_file(map.cart.sav_path().unwrap()).unwrap();
+    }
+
+    /// Channel n: a 4 sample wave at address 0 (0, 15, 0, 15), stepping a
+    ///  sample per update.
+    fn square(audio: &mut N163Audio, channel: usize, volume: u8) {
+        audio.RAM[0] = 0xF0;
+        audio.RAM[1] = 0xF0;
+        let base = 0x40 + channel * 8;
+        audio.RAM[base + 4] = 0xFC | 1;
+        audio.RAM[base + 7] = (audio.RAM[base + 7] & 0xF0) | volume;
+    }
+
+    /// The level on the DAC at each of the next updates.
+    fn slots(audio: &mut N163Audio, count: usize) -> Vec<i16> {
+        (0..count).map(|_| {
+            for _ in 0..N163_CYCLES {
+                audio.clock();
+            }
+            audio.level
+        }).collect()
+    }
+
+    #[test]
+    fn test_n163_channel(){
+        let mut audio = N163Audio::new();
+        square(&mut audio, 7, 15);
+        assert_eq!(audio.channels(), 1);
+        //-8 and +7, times the volume.
+        assert_eq!(slots(&mut audio, 4), vec![105, -120, 105, -120]);
+        assert!((audio.output() + 0.15).abs() < 1e-6);
+
+        audio.disabled = true;
+        assert_eq!(audio.output(), 0.0);
+    }
+
+    #[test]
+    fn test_n163_multiplexing(){
+        //Two channels take turns on the DAC, 15 cycles each.
+        let mut audio = N163Audio::new();
+        audio.RAM[0x7F] = 0x10;
+        square(&mut audio, 7, 15);
+        square(&mut audio, 6, 0);
+        assert_eq!(audio.channels(), 2);
+        let levels = slots(&mut audio, 6);
+        assert_eq!(levels, vec![105, 0, -120, 0, 105, 0]);
+
+        //With all eight on, channel 7 comes back every 120 cycles, a
+        // ~14.9kHz whine under everything.
+        audio.RAM[0x7F] = 0x70 | 15;
+        let seven: Vec<u8> = (0..16).map(|_| { slots(&mut audio, 1); audio.channel }).collect();
+        assert_eq!(&seven[..8], &seven[8..]);
+        assert_eq!(seven.iter().filter(|&&c| c == 7).count(), 2);
+    }
+}
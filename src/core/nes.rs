@@ -8,40 +8,83 @@
 
 pub use crate::core::*;
 pub use crate::core::cpu::OP_SIZES;
+pub use crate::core::cpu::OP_SPEEDS;
+use std::io;
+use std::io::Read;
+use std::path::Path;
 
 const DEBUG_ROM: bool = true;
 
 
 pub struct NES {
     cpu:    CPU,
+    power:  PowerOn,    //How RAM/registers were filled at power-on.
 }
 impl NES {
     //Loads values for each hardware device, including rom-file. 
-    pub fn new(file_n: &'static str) -> NES {
-        //Creates a CART type that will be read as memory later.
-        let mut cart = CART::new(file_n);
-
+    pub fn new<P: AsRef<Path>>(file_n: P) -> NES {
         //Reads values from ROM file into memory.
         //This CART data can now be used to propogate 16-bit address space. 
-        match cart.read_cart(){
-            Ok(e)    => debug!("COMPLETE -> ROM read."),
-            Err(e)   => panic!("ERROR    -> ROM read."),
+        match NES::from_path(file_n) {
+            Ok(nes)  => nes,
+            Err(e)   => panic!("ERROR    -> ROM read: {}", e),
+        }
+    }
+    /// Boots a ROM file, picked at runtime or otherwise.
+    pub fn from_path<P: AsRef<Path>>(file_n: P) -> io::Result<NES> {
+        NES::from_cart(CART::from_path(file_n)?)
+    }
+    /// Boots a ROM from any reader, ie: a socket.
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<NES> {
+        NES::from_cart(CART::from_reader(reader)?)
+    }
+    /// Boots a ROM from memory, ie: NES::from_bytes(include_bytes!("a.nes"))
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<NES> {
+        NES::from_cart(CART::from_bytes(bytes)?)
+    }
+
+    /// Boots an already loaded cart, or fails if its mapper isn't emulated.
+    pub fn from_cart(mut cart: CART) -> io::Result<NES> {
+        debug!("COMPLETE -> ROM read.");
+        //Battery backed carts pick up where they left off.
+        if let Err(e) = cart.load_sav() {
+            warn!("ERROR    -> SAV read: {}", e);
         }
 
-        //Find and create mapper.
-        let map_num = (cart.HEAD[7] & 240) 
-                        + (((cart.HEAD[6]) & 240) >> 4);
-        let mapper: Box<MAP> = new_map(map_num, Box::new(cart));
+        //Nametable mirroring is soldered on for simple carts.
+        let mirroring = cart.mirroring;
+        let timing    = cart.timing;
+
+        //Find and create mapper, from the (NES 2.0 aware) header fields.
+        debug!("CART     -> {:?}", cart);
+        let map_num = cart.mapper;
+        let mapper: Box<dyn MAP> = new_map(map_num, Box::new(cart))?;
         debug!("COMPLETE -> Mapper init.");
+        let mut nes = NES::from_mapper(mapper, mirroring);
+        nes.cpu.memory.APU.timing = timing;
+        Ok(nes)
+    }
+
+    /// Boots a Famicom Disk System disk image, with the user's disk BIOS
+    ///  (disksys.rom). Disk writes are saved next to the image, as .fds.sav.
+    pub fn from_fds<P: AsRef<Path>, Q: AsRef<Path>>(disk: P, bios: Q) -> io::Result<NES> {
+        let image = FdsImage::from_path(disk)?;
+        let fds = Fds::new(std::fs::read(bios)?, image)?;
+        debug!("COMPLETE -> FDS init, {} sides.", fds.drive.sides());
+        Ok(NES::from_mapper(Box::new(fds), Mirroring::Horizontal))
+    }
 
+    /// Boots whatever is in the cart slot.
+    pub fn from_mapper(mapper: Box<dyn MAP>, mirroring: Mirroring) -> NES {
         //PPU init
-        let ppu: u8 = 0;
+        let mut ppu = PPU::new();
+        ppu.mirroring = mirroring;
         debug!("COMPLETE -> PPU init.");
         //APU init
-        let apu: u8 = 0;
+        let apu = APU::new();
         debug!("COMPLETE -> APU init.");
         //Input init
-        let input: u8 = 0;
+        let input = [Controller::new(); 2];
         debug!("COMPLETE -> INPUT init.");
 
         //Main memory map init
@@ -50,10 +93,35 @@ impl NES {
 
 
         //CPU init
-        NES{
-            cpu: CPU::new(memory),
-        }
+        let mut nes = NES{
+            cpu:    CPU::new(memory),
+            power:  PowerOn::default(),
+        };
+        nes.power_on(PowerOn::default());
+        nes
+    }
 
+    /// Applies a power-on policy to RAM, the CPU registers, the PPU and the
+    ///  cart, then starts from the reset vector. The APU's power-up state
+    ///  is fixed.
+    /// Random policies carry their seed, which is logged here and kept in
+    ///  NES::power, so a run can be reproduced.
+    pub fn power_on(&mut self, init: PowerOn) {
+        match init.seed() {
+            Some(seed) => info!("POWER    -> {:?}, seed: {:#018X}", init, seed),
+            None       => info!("POWER    -> {:?}", init),
+        }
+        self.power = init;
+        self.cpu.power_on(&mut init.source());
+    }
+    /// Presses the reset button. Unlike power_on, RAM keeps its contents.
+    pub fn reset(&mut self) {
+        info!("POWER    -> Reset");
+        self.cpu.reset();
+    }
+    /// The power-on policy this console was last switched on with.
+    pub fn power(&self) -> PowerOn {
+        self.power
     }
     //+ Further Boot Stuff.
     //-+ Read CHR ROM write data to PPU
@@ -67,7 +135,18 @@ impl NES {
         //CPU running code
 
         //Run a step from each piece of hardware!
+        let opnum = self.cpu.memory.peek(self.cpu.pc);
         self.cpu.step();
+
+        //The CPU doesn't count its own cycles yet, so the opcode's base
+        // timing stands in for clocking the APU and cart.
+        let cycles = OP_SPEEDS[opnum as usize].max(2);
+        for _ in 0..cycles {
+            self.cpu.memory.clock();
+        }
+        if self.cpu.memory.irq() {
+            self.cpu.irq();
+        }
         //self.cpu.memory.ppu.step(); //Theoretically how PPU is called.        
 
 
@@ -89,12 +168,48 @@ impl NES {
         */
     }
 
+    /// Where the CPU is, for debuggers.
+    pub fn pc(&self) -> u16 {
+        self.cpu.pc
+    }
+    /// Reads memory without side effects, for RAM watches and the like.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.cpu.memory.peek(address)
+    }
+    /// Disassembles `count` instructions, starting at an address.
+    pub fn disassemble(&self, address: u16, count: usize) -> Vec<String> {
+        let mut address = address;
+        let mut lines = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (text, next) = self.cpu.disassemble(address);
+            lines.push(text);
+            address = next;
+        }
+        lines
+    }
+    /// The FDS disk drive, for switching sides. None for carts.
+    pub fn disk(&mut self) -> Option<&mut DiskDrive> {
+        self.cpu.memory.CART.disk()
+    }
+    /// Sets the buttons (BUTTON_* bits) held on controller port 0 or 1.
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.cpu.memory.INPUT[port].set_buttons(buttons);
+    }
+
     //Ends fde loop, deallocates if needed.
     //This likely won't be needed considering the ultimate lack of
     // pointers needed so far, but it's a logical state to at least
     // define for now.
     pub fn shutdown (&mut self){
+        if let Err(e) = self.flush_sav() {
+            warn!("ERROR    -> SAV write: {}", e);
+        }
+    }
 
+    /// Writes battery backed PRG-RAM to the ROM's .sav file.
+    /// Called on shutdown, but can be called whenever a save is wanted.
+    pub fn flush_sav (&mut self) -> std::io::Result<()> {
+        self.cpu.memory.CART.flush_sav()
     }
 
     ///**Currently NIL** Intended to use serialization to save the NES state.
@@ -0,0 +1,586 @@
+/* NSF/NSFe music files, and a headless player for them.
+ * An NSF is a game's sound driver ripped out with its music data: INIT
+ *  sets up a song, and PLAY is called once a frame. The player runs both
+ *  on the real CPU and APU, and renders the result to PCM.
+ * Author: Spalynx
+ *--------------NSF Memory Map-----------------------------------------
+ * $4100-$410F  Player driver: JSR to INIT/PLAY, then spin.
+ * $5FF6-$5FF7  FDS only, 4kb banks at $6000/$7000.
+ * $5FF8-$5FFF  4kb banks at $8000-$FFFF, when bankswitched.
+ * $6000-$7FFF  8kb RAM.
+ * $8000-$FFFF  Program, RAM as well on the FDS.
+ *--------------Expansion Chips (header $7B)----------------------------
+ * bit 0 VRC6, 1 VRC7, 2 FDS, 3 MMC5, 4 Namco 163, 5 Sunsoft 5B.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/NSF
+ * http://wiki.nesdev.com/w/index.php/NSFe
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use ::log::*;
+use crate::core::*;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./nsf_test.rs"]
+pub mod nsf_test;
+
+pub const CHIP_VRC6: u8 = 0x01;
+pub const CHIP_VRC7: u8 = 0x02;
+pub const CHIP_FDS:  u8 = 0x04;
+pub const CHIP_MMC5: u8 = 0x08;
+pub const CHIP_N163: u8 = 0x10;
+pub const CHIP_5B:   u8 = 0x20;
+
+//Where the player's driver lives: JSR $xxxx / JMP *.
+const DRIVER: u16 = 0x4100;
+const DRIVER_IDLE: u16 = DRIVER + 3;
+
+//CPU clocks, and the PLAY rates used when the header leaves them at 0.
+const NTSC_HZ: u64 = 1_789_773;
+const PAL_HZ:  u64 = 1_662_607;
+const NTSC_PLAY_US: u16 = 16639;
+const PAL_PLAY_US:  u16 = 19997;
+
+fn bad(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Null terminated (or padded) header text.
+fn text(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn le16(data: &[u8], at: usize) -> u16 {
+    data[at] as u16 | (data[at + 1] as u16) << 8
+}
+
+/// Per-track metadata, from NSFe's tlbl/time/fade chunks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NsfTrack {
+    pub name:       Option<String>,
+    pub time:       Option<Duration>,
+    pub fade:       Option<Duration>,
+}
+
+/// A parsed .nsf or .nsfe file.
+#[derive(Clone, Debug)]
+pub struct Nsf {
+    pub nsfe:       bool,
+    pub version:    u8,
+    pub songs:      u8,
+    pub start:      u8,         //First song to play, 0 based.
+    pub load:       u16,
+    pub init:       u16,
+    pub play:       u16,
+    pub title:      String,
+    pub artist:     String,
+    pub copyright:  String,
+    pub ripper:     String,
+    pub ntsc_speed: u16,        //PLAY period, in microseconds.
+    pub pal_speed:  u16,
+    pub region:     u8,         //Bit 0 PAL, bit 1 dual.
+    pub chips:      u8,         //CHIP_* flags.
+    pub banks:      Option<[u8; 8]>,    //None if not bankswitched.
+    pub data:       Vec<u8>,
+    pub tracks:     Vec<NsfTrack>,
+    pub playlist:   Option<Vec<u8>>,
+}
+
+impl Nsf {
+    /// Parses either format, going by the magic.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Nsf> {
+        if bytes.starts_with(b"NESM\x1A") {
+            Nsf::parse_nsf(bytes)
+        }
+        else if bytes.starts_with(b"NSFE") {
+            Nsf::parse_nsfe(bytes)
+        }
+        else {
+            Err(bad("Not an NSF or NSFe file.".to_string()))
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Nsf> {
+        Nsf::from_bytes(&fs::read(path)?)
+    }
+
+    fn empty() -> Nsf {
+        Nsf {
+            nsfe: false, version: 1, songs: 1, start: 0,
+            load: 0x8000, init: 0x8000, play: 0x8000,
+            title: String::new(), artist: String::new(),
+            copyright: String::new(), ripper: String::new(),
+            ntsc_speed: NTSC_PLAY_US, pal_speed: PAL_PLAY_US,
+            region: 0, chips: 0, banks: None,
+            data: Vec::new(), tracks: Vec::new(), playlist: None,
+        }
+    }
+
+    /// The classic format: a 128 byte header, then the program.
+    fn parse_nsf(bytes: &[u8]) -> io::Result<Nsf> {
+        if bytes.len() < 0x80 {
+            return Err(bad("NSF header is truncated.".to_string()));
+        }
+        let h = &bytes[..0x80];
+        let mut banks = [0u8; 8];
+        banks.copy_from_slice(&h[0x70..0x78]);
+
+        let mut nsf = Nsf {
+            version:    h[5],
+            songs:      h[6],
+            start:      h[7].saturating_sub(1),
+            load:       le16(h, 0x08),
+            init:       le16(h, 0x0A),
+            play:       le16(h, 0x0C),
+            title:      text(&h[0x0E..0x2E]),
+            artist:     text(&h[0x2E..0x4E]),
+            copyright:  text(&h[0x4E..0x6E]),
+            ntsc_speed: le16(h, 0x6E),
+            pal_speed:  le16(h, 0x78),
+            region:     h[0x7A] & 3,
+            chips:      h[0x7B],
+            banks:      if banks.iter().any(|&b| b != 0) { Some(banks) } else { None },
+            data:       bytes[0x80..].to_vec(),
+            ..Nsf::empty()
+        };
+        //NSF2 gives the program length, anything after it is metadata.
+        let length = h[0x7D] as usize | (h[0x7E] as usize) << 8 | (h[0x7F] as usize) << 16;
+        if nsf.version >= 2 && length > 0 && length < nsf.data.len() {
+            nsf.data.truncate(length);
+        }
+        nsf.tracks = vec![NsfTrack::default(); nsf.songs as usize];
+        Ok(nsf)
+    }
+
+    /// NSFe: the same fields, in chunks, with optional metadata.
+    fn parse_nsfe(bytes: &[u8]) -> io::Result<Nsf> {
+        let mut nsf = Nsf { nsfe: true, ..Nsf::empty() };
+        let mut info = false;
+        let mut names: Vec<String> = Vec::new();
+        let mut times: Vec<i32> = Vec::new();
+        let mut fades: Vec<i32> = Vec::new();
+
+        let mut pos = 4;
+        while pos + 8 <= bytes.len() {
+            let len = u32::from_le_bytes([bytes[pos], bytes[pos+1], bytes[pos+2], bytes[pos+3]]) as usize;
+            let id  = &bytes[pos + 4..pos + 8];
+            pos += 8;
+            let data = bytes.get(pos..pos.saturating_add(len)).ok_or_else(||
+                bad(format!("NSFe chunk {} is truncated.", String::from_utf8_lossy(id))))?;
+            pos += len;
+
+            match id {
+                b"INFO" => {
+                    if data.len() < 9 {
+                        return Err(bad("NSFe INFO chunk is too short.".to_string()));
+                    }
+                    nsf.load   = le16(data, 0);
+                    nsf.init   = le16(data, 2);
+                    nsf.play   = le16(data, 4);
+                    nsf.region = data[6] & 3;
+                    nsf.chips  = data[7];
+                    nsf.songs  = data.get(8).cloned().unwrap_or(1);
+                    nsf.start  = data.get(9).cloned().unwrap_or(0);
+                    info = true;
+                },
+                b"DATA" => nsf.data = data.to_vec(),
+                b"BANK" => {
+                    let mut banks = [0u8; 8];
+                    let n = data.len().min(8);
+                    banks[..n].copy_from_slice(&data[..n]);
+                    nsf.banks = Some(banks);
+                },
+                b"RATE" => {
+                    if data.len() >= 2 { nsf.ntsc_speed = le16(data, 0); }
+                    if data.len() >= 4 { nsf.pal_speed  = le16(data, 2); }
+                },
+                b"auth" => {
+                    let mut fields = data.split(|&b| b == 0).map(text);
+                    nsf.title     = fields.next().unwrap_or_default();
+                    nsf.artist    = fields.next().unwrap_or_default();
+                    nsf.copyright = fields.next().unwrap_or_default();
+                    nsf.ripper    = fields.next().unwrap_or_default();
+                },
+                b"tlbl" => names = data.split(|&b| b == 0).map(text).collect(),
+                b"time" => times = data.chunks_exact(4)
+                    .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+                b"fade" => fades = data.chunks_exact(4)
+                    .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+                b"plst" => nsf.playlist = Some(data.to_vec()),
+                b"NEND" => break,
+                //Lowercase chunks are optional, uppercase ones can't be skipped.
+                _ if id[0].is_ascii_uppercase() => {
+                    return Err(bad(format!("NSFe chunk {} is required, but not supported.",
+                                           String::from_utf8_lossy(id))));
+                },
+                _ => debug!("NSF      -> Skipping chunk {}", String::from_utf8_lossy(id)),
+            }
+        }
+        if !info || nsf.data.is_empty() {
+            return Err(bad("NSFe needs both INFO and DATA chunks.".to_string()));
+        }
+
+        //Negative times mean "not given".
+        let ms = |v: Option<&i32>| v.filter(|&&v| v >= 0).map(|&v| Duration::from_millis(v as u64));
+        nsf.tracks = (0..nsf.songs as usize).map(|i| NsfTrack {
+            name: names.get(i).filter(|n| !n.is_empty()).cloned(),
+            time: ms(times.get(i)),
+            fade: ms(fades.get(i)),
+        }).collect();
+        Ok(nsf)
+    }
+
+    /// PAL only tunes run at PAL speed, everything else at NTSC.
+    pub fn timing(&self) -> Timing {
+        if self.region & 3 == 1 { Timing::Pal } else { Timing::Ntsc }
+    }
+
+    /// Expansion chips the file asks for, by name.
+    pub fn chip_names(&self) -> Vec<&'static str> {
+        [(CHIP_VRC6, "VRC6"), (CHIP_VRC7, "VRC7"), (CHIP_FDS, "FDS"),
+         (CHIP_MMC5, "MMC5"), (CHIP_N163, "Namco 163"), (CHIP_5B, "Sunsoft 5B")]
+            .iter().filter(|(bit, _)| self.chips & bit != 0).map(|&(_, name)| name).collect()
+    }
+}
+
+//~~~MAPPER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// The cart an NSF player would be: program ROM behind 4kb banks, RAM,
+///  a tiny driver, and whichever expansion chips the file wants.
+#[allow(non_snake_case)]
+pub struct NsfMap {
+    pub PRG:        Vec<u8>,    //Whole 4kb banks, padded at the front.
+    pub RAM:        Vec<u8>,    //$6000-$7FFF, or $6000-$FFFF on the FDS.
+    pub banks:      [u8; 8],    //$5FF8-$5FFF.
+    pub driver:     [u8; 16],   //$4100-$410F.
+    pub chips:      u8,
+    pub fds:        Option<FdsAudio>,
+    pub vrc6:       Option<Vrc6Audio>,
+    pub vrc7:       Option<Vrc7Audio>,
+    pub mmc5:       Option<Mmc5Audio>,
+    pub sunsoft:    Option<Sunsoft5b>,
+    pub n163:       Option<N163Audio>,
+}
+
+impl NsfMap {
+    pub fn new(nsf: &Nsf) -> NsfMap {
+        let fds = nsf.chips & CHIP_FDS != 0;
+        for name in nsf.chip_names() {
+            debug!("NSF      -> {} audio.", name);
+        }
+
+        //Bankswitched data is padded so the load address lands in place
+        // within its bank, otherwise it's laid out from $8000 (or $6000).
+        let (prg, banks) = match nsf.banks {
+            Some(banks) => {
+                let mut prg = vec![0; (nsf.load & 0x0FFF) as usize];
+                prg.extend_from_slice(&nsf.data);
+                (prg, banks)
+            },
+            None => {
+                let base = if fds { 0x6000 } else { 0x8000 };
+                let mut prg = vec![0; (nsf.load as usize).saturating_sub(base)];
+                prg.extend_from_slice(&nsf.data);
+                prg.resize(if fds { 0xA000 } else { 0x8000 }, 0);
+                (prg, [0, 1, 2, 3, 4, 5, 6, 7])
+            },
+        };
+        let mut map = NsfMap {
+            PRG:    prg,
+            RAM:    vec![0; if fds { 0xA000 } else { 0x2000 }],
+            banks,
+            driver: [0x20, 0, 0, 0x4C, DRIVER_IDLE as u8, (DRIVER_IDLE >> 8) as u8,
+                     0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            chips:  nsf.chips,
+            fds:    if fds { Some(FdsAudio::new()) } else { None },
+            vrc6:   if nsf.chips & CHIP_VRC6 != 0 { Some(Vrc6Audio::new()) } else { None },
+            vrc7:   if nsf.chips & CHIP_VRC7 != 0 { Some(Vrc7Audio::new()) } else { None },
+            mmc5:   if nsf.chips & CHIP_MMC5 != 0 { Some(Mmc5Audio::new()) } else { None },
+            sunsoft: if nsf.chips & CHIP_5B != 0 { Some(Sunsoft5b::new()) } else { None },
+            n163:   if nsf.chips & CHIP_N163 != 0 { Some(N163Audio::new()) } else { None },
+        };
+        let padded = (map.PRG.len() + 0xFFF) & !0xFFF;
+        map.PRG.resize(padded.max(0x1000), 0);
+
+        //The FDS runs from RAM, which banks are copied into.
+        if fds {
+            match nsf.banks {
+                Some(b) => {
+                    map.fill_ram(0, b[6]);
+                    map.fill_ram(1, b[7]);
+                    for (slot, &bank) in b.iter().enumerate() {
+                        map.fill_ram(slot + 2, bank);
+                    }
+                },
+                None => map.RAM = map.PRG[..0xA000].to_vec(),
+            }
+        }
+        map
+    }
+
+    fn bank(&self, bank: u8) -> &[u8] {
+        let count = self.PRG.len() / 0x1000;
+        let start = (bank as usize % count) * 0x1000;
+        &self.PRG[start..start + 0x1000]
+    }
+    /// FDS: copies a bank into one of the 4kb RAM slots from $6000.
+    fn fill_ram(&mut self, slot: usize, bank: u8) {
+        let data = self.bank(bank).to_vec();
+        self.RAM[slot * 0x1000..(slot + 1) * 0x1000].copy_from_slice(&data);
+    }
+}
+
+impl MAP for NsfMap {
+    fn get(&mut self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF if self.n163.is_some() => self.n163.as_mut().map_or(0, |n| n.read()),
+            _ => self.peek(address),
+        }
+    }
+    fn peek(&self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.n163.as_ref().map_or(0, |n| n.peek()),
+            0x4040..=0x409F => self.fds.as_ref().map_or(0, |f| f.read(address)),
+            0x5010 | 0x5015 => self.mmc5.as_ref().map_or(0, |m| m.peek(address)),
+            0x4100..=0x410F => self.driver[(address - DRIVER) as usize],
+            0x6000..=0xFFFF if self.fds.is_some() => self.RAM[address as usize - 0x6000],
+            0x6000..=0x7FFF => self.RAM[address as usize - 0x6000],
+            0x8000..=0xFFFF => {
+                let slot = (address as usize - 0x8000) >> 12;
+                self.bank(self.banks[slot])[address as usize & 0xFFF]
+            },
+            _ => 0,
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        match address {
+            0x4040..=0x408A => if let Some(ref mut fds) = self.fds { fds.write(address, val) },
+            0x4800..=0x4FFF => if let Some(ref mut n163) = self.n163 { n163.write(val) },
+            0xF800..=0xFFFF if self.n163.is_some() => if let Some(ref mut n163) = self.n163 { n163.set_address(val) },
+            0x5000..=0x5015 if self.mmc5.is_some() => if let Some(ref mut mmc5) = self.mmc5 { mmc5.write(address, val) },
+            0x9010 | 0x9030 if self.vrc7.is_some() => if let Some(ref mut vrc7) = self.vrc7 { vrc7.write(address, val) },
+            0xC000..=0xFFFF if self.sunsoft.is_some() => if let Some(ref mut sunsoft) = self.sunsoft { sunsoft.write(address, val) },
+            0x9000..=0xB002 if self.vrc6.is_some() => if let Some(ref mut vrc6) = self.vrc6 { vrc6.write(address, val) },
+            0x4100..=0x410F => self.driver[(address - DRIVER) as usize] = val,
+            0x5FF6 | 0x5FF7 if self.fds.is_some() => self.fill_ram((address - 0x5FF6) as usize, val),
+            0x5FF8..=0x5FFF => {
+                let slot = (address - 0x5FF8) as usize;
+                self.banks[slot] = val;
+                if self.fds.is_some() {
+                    self.fill_ram(slot + 2, val);
+                }
+            },
+            0x6000..=0xFFFF if self.fds.is_some() => self.RAM[address as usize - 0x6000] = val,
+            0x6000..=0x7FFF => self.RAM[address as usize - 0x6000] = val,
+            _ => {},
+        }
+    }
+    fn get_chr(&self, _address: u16) -> u8 { 0 }
+    fn set_chr(&mut self, _address: u16, _val: u8) {}
+    fn clock(&mut self) {
+        if let Some(ref mut fds) = self.fds {
+            fds.clock();
+        }
+        if let Some(ref mut vrc6) = self.vrc6 {
+            vrc6.clock();
+        }
+        if let Some(ref mut vrc7) = self.vrc7 {
+            vrc7.clock();
+        }
+        if let Some(ref mut mmc5) = self.mmc5 {
+            mmc5.clock();
+        }
+        if let Some(ref mut sunsoft) = self.sunsoft {
+            sunsoft.clock();
+        }
+        if let Some(ref mut n163) = self.n163 {
+            n163.clock();
+        }
+    }
+    fn audio(&self) -> f32 {
+        self.fds.as_ref().map_or(0.0, |f| f.mixed())
+            + self.vrc6.as_ref().map_or(0.0, |v| v.output())
+            + self.vrc7.as_ref().map_or(0.0, |v| v.output())
+            + self.mmc5.as_ref().map_or(0.0, |m| m.output())
+            + self.sunsoft.as_ref().map_or(0.0, |s| s.output())
+            + self.n163.as_ref().map_or(0.0, |n| n.output())
+    }
+}
+
+//~~~PLAYER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// Runs an NSF on the CPU/APU, and renders it to PCM, ie:
+///  let mut player = NsfPlayer::new(Nsf::from_path("smb.nsf")?, 44100);
+///  player.start(0);
+///  let pcm = player.render(Duration::from_secs(30));
+pub struct NsfPlayer {
+    pub nsf:            Nsf,
+    pub cpu:            CPU,
+    pub sample_rate:    u32,
+    pub track:          u8,
+    cpu_hz:             u64,
+    play_cycles:        u64,    //CPU cycles between PLAY calls.
+    until_play:         u64,
+    sample_clock:       u64,
+    sample_sum:         f32,
+    sample_count:       u32,
+    filter:             NesFilter,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf, sample_rate: u32) -> NsfPlayer {
+        let map = NsfMap::new(&nsf);
+        let mut apu = APU::new();
+        apu.timing = nsf.timing();
+        let memory = MEM::new(Box::new(map), PPU::new(), apu, [Controller::new(); 2]);
+
+        let (cpu_hz, speed, default) = match nsf.timing() {
+            Timing::Pal => (PAL_HZ, nsf.pal_speed, PAL_PLAY_US),
+            _           => (NTSC_HZ, nsf.ntsc_speed, NTSC_PLAY_US),
+        };
+        let speed = if speed == 0 { default } else { speed } as u64;
+        NsfPlayer {
+            cpu:            CPU::new(memory),
+            sample_rate,
+            track:          nsf.start,
+            cpu_hz,
+            play_cycles:    speed * cpu_hz / 1_000_000,
+            until_play:     0,
+            sample_clock:   0,
+            sample_sum:     0.0,
+            sample_count:   0,
+            filter:         NesFilter::new(sample_rate),
+            nsf,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<NsfPlayer> {
+        Ok(NsfPlayer::new(Nsf::from_path(path)?, sample_rate))
+    }
+
+    /// Resets the machine and runs INIT for a track, 0 based.
+    pub fn start(&mut self, track: u8) {
+        self.track = track;
+        let mut src = PowerOn::Zeros.source();
+        self.cpu.power_on(&mut src);
+        let map = NsfMap::new(&self.nsf);
+        self.cpu.memory.CART = Box::new(map);
+
+        //Per the NSF spec: APU cleared, channels enabled, frame IRQs off.
+        for address in 0x4000..0x4014 {
+            self.cpu.memory.set(address, 0);
+        }
+        self.cpu.memory.set(0x4010, 0x10);
+        self.cpu.memory.set(0x4015, 0x00);
+        self.cpu.memory.set(0x4015, 0x0F);
+        self.cpu.memory.set(0x4017, 0x40);
+
+        self.cpu.a = track;
+        self.cpu.x = (self.nsf.timing() == Timing::Pal) as u8;
+        self.cpu.y = 0;
+        self.call(self.nsf.init);
+        //Let INIT run to completion before the first PLAY.
+        let mut budget = self.cpu_hz;
+        while self.cpu.pc != DRIVER_IDLE && budget > 0 {
+            budget = budget.saturating_sub(self.instruction());
+        }
+        if budget == 0 {
+            warn!("NSF      -> INIT for track {} never returned.", track);
+        }
+        self.until_play = 0;
+    }
+
+    /// Points the driver's JSR at a routine, and the CPU at the driver.
+    fn call(&mut self, address: u16) {
+        self.cpu.memory.set(DRIVER + 1, address as u8);
+        self.cpu.memory.set(DRIVER + 2, (address >> 8) as u8);
+        self.cpu.pc = DRIVER;
+    }
+
+    /// Runs one instruction, clocking the APU and cart through it.
+    /// Returns the cycles it took.
+    fn instruction(&mut self) -> u64 {
+        let opnum = self.cpu.memory.peek(self.cpu.pc);
+        self.cpu.step();
+        let cycles = OP_SPEEDS[opnum as usize].max(2) as u64;
+        for _ in 0..cycles {
+            self.cpu.memory.clock();
+        }
+        cycles
+    }
+
+    /// Renders the current track for a while, as mono samples in -1.0..1.0.
+    pub fn render(&mut self, duration: Duration) -> Vec<f32> {
+        let total = (duration.as_secs_f64() * self.sample_rate as f64) as usize;
+        let mut out = Vec::with_capacity(total);
+        while out.len() < total {
+            //PLAY is only called once the last one has returned.
+            if self.until_play == 0 && self.cpu.pc == DRIVER_IDLE {
+                self.call(self.nsf.play);
+                self.until_play = self.play_cycles;
+            }
+            let cycles = self.instruction();
+            self.until_play = self.until_play.saturating_sub(cycles);
+
+            //Box filter down to the sample rate.
+            let level = self.cpu.memory.output();
+            self.sample_sum   += level * cycles as f32;
+            self.sample_count += cycles as u32;
+            self.sample_clock += cycles * self.sample_rate as u64;
+            while self.sample_clock >= self.cpu_hz && out.len() < total {
+                self.sample_clock -= self.cpu_hz;
+                let avg = self.sample_sum / self.sample_count.max(1) as f32;
+                out.push(self.filter.step(avg).clamp(-1.0, 1.0));
+                self.sample_sum   = 0.0;
+                self.sample_count = 0;
+            }
+        }
+        out
+    }
+
+    /// render(), as 16-bit PCM.
+    pub fn render_i16(&mut self, duration: Duration) -> Vec<i16> {
+        self.render(duration).iter().map(|&s| (s * 32767.0) as i16).collect()
+    }
+
+    /// Reads CPU memory, ie: to check a driver's variables.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.cpu.memory.peek(address)
+    }
+}
+
+/// Renders a track of an NSF/NSFe file, in one go.
+pub fn render_track<P: AsRef<Path>>(path: P, track: u8, duration: Duration,
+                                    sample_rate: u32) -> io::Result<Vec<i16>> {
+    let mut player = NsfPlayer::from_path(path, sample_rate)?;
+    player.start(track);
+    Ok(player.render_i16(duration))
+}
+
+/// Wraps mono 16-bit PCM in a .wav file, for listening to or keeping as a
+///  reference render.
+pub fn wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let data = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());     //PCM.
+    out.extend_from_slice(&1u16.to_le_bytes());     //Mono.
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data.to_le_bytes());
+    for s in samples {
+        out.extend_from_slice(&s.to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,219 @@
+/*  Unit test module of the NSF/NSFe loader and player (nsf.rs).
+ *  Tunes are tiny hand assembled drivers: INIT stores the track number and
+ *   starts a 440Hz square, PLAY counts the frames it's called for.
+ */
+use crate::core::nsf::*;
+
+#[cfg(test)]
+pub mod nsf_test {
+    use super::*;
+    use std::time::Duration;
+
+    /// INIT at $8000, PLAY at $8100.
+    pub fn program() -> Vec<u8> {
+        let mut prg = vec![
+            0x8D, 0x00, 0x02,       //STA $0200
+            0xA9, 0x0F, 0x8D, 0x15, 0x40,   //LDA #$0F, STA $4015
+            0xA9, 0xBF, 0x8D, 0x00, 0x40,   //LDA #$BF, STA $4000
+            0xA9, 0xFD, 0x8D, 0x02, 0x40,   //LDA #$FD, STA $4002
+            0xA9, 0x00, 0x8D, 0x03, 0x40,   //LDA #$00, STA $4003
+            0x60,                   //RTS
+        ];
+        prg.resize(0x100, 0);
+        prg.extend_from_slice(&[
+            0xEE, 0x01, 0x02,       //INC $0201
+            0x60,                   //RTS
+        ]);
+        prg
+    }
+
+    pub fn header(songs: u8, banks: [u8; 8], chips: u8) -> Vec<u8> {
+        let mut h = b"NESM\x1A\x01".to_vec();
+        h.push(songs);
+        h.push(2);                              //Start on song 2.
+        h.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x81]);
+        let mut title = b"Test Tune".to_vec();
+        title.resize(32, 0);
+        h.extend_from_slice(&title);
+        let mut artist = b"Spalynx".to_vec();
+        artist.resize(32, 0);
+        h.extend_from_slice(&artist);
+        h.extend_from_slice(&[0; 32]);
+        h.extend_from_slice(&16639u16.to_le_bytes());
+        h.extend_from_slice(&banks);
+        h.extend_from_slice(&19997u16.to_le_bytes());
+        h.push(0);
+        h.push(chips);
+        h.extend_from_slice(&[0; 4]);
+        assert_eq!(h.len(), 0x80);
+        h
+    }
+
+    pub fn synthetic_nsf() -> Vec<u8> {
+        let mut file = header(3, [0; 8], 0);
+        file.extend_from_slice(&program());
+        file
+    }
+
+    fn chunk(out: &mut Vec<u8>, id: &[u8], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(id);
+        out.extend_from_slice(data);
+    }
+
+    //~~~PARSING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_parse_nsf() {
+        let nsf = Nsf::from_bytes(&synthetic_nsf()).unwrap();
+        assert!(!nsf.nsfe);
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.start, 1);
+        assert_eq!((nsf.load, nsf.init, nsf.play), (0x8000, 0x8000, 0x8100));
+        assert_eq!(nsf.title, "Test Tune");
+        assert_eq!(nsf.artist, "Spalynx");
+        assert_eq!(nsf.ntsc_speed, 16639);
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.timing(), Timing::Ntsc);
+        assert_eq!(nsf.tracks.len(), 3);
+
+        assert!(Nsf::from_bytes(b"NESM\x1A\x01").is_err());
+        assert!(Nsf::from_bytes(b"NES\x1A").is_err());
+    }
+
+    #[test]
+    fn test_parse_nsfe() {
+        let mut file = b"NSFE".to_vec();
+        chunk(&mut file, b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x00, 0x81, 0x01, CHIP_VRC6, 2, 1]);
+        chunk(&mut file, b"DATA", &program());
+        chunk(&mut file, b"RATE", &[0x1A, 0x41]);
+        chunk(&mut file, b"auth", b"Title\0Artist\0(c) Someone\0Ripper\0");
+        chunk(&mut file, b"tlbl", b"Intro\0Boss\0");
+        let mut times = 90000i32.to_le_bytes().to_vec();
+        times.extend_from_slice(&(-1i32).to_le_bytes());
+        chunk(&mut file, b"time", &times);
+        chunk(&mut file, b"fade", &5000i32.to_le_bytes());
+        chunk(&mut file, b"xtra", b"skipped");
+        chunk(&mut file, b"NEND", &[]);
+
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        assert!(nsf.nsfe);
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.start, 1);
+        assert_eq!(nsf.play, 0x8100);
+        assert_eq!(nsf.timing(), Timing::Pal);
+        assert_eq!(nsf.ntsc_speed, 0x411A);
+        assert_eq!(nsf.chip_names(), vec!["VRC6"]);
+        assert_eq!((nsf.title.as_str(), nsf.ripper.as_str()), ("Title", "Ripper"));
+        assert_eq!(nsf.tracks[0], NsfTrack {
+            name: Some("Intro".to_string()),
+            time: Some(Duration::from_secs(90)),
+            fade: Some(Duration::from_secs(5)),
+        });
+        assert_eq!(nsf.tracks[1].name, Some("Boss".to_string()));
+        assert_eq!(nsf.tracks[1].time, None);
+
+        //Required chunks can't be skipped.
+        let mut bad = b"NSFE".to_vec();
+        chunk(&mut bad, b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x00, 0x81, 0, 0, 1]);
+        chunk(&mut bad, b"DATA", &program());
+        chunk(&mut bad, b"WHAT", &[]);
+        assert!(Nsf::from_bytes(&bad).is_err());
+    }
+
+    //~~~MAPPER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_bankswitching() {
+        let mut file = header(1, [0, 1, 2, 0, 0, 0, 0, 0], 0);
+        for bank in 0..3u8 {
+            file.extend_from_slice(&[0x10 + bank; 0x1000]);
+        }
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        let mut map = NsfMap::new(&nsf);
+        assert_eq!(map.peek(0x8000), 0x10);
+        assert_eq!(map.peek(0x9FFF), 0x11);
+        assert_eq!(map.peek(0xA000), 0x12);
+        assert_eq!(map.peek(0xB000), 0x10);
+
+        map.set(0x5FF8, 2);
+        assert_eq!(map.peek(0x8000), 0x12);
+        //Out of range banks wrap.
+        map.set(0x5FFF, 4);
+        assert_eq!(map.peek(0xF000), 0x11);
+
+        //ROM is read only, $6000 is RAM.
+        map.set(0x8000, 0);
+        assert_eq!(map.peek(0x8000), 0x12);
+        map.set(0x6000, 0x55);
+        assert_eq!(map.peek(0x6000), 0x55);
+    }
+
+    #[test]
+    fn test_fds_layout() {
+        let mut file = header(1, [0; 8], CHIP_FDS);
+        file.extend_from_slice(&program());
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        let mut map = NsfMap::new(&nsf);
+        assert_eq!(map.peek(0x8000), 0x8D);
+        //All of $6000-$FFFF is RAM.
+        map.set(0x8000, 0xEA);
+        assert_eq!(map.peek(0x8000), 0xEA);
+        map.set(0xFFFF, 0x12);
+        assert_eq!(map.peek(0xFFFF), 0x12);
+        //The wavetable answers at $4040.
+        map.set(0x4089, 0x80);
+        map.set(0x4041, 0x2A);
+        assert_eq!(map.peek(0x4041), 0x6A);
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let mut file = header(1, [0; 8], CHIP_VRC6 | CHIP_MMC5);
+        file.extend_from_slice(&program());
+        let nsf = Nsf::from_bytes(&file).unwrap();
+        let mut map = NsfMap::new(&nsf);
+        assert_eq!(map.audio(), 0.0);
+        //A digitized VRC6 pulse holds its volume.
+        map.set(0x9000, 0x8F);
+        map.set(0x9002, 0x80);
+        let vrc6 = map.audio();
+        assert!(vrc6 > 0.0);
+        //Then the MMC5's PCM on top.
+        map.set(0x5011, 0x80);
+        assert!(map.audio() > vrc6);
+    }
+
+    //~~~PLAYER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_player() {
+        let nsf = Nsf::from_bytes(&synthetic_nsf()).unwrap();
+        let mut player = NsfPlayer::new(nsf, 44100);
+        player.start(2);
+        assert_eq!(player.peek(0x0200), 2);
+        assert_eq!(player.peek(0x0201), 0);
+
+        let pcm = player.render(Duration::from_secs(1));
+        assert_eq!(pcm.len(), 44100);
+        //PLAY runs at the header's rate, ~60.1Hz.
+        let plays = player.peek(0x0201);
+        assert!((59..=61).contains(&plays), "{} plays", plays);
+
+        //A 440Hz square: count its upward zero crossings.
+        assert!(pcm.iter().any(|&s| s.abs() > 0.05));
+        let crossings = pcm.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        assert!((430..=450).contains(&crossings), "{} crossings", crossings);
+
+        //Restarting resets the machine.
+        player.start(0);
+        assert_eq!(player.peek(0x0200), 0);
+        assert_eq!(player.peek(0x0201), 0);
+    }
+
+    #[test]
+    fn test_wav() {
+        let wav = wav(&[0, 1, -1], 44100);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[44..], &[0, 0, 1, 0, 0xFF, 0xFF]);
+    }
+}
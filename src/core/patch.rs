@@ -0,0 +1,325 @@
+/* Soft-patching of ROM images: hacks and translations are passed around
+ *  as patches against the original dump, and applied in memory at load.
+ * Author: Spalynx
+ *--------------Formats------------------------------------------------
+ * IPS      "PATCH", then records of 24-bit offset, 16-bit size, data.
+ *           A size of 0 is an RLE record: 16-bit count, 1 byte value.
+ *           "EOF", optionally followed by a 24-bit truncation length.
+ * UPS      "UPS1", source/target sizes, then XOR hunks. Footer holds
+ *           source, target and patch CRC32s.
+ * BPS      "BPS1", source/target sizes, metadata, then copy/read
+ *           commands. Same CRC32 footer as UPS.
+ *---------------------------------------------------------------------
+ * IPS has no checksums, so a patch for the wrong dump applies silently.
+ * UPS/BPS refuse to apply to anything but the exact source image.
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::core::hash::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+/// A patch file, loaded and identified but not yet applied.
+#[derive(Clone, Debug)]
+pub struct Patch {
+    pub format: PatchFormat,
+    pub name: String,       //File name, or "IPS patch" etc from memory.
+    pub data: Vec<u8>,
+}
+
+impl Patch {
+    /// Identifies a patch from its magic bytes.
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Patch> {
+        let format = if data.starts_with(b"PATCH") {
+            PatchFormat::Ips
+        } else if data.starts_with(b"UPS1") {
+            PatchFormat::Ups
+        } else if data.starts_with(b"BPS1") {
+            PatchFormat::Bps
+        } else {
+            return Err(bad("Not an IPS, UPS or BPS patch."));
+        };
+        let name = match format {
+            PatchFormat::Ips => "IPS patch",
+            PatchFormat::Ups => "UPS patch",
+            PatchFormat::Bps => "BPS patch",
+        };
+        Ok(Patch { format, name: name.to_string(), data })
+    }
+
+    /// Reads a patch from a file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Patch> {
+        let path = path.as_ref();
+        let mut patch = Patch::from_bytes(fs::read(path)?)
+            .map_err(|e| bad(&format!("{}: {}", path.display(), e)))?;
+        patch.name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        Ok(patch)
+    }
+
+    /// Applies the patch to a whole image, header included.
+    pub fn apply(&self, image: &[u8]) -> io::Result<Vec<u8>> {
+        let result = match self.format {
+            PatchFormat::Ips => apply_ips(&self.data, image),
+            PatchFormat::Ups => apply_ups(&self.data, image),
+            PatchFormat::Bps => apply_bps(&self.data, image),
+        };
+        result.map_err(|e| bad(&format!("{}: {}", self.name, e)))
+    }
+}
+
+/// Patches sitting next to a ROM with the same name, ie: for zelda.nes,
+///  zelda.ips, zelda.ups and zelda.bps, in that order.
+pub fn find_patches(rom: &Path) -> Vec<PathBuf> {
+    ["ips", "ups", "bps"].iter()
+        .map(|ext| rom.with_extension(ext))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn bad(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn truncated() -> io::Error {
+    bad("patch is truncated.")
+}
+
+/// Bounds checked reads through a patch.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let b = *self.data.get(self.pos).ok_or_else(truncated)?;
+        self.pos += 1;
+        Ok(b)
+    }
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let out = self.pos.checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(truncated)?;
+        self.pos += n;
+        Ok(out)
+    }
+    /// Big endian, as IPS has it.
+    fn be(&mut self, n: usize) -> io::Result<usize> {
+        Ok(self.bytes(n)?.iter().fold(0, |acc, &b| (acc << 8) | b as usize))
+    }
+    /// UPS/BPS variable length number: 7 bits at a time, with the
+    ///  continuation folded into the value so every encoding is unique.
+    fn varint(&mut self) -> io::Result<usize> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let b = self.byte()?;
+            value = value.checked_add((b & 0x7F) as usize * shift).ok_or_else(truncated)?;
+            if b & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or_else(truncated)?;
+            value = value.checked_add(shift).ok_or_else(truncated)?;
+        }
+    }
+}
+
+fn apply_ips(patch: &[u8], image: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = image.to_vec();
+    let mut cur = Cursor { data: patch, pos: 5 };
+
+    loop {
+        if cur.data.get(cur.pos..cur.pos + 3) == Some(b"EOF") {
+            cur.pos += 3;
+            break;
+        }
+        let offset = cur.be(3)?;
+        let size   = cur.be(2)?;
+        let (count, fill) = if size == 0 {
+            (cur.be(2)?, None)
+        } else {
+            (size, Some(cur.bytes(size)?))
+        };
+
+        //Records past the end grow the image.
+        if out.len() < offset + count {
+            out.resize(offset + count, 0);
+        }
+        match fill {
+            Some(data) => out[offset..offset + count].copy_from_slice(data),
+            None => {
+                let value = cur.byte()?;
+                out[offset..offset + count].iter_mut().for_each(|b| *b = value);
+            },
+        }
+    }
+
+    //Lunar IPS extension: a final length to truncate the image to.
+    if cur.data.len() >= cur.pos + 3 {
+        let len = cur.be(3)?;
+        out.truncate(len);
+    }
+    Ok(out)
+}
+
+/// Checks the CRC32 footer shared by UPS and BPS against the patch and
+///  its source, and returns the CRC32 the target should come out with.
+fn check_footer(patch: &[u8], image: &[u8]) -> io::Result<u32> {
+    if patch.len() < 16 {
+        return Err(truncated());
+    }
+    let le = |at: usize| u32::from_le_bytes([patch[at], patch[at+1], patch[at+2], patch[at+3]]);
+    let end = patch.len() - 12;
+    let (source, target, own) = (le(end), le(end + 4), le(end + 8));
+
+    let actual = crc32(&patch[..end + 8]);
+    if actual != own {
+        return Err(bad(&format!("patch CRC32 mismatch, expected {:08X} got {:08X}. \
+                                 The patch file is corrupt.", own, actual)));
+    }
+    let actual = crc32(image);
+    if actual != source {
+        return Err(bad(&format!("source CRC32 mismatch, expected {:08X} got {:08X}. \
+                                 This patch is for a different dump.", source, actual)));
+    }
+    Ok(target)
+}
+
+/// Largest target a UPS/BPS patch may ask for. The size is read before the
+///  target CRC32 can be checked, so it's bounded up front rather than
+///  allocated blindly. The biggest NES images are a few megabytes.
+const MAX_TARGET: usize = 64 << 20;
+
+/// Reads the source/target sizes shared by UPS and BPS, checks the source
+///  size, and returns the target size.
+fn read_sizes(cur: &mut Cursor, image: &[u8]) -> io::Result<usize> {
+    let source_size = cur.varint()?;
+    let target_size = cur.varint()?;
+    if source_size != image.len() {
+        return Err(bad(&format!("source is {} bytes, patch expects {}.",
+                                image.len(), source_size)));
+    }
+    if target_size > MAX_TARGET {
+        return Err(bad(&format!("target is {} bytes, more than the {} allowed.",
+                                target_size, MAX_TARGET)));
+    }
+    Ok(target_size)
+}
+
+fn check_target(out: &[u8], expected: u32) -> io::Result<()> {
+    let actual = crc32(out);
+    if actual != expected {
+        return Err(bad(&format!("target CRC32 mismatch, expected {:08X} got {:08X}.",
+                                expected, actual)));
+    }
+    Ok(())
+}
+
+fn apply_ups(patch: &[u8], image: &[u8]) -> io::Result<Vec<u8>> {
+    let target_crc = check_footer(patch, image)?;
+    let end = patch.len() - 12;
+    let mut cur = Cursor { data: &patch[..end], pos: 4 };
+
+    let target_size = read_sizes(&mut cur, image)?;
+
+    let mut out = image.to_vec();
+    out.resize(target_size, 0);
+
+    //Hunks: skip ahead, then XOR until a zero byte.
+    let mut at: usize = 0;
+    while cur.pos < end {
+        at = at.checked_add(cur.varint()?).ok_or_else(truncated)?;
+        loop {
+            let x = cur.byte()?;
+            if x == 0 {
+                at = at.saturating_add(1);
+                break;
+            }
+            if at < out.len() {
+                out[at] ^= x;
+            }
+            at = at.saturating_add(1);
+        }
+    }
+
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
+
+fn apply_bps(patch: &[u8], image: &[u8]) -> io::Result<Vec<u8>> {
+    let target_crc = check_footer(patch, image)?;
+    let end = patch.len() - 12;
+    let mut cur = Cursor { data: &patch[..end], pos: 4 };
+
+    let target_size = read_sizes(&mut cur, image)?;
+    let metadata = cur.varint()?;
+    cur.bytes(metadata)?;
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_rel: isize = 0;
+    let mut target_rel: isize = 0;
+
+    //Signed relative offsets: low bit is the sign.
+    fn relative(cur: &mut Cursor) -> io::Result<isize> {
+        let d = cur.varint()?;
+        let n = (d >> 1) as isize;
+        Ok(if d & 1 == 1 { -n } else { n })
+    }
+
+    while cur.pos < end {
+        let data = cur.varint()?;
+        let len = (data >> 2) + 1;
+        if out.len() + len > target_size {
+            return Err(bad("patch writes past the end of the target."));
+        }
+        match data & 3 {
+            //SourceRead: same bytes, same place as the source.
+            0 => {
+                let at = out.len();
+                let src = image.get(at..at + len).ok_or_else(truncated)?;
+                out.extend_from_slice(src);
+            },
+            //TargetRead: new bytes from the patch.
+            1 => out.extend_from_slice(cur.bytes(len)?),
+            //SourceCopy: bytes from anywhere in the source.
+            2 => {
+                source_rel = source_rel.checked_add(relative(&mut cur)?)
+                    .filter(|&rel| rel >= 0)
+                    .ok_or_else(truncated)?;
+                let at = source_rel as usize;
+                let src = at.checked_add(len)
+                    .and_then(|end| image.get(at..end))
+                    .ok_or_else(truncated)?;
+                out.extend_from_slice(src);
+                source_rel += len as isize;
+            },
+            //TargetCopy: bytes already written, may overlap (RLE).
+            _ => {
+                target_rel = target_rel.checked_add(relative(&mut cur)?)
+                    .ok_or_else(truncated)?;
+                if target_rel < 0 || target_rel as usize >= out.len() {
+                    return Err(truncated());
+                }
+                for _ in 0..len {
+                    let b = out[target_rel as usize];
+                    out.push(b);
+                    target_rel += 1;
+                }
+            },
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(bad(&format!("target is {} bytes, patch expects {}.",
+                                out.len(), target_size)));
+    }
+    check_target(&out, target_crc)?;
+    Ok(out)
+}
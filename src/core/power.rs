@@ -0,0 +1,90 @@
+/* Power-on state of the console.
+ * Real hardware doesn't come up clean: RAM, OAM, palette RAM and a few
+ *  registers hold whatever the chips settled on. Some games (and plenty of
+ *  homebrew bugs) depend on that, so the init policy is configurable.
+ * Author: Spalynx
+ */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The policy used to fill RAM and registers when the NES is switched on.
+/// **Random** carries its seed, so any run can be reproduced later.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PowerOn {
+    /// Everything zeroed. Friendliest to test ROMs, and the old behaviour.
+    #[default]
+    Zeros,
+    /// Everything $FF.
+    Ones,
+    /// RAM in the $00 x4, $FF x4 stripes commonly seen on real consoles,
+    ///  registers at their documented values.
+    Pattern,
+    /// Seeded pseudo-random fill of RAM and the unspecified registers.
+    Random(u64),
+}
+
+impl PowerOn {
+    /// A random power-on, seeded from the clock.
+    /// Grab the seed from the result (or the log) to replay the run.
+    pub fn random() -> PowerOn {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x5EED);
+        PowerOn::Random(seed)
+    }
+
+    /// The seed of a random power-on, if there is one.
+    pub fn seed(&self) -> Option<u64> {
+        match *self {
+            PowerOn::Random(seed) => Some(seed),
+            _ => None,
+        }
+    }
+
+    /// Creates a byte source that the hardware devices pull their state from.
+    pub fn source(&self) -> PowerSource {
+        //xorshift can't have a zero state.
+        let state = self.seed().unwrap_or(0) ^ 0x9E37_79B9_7F4A_7C15;
+        PowerSource {
+            init:  *self,
+            state: if state == 0 {1} else {state},
+        }
+    }
+}
+
+/// Hands out power-on bytes according to a PowerOn policy.
+/// Every device pulls from the same source, in a fixed order, so a seed
+///  always produces the same console.
+pub struct PowerSource {
+    init:  PowerOn,
+    state: u64,
+}
+
+impl PowerSource {
+    /// Value for a single unspecified register.
+    pub fn byte(&mut self) -> u8 {
+        match self.init {
+            PowerOn::Zeros | PowerOn::Pattern => 0x00,
+            PowerOn::Ones => 0xFF,
+            PowerOn::Random(_) => self.next() as u8,
+        }
+    }
+
+    /// Fills a block of memory (RAM, OAM, palettes...).
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = match self.init {
+                PowerOn::Pattern => if (i / 4) % 2 == 0 {0x00} else {0xFF},
+                _ => self.byte(),
+            };
+        }
+    }
+
+    //xorshift64*, good enough for garbage RAM.
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32
+    }
+}
@@ -0,0 +1,251 @@
+/* Emulates the NES 2C02 PPU, from the CPU's side of the bus.
+ * Author: Spalynx
+ *--------------Registers ($2000-$2007, mirrored up to $3FFF)----------
+ * $2000     PPUCTRL      NMI enable, sprite size, pattern tables, increment.
+ * $2001     PPUMASK      Color emphasis, sprite/background enable.
+ * $2002     PPUSTATUS    VBlank, sprite 0 hit, overflow. Reading clears.
+ * $2003     OAMADDR      OAM read/write address.
+ * $2004     OAMDATA      OAM data port.
+ * $2005     PPUSCROLL    Fine scroll position (two writes: X, Y).
+ * $2006     PPUADDR      VRAM address (two writes: high, low).
+ * $2007     PPUDATA      VRAM data port, reads are buffered.
+ *--------------PPU Memory Map-----------------------------------------
+ * $0000-$1FFF     =      Pattern tables (CHR, on the cart).
+ * $2000-$2FFF     =      Nametables (2kb CIRAM, mirrored by the cart).
+ * $3F00-$3FFF     =      Palette RAM.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/PPU_registers
+ */
+
+pub use crate::core::*;
+
+/// How the cart wires the four logical nametables onto the 2kb of CIRAM.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleLow,
+    SingleHigh,
+    FourScreen,
+    /// Any CIRAM page (0/1) for each of the four, ie: MMC5's $5105.
+    Mapped([u8; 4]),
+}
+
+#[allow(non_snake_case)]
+pub struct PPU {
+    pub ctrl:       u8,         //$2000
+    pub mask:       u8,         //$2001
+    pub status:     u8,         //$2002
+    pub oam_addr:   u8,         //$2003
+    pub v:          u16,        //Current VRAM address (15 bits).
+    pub t:          u16,        //Temporary VRAM address (15 bits).
+    pub x:          u8,         //Fine X scroll (3 bits).
+    pub w:          bool,       //First/second write toggle.
+    pub buffer:     u8,         //PPUDATA read buffer.
+    pub bus:        u8,         //Last value put on the register bus.
+    pub mirroring:  Mirroring,
+    pub OAM:        [u8; 256],
+    pub PALETTE:    [u8; 32],
+    pub VRAM:       [u8; 0x1000],   //2kb CIRAM, plus 2kb for four-screen.
+}
+
+impl Default for PPU {
+    fn default() -> PPU { PPU::new() }
+}
+
+impl PPU {
+    pub fn new() -> PPU {
+        PPU {
+            ctrl:       0,
+            mask:       0,
+            status:     0,
+            oam_addr:   0,
+            v:          0,
+            t:          0,
+            x:          0,
+            w:          false,
+            buffer:     0,
+            bus:        0,
+            mirroring:  Mirroring::Horizontal,
+            OAM:        [0; 256],
+            PALETTE:    [0; 32],
+            VRAM:       [0; 0x1000],
+        }
+    }
+
+    /// Power-up state, per nesdev's PPU_power_up_state.
+    /// PPUCTRL, PPUMASK, OAMADDR and the scroll/address latches are cleared.
+    /// VBlank and overflow in PPUSTATUS, the read buffer, OAM, palettes and
+    ///  nametables are all whatever the chip settles on.
+    pub fn power_on(&mut self, src: &mut PowerSource) {
+        self.ctrl       = 0;
+        self.mask       = 0;
+        self.status     = src.byte() & 0b1010_0000;
+        self.oam_addr   = 0;
+        self.v          = 0;
+        self.t          = 0;
+        self.x          = 0;
+        self.w          = false;
+        self.buffer     = src.byte();
+        self.bus        = 0;
+        src.fill(&mut self.OAM);
+        src.fill(&mut self.PALETTE);
+        src.fill(&mut self.VRAM);
+    }
+
+    /// Reads a PPU register, given a CPU address in $2000-$3FFF.
+    pub fn read_register(&mut self, address: u16, cart: &mut dyn MAP) -> u8 {
+        let val = match address & 7 {
+            2 => {
+                let status = (self.status & 0xE0) | (self.bus & 0x1F);
+                self.status &= !0x80;   //Reading clears VBlank...
+                self.w = false;         //...and the write toggle.
+                status
+            },
+            4 => self.OAM[self.oam_addr as usize],
+            7 => {
+                let address = self.v & 0x3FFF;
+                cart.ppu_bus(address);
+                let val = if address >= 0x3F00 {
+                    //Palettes skip the buffer, but it still gets filled
+                    // with the nametable "underneath" them.
+                    self.buffer = self.read_vram(address - 0x1000, cart);
+                    self.read_vram(address, cart)
+                }
+                else {
+                    let val = self.buffer;
+                    self.buffer = self.fetch_vram(address, cart);
+                    val
+                };
+                self.increment_v();
+                cart.ppu_bus(self.v & 0x3FFF);
+                val
+            },
+            _ => self.bus,          //Write only registers, open bus.
+        };
+        self.bus = val;
+        val
+    }
+
+    /// Returns what read_register would, without clearing VBlank, flipping
+    ///  the write toggle, or moving the PPUDATA buffer and address along.
+    pub fn peek_register(&self, address: u16, cart: &dyn MAP) -> u8 {
+        match address & 7 {
+            2 => (self.status & 0xE0) | (self.bus & 0x1F),
+            4 => self.OAM[self.oam_addr as usize],
+            7 => {
+                let address = self.v & 0x3FFF;
+                if address >= 0x3F00 { self.read_vram(address, cart) }
+                else { self.buffer }
+            },
+            _ => self.bus,
+        }
+    }
+
+    /// Writes a PPU register, given a CPU address in $2000-$3FFF.
+    pub fn write_register(&mut self, address: u16, val: u8, cart: &mut dyn MAP) {
+        self.bus = val;
+        match address & 7 {
+            0 => {
+                self.ctrl = val;
+                self.t = (self.t & 0xF3FF) | (((val & 3) as u16) << 10);
+            },
+            1 => self.mask = val,
+            3 => self.oam_addr = val,
+            4 => {
+                self.OAM[self.oam_addr as usize] = val;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            },
+            5 => {
+                if !self.w {
+                    self.t = (self.t & 0xFFE0) | (val >> 3) as u16;
+                    self.x = val & 7;
+                }
+                else {
+                    self.t = (self.t & 0x8C1F) | (((val & 7) as u16) << 12)
+                                               | (((val & 0xF8) as u16) << 2);
+                }
+                self.w = !self.w;
+            },
+            6 => {
+                if !self.w {
+                    self.t = (self.t & 0x80FF) | (((val & 0x3F) as u16) << 8);
+                }
+                else {
+                    self.t = (self.t & 0xFF00) | val as u16;
+                    self.v = self.t;
+                    //Outside of rendering, v sits on the PPU's address bus.
+                    cart.ppu_bus(self.v & 0x3FFF);
+                }
+                self.w = !self.w;
+            },
+            7 => {
+                let address = self.v & 0x3FFF;
+                cart.ppu_bus(address);
+                self.write_vram(address, val, cart);
+                self.increment_v();
+                cart.ppu_bus(self.v & 0x3FFF);
+            },
+            _ => {},                //PPUSTATUS is read only.
+        }
+    }
+
+    /// Reads from the PPU's own 14-bit address space.
+    pub fn read_vram(&self, address: u16, cart: &dyn MAP) -> u8 {
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => cart.get_chr(address),
+            0x2000..=0x3EFF => cart.nametable(address)
+                .unwrap_or_else(|| self.VRAM[self.nametable_index(address, cart)]),
+            _               => self.PALETTE[palette_index(address)],
+        }
+    }
+    /// read_vram(), as a real fetch: the cart sees it, and may act on it.
+    pub fn fetch_vram(&self, address: u16, cart: &mut dyn MAP) -> u8 {
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => cart.fetch_chr(address),
+            0x2000..=0x3EFF => match cart.fetch_nametable(address) {
+                Some(val) => val,
+                None => self.VRAM[self.nametable_index(address, cart)],
+            },
+            _               => self.read_vram(address, cart),
+        }
+    }
+    /// Writes to the PPU's own 14-bit address space.
+    pub fn write_vram(&mut self, address: u16, val: u8, cart: &mut dyn MAP) {
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => cart.set_chr(address, val),
+            0x2000..=0x3EFF => if !cart.set_nametable(address, val) {
+                self.VRAM[self.nametable_index(address, cart)] = val;
+            },
+            _               => self.PALETTE[palette_index(address)] = val,
+        }
+    }
+
+    /// Folds a $2000-$3EFF address down onto CIRAM, per the cart's mirroring.
+    /// Mappers that switch mirroring get the last word.
+    fn nametable_index(&self, address: u16, cart: &dyn MAP) -> usize {
+        let offset = (address & 0x3FF) as usize;
+        let table  = ((address >> 10) & 3) as usize;
+        let page = match cart.mirroring().unwrap_or(self.mirroring) {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical   => table & 1,
+            Mirroring::SingleLow  => 0,
+            Mirroring::SingleHigh => 1,
+            Mirroring::FourScreen => table,
+            Mirroring::Mapped(pages) => pages[table] as usize & 1,
+        };
+        page * 0x400 + offset
+    }
+
+    //PPUCTRL bit 2 picks between going across (1) or down (32).
+    fn increment_v(&mut self) {
+        let step = if self.ctrl & 0b100 == 0 {1} else {32};
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+}
+
+//$3F10/$3F14/$3F18/$3F1C are mirrors of the background entries below them.
+fn palette_index(address: u16) -> usize {
+    let index = (address & 0x1F) as usize;
+    if index >= 0x10 && index & 3 == 0 { index - 0x10 } else { index }
+}
@@ -0,0 +1,178 @@
+/* Mapper registry: every mapper number the emulator knows about, what it
+ *  is called, which boards carry it, and how to build one.
+ * new_map looks here rather than in a hard-coded match, so downstream
+ *  crates can register boards of their own (homebrew, or something being
+ *  prototyped) without touching soliloquy.
+ * Author: Spalynx
+ *--------------Lookup order-------------------------------------------
+ * 1. Entries added with register_mapper, newest first. A custom entry
+ *     with a built-in number replaces it.
+ * 2. The built-in table, in order, so a submapper specific entry has to
+ *     come before the catch-all for its number.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/Mapper
+ */
+
+use std::sync::RwLock;
+use ::log::*;
+use crate::core::cartridge::CART;
+use crate::core::mapper::*;
+use crate::core::mmc5::Mmc5;
+use crate::core::vrc6::Vrc6;
+use crate::core::vrc7::Vrc7;
+use crate::core::n163::N163;
+
+/// Builds a mapper around a cart.
+pub type MapperFn = fn(Box<CART>) -> Box<dyn MAP>;
+
+/// One registry entry.
+#[derive(Clone, Copy, Debug)]
+pub struct MapperInfo {
+    pub number:     u16,
+    /// Submappers this entry covers, empty for all of them.
+    pub submappers: &'static [u8],
+    /// Board names, without their NES-/HVC-/UNL-/BMC-/BTL- prefix.
+    pub boards:     &'static [&'static str],
+    pub name:       &'static str,
+    /// None for mappers that are known by name, but not emulated.
+    pub new:        Option<MapperFn>,
+}
+impl MapperInfo {
+    /// True if this entry is the one for number.submapper.
+    pub fn covers(&self, number: u16, submapper: u8) -> bool {
+        self.number == number && (self.submappers.is_empty() || self.submappers.contains(&submapper))
+    }
+    /// The submapper a board name implies, the first one listed.
+    pub fn submapper(&self) -> u8 {
+        self.submappers.first().copied().unwrap_or(0)
+    }
+}
+
+/// Mappers registered at runtime, see register_mapper.
+static CUSTOM: RwLock<Vec<MapperInfo>> = RwLock::new(Vec::new());
+
+/// The mappers soliloquy ships with, plus a few well known ones it only
+///  has names for.
+static BUILTIN: &[MapperInfo] = &[
+    MapperInfo { number: 0, submappers: &[], name: "NROM",
+        boards: &["NROM", "NROM-128", "NROM-256", "RROM", "RROM-128"],
+        new: Some(|cart| Box::new(Nrom{cart})) },
+    MapperInfo { number: 1, submappers: &[5], name: "MMC1 (SEROM)",
+        boards: &["SEROM"],
+        new: Some(|cart| Box::new(MMC1::new(cart))) },
+    MapperInfo { number: 1, submappers: &[], name: "MMC1 (SxROM)",
+        boards: &["SAROM", "SBROM", "SCROM", "SGROM", "SKROM", "SLROM", "SL1ROM",
+                  "SNROM", "SOROM", "SUROM", "SXROM"],
+        new: Some(|cart| Box::new(MMC1::new(cart))) },
+    MapperInfo { number: 2, submappers: &[], name: "UxROM",
+        boards: &["UNROM", "UOROM"],
+        new: Some(|cart| Box::new(Uxrom{cart, bank: 0})) },
+    MapperInfo { number: 3, submappers: &[], name: "CNROM",
+        boards: &["CNROM"],
+        new: Some(|cart| Box::new(Cnrom{cart, bank: 0})) },
+    MapperInfo { number: 4, submappers: &[], name: "MMC3/MMC6 (TxROM)",
+        boards: &["TBROM", "TEROM", "TFROM", "TGROM", "TKROM", "TLROM", "TL1ROM",
+                  "TNROM", "TR1ROM", "TSROM", "TVROM", "B4"],
+        new: Some(|cart| Box::new(MMC3::new(cart))) },
+    MapperInfo { number: 5, submappers: &[], name: "MMC5 (ExROM)",
+        boards: &["EKROM", "ELROM", "ETROM", "EWROM"],
+        new: Some(|cart| Box::new(Mmc5::new(cart))) },
+    MapperInfo { number: 7, submappers: &[], name: "AxROM",
+        boards: &["AMROM", "ANROM", "AN1ROM", "AOROM"],
+        new: Some(|cart| Box::new(Axrom{cart, bank: 0})) },
+    MapperInfo { number: 9, submappers: &[], name: "MMC2 (PxROM)",
+        boards: &["PNROM", "PEEOROM"],
+        new: Some(|cart| Box::new(MMC2::new(cart, false))) },
+    MapperInfo { number: 10, submappers: &[], name: "MMC4 (FxROM)",
+        boards: &["FJROM", "FKROM"],
+        new: Some(|cart| Box::new(MMC2::new(cart, true))) },
+    MapperInfo { number: 11, submappers: &[], name: "Color Dreams",
+        boards: &[],
+        new: Some(|cart| Box::new(ColorDreams{cart, bank: 0})) },
+    MapperInfo { number: 13, submappers: &[], name: "CPROM",
+        boards: &["CPROM"], new: None },
+    MapperInfo { number: 16, submappers: &[], name: "Bandai FCG",
+        boards: &[], new: None },
+    MapperInfo { number: 18, submappers: &[], name: "Jaleco SS88006",
+        boards: &[], new: None },
+    MapperInfo { number: 19, submappers: &[], name: "Namco 163",
+        boards: &[],
+        new: Some(|cart| Box::new(N163::new(cart))) },
+    //The FDS boots from a disk image and BIOS, see NES::from_fds.
+    MapperInfo { number: 20, submappers: &[], name: "Famicom Disk System",
+        boards: &[], new: None },
+    MapperInfo { number: 21, submappers: &[], name: "VRC4a/VRC4c",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc4::new(cart))) },
+    MapperInfo { number: 22, submappers: &[], name: "VRC2a",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc4::new(cart))) },
+    MapperInfo { number: 23, submappers: &[], name: "VRC2b/VRC4e",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc4::new(cart))) },
+    MapperInfo { number: 24, submappers: &[], name: "VRC6a",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc6::new(cart))) },
+    MapperInfo { number: 25, submappers: &[], name: "VRC4b/VRC4d",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc4::new(cart))) },
+    MapperInfo { number: 26, submappers: &[], name: "VRC6b",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc6::new(cart))) },
+    MapperInfo { number: 34, submappers: &[1], name: "NINA-001",
+        boards: &[],
+        new: Some(|cart| Box::new(Bnrom::new(cart))) },
+    MapperInfo { number: 34, submappers: &[2], name: "BNROM",
+        boards: &["BNROM"],
+        new: Some(|cart| Box::new(Bnrom::new(cart))) },
+    MapperInfo { number: 34, submappers: &[], name: "BNROM/NINA-001",
+        boards: &[],
+        new: Some(|cart| Box::new(Bnrom::new(cart))) },
+    MapperInfo { number: 66, submappers: &[], name: "GxROM",
+        boards: &["GNROM", "MHROM"],
+        new: Some(|cart| Box::new(Gxrom{cart, bank: 0})) },
+    MapperInfo { number: 69, submappers: &[], name: "Sunsoft FME-7",
+        boards: &[],
+        new: Some(|cart| Box::new(Fme7::new(cart))) },
+    MapperInfo { number: 71, submappers: &[], name: "Camerica",
+        boards: &[],
+        new: Some(|cart| Box::new(Camerica::new(cart))) },
+    MapperInfo { number: 85, submappers: &[], name: "VRC7",
+        boards: &[],
+        new: Some(|cart| Box::new(Vrc7::new(cart))) },
+    MapperInfo { number: 206, submappers: &[], name: "Namco 118",
+        boards: &[], new: None },
+];
+
+/// Adds a mapper at runtime, ahead of everything already registered.
+/// ie: register_mapper(MapperInfo { number: 3840, submappers: &[],
+///      boards: &["MYBOARD"], name: "My board", new: Some(MyBoard::boxed) });
+pub fn register_mapper(info: MapperInfo) {
+    debug!("MAPPER   -> Registered #{} {}.", info.number, info.name);
+    CUSTOM.write().unwrap_or_else(|e| e.into_inner()).push(info);
+}
+
+/// Every registry entry, in lookup order.
+pub fn mappers() -> Vec<MapperInfo> {
+    let custom = CUSTOM.read().unwrap_or_else(|e| e.into_inner());
+    custom.iter().rev().chain(BUILTIN.iter()).cloned().collect()
+}
+
+/// The entry for mapper number.submapper, if there is one.
+pub fn find_mapper(number: u16, submapper: u8) -> Option<MapperInfo> {
+    mappers().into_iter().find(|m| m.covers(number, submapper))
+}
+
+/// The entry carrying a board name, prefixes and case aside.
+pub fn find_board(board: &str) -> Option<MapperInfo> {
+    let board = board.to_ascii_uppercase();
+    let name = ["NES-", "HVC-", "UNL-", "BMC-", "BTL-"].iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(&board);
+    mappers().into_iter().find(|m| m.boards.contains(&name))
+}
+
+/// The human readable name of mapper number.submapper, for display.
+pub fn mapper_name(number: u16, submapper: u8) -> Option<&'static str> {
+    find_mapper(number, submapper).map(|m| m.name)
+}
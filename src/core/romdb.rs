@@ -0,0 +1,355 @@
+/* Built-in ROM database, for correcting bad iNES headers.
+ * Entries follow the layout of the NES 2.0 XML database (nes20db), and are
+ *  keyed by the CRC32/SHA-1 of the PRG+CHR payload. The 16 byte header and
+ *  trainer are left out of the hash, since the header is what's suspect.
+ * Author: Spalynx
+ *--------------Entry layout-------------------------------------------
+ * <game name="Title">
+ *   <rom      size="40960" crc32="1234ABCD" sha1="..."/>
+ *   <prgram   size="8192"/>    <prgnvram size="0"/>
+ *   <chrram   size="0"/>       <chrnvram size="0"/>
+ *   <pcb      mapper="1" submapper="0" mirroring="H" battery="1"
+ *             board="NES-SNROM"/>
+ *   <console  type="0" region="0"/>
+ * </game>
+ *---------------------------------------------------------------------
+ * Only the attributes that are present override the header. nes20db puts
+ *  the title in a comment inside <game>, which is picked up too, so its
+ *  file can be loaded as-is with GameDb::from_path.
+ * The bundled db/nes20db.xml is the default LoadOptions.database. It only
+ *  holds a few verified dumps, a full nes20db.xml can stand in for it.
+ */
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use crate::core::cartridge::*;
+use crate::core::hash::to_hex;
+use crate::core::ppu::Mirroring;
+
+/// One known-good dump.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DbEntry {
+    pub title:      String,
+    pub crc32:      u32,
+    pub sha1:       Option<[u8; 20]>,
+    pub mapper:     Option<u16>,
+    pub submapper:  Option<u8>,
+    pub board:      Option<String>,
+    pub mirroring:  Option<Mirroring>,
+    pub battery:    Option<bool>,
+    pub prg_ram:    Option<usize>,
+    pub prg_nvram:  Option<usize>,
+    pub chr_ram:    Option<usize>,
+    pub chr_nvram:  Option<usize>,
+    pub timing:     Option<Timing>,
+    pub console:    Option<Console>,
+}
+
+/// A header field the database disagreed with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    pub field:      &'static str,
+    pub header:     String,
+    pub database:   String,
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.header, self.database)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GameDb {
+    pub entries: Vec<DbEntry>,
+}
+
+static BUNDLED: OnceLock<Arc<GameDb>> = OnceLock::new();
+
+impl GameDb {
+    /// The database shipped inside the crate, parsed on first use.
+    pub fn bundled() -> Arc<GameDb> {
+        BUNDLED.get_or_init(|| {
+            let db = GameDb::from_xml(include_str!("db/nes20db.xml"))
+                .expect("Bundled ROM database is malformed.");
+            debug!("COMPLETE -> ROM database, {} entries.", db.entries.len());
+            Arc::new(db)
+        }).clone()
+    }
+
+    /// Loads an external database, ie: a full copy of nes20db.xml.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<GameDb> {
+        let db = GameDb::from_xml(&std::fs::read_to_string(path)?)?;
+        debug!("COMPLETE -> ROM database, {} entries.", db.entries.len());
+        Ok(db)
+    }
+
+    /// Parses the <game> entries out of a NES 2.0 XML style database.
+    pub fn from_xml(xml: &str) -> io::Result<GameDb> {
+        let mut db = GameDb::default();
+        let mut game: Option<DbEntry> = None;
+
+        for tag in Tags::new(xml) {
+            let tag = tag?;
+            if tag.name == "game" {
+                if tag.close {
+                    db.entries.extend(game.take());
+                }
+                else {
+                    let title = tag.attr("name").unwrap_or("").to_string();
+                    game = Some(DbEntry { title, ..DbEntry::default() });
+                }
+                continue;
+            }
+
+            let entry = match game.as_mut() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if tag.name == "!--" {
+                //nes20db: <!-- Some Directory\Title (U).nes -->
+                if entry.title.is_empty() {
+                    let text = tag.text.trim();
+                    let file = text.rsplit(['\\', '/']).next().unwrap_or(text);
+                    entry.title = file.trim_end_matches(".nes").to_string();
+                }
+            }
+            else if !tag.close {
+                read_field(entry, &tag)?;
+            }
+        }
+        Ok(db)
+    }
+
+    /// Looks a dump up by its PRG+CHR hashes. SHA-1 wins when the entry
+    ///  has one, CRC32 is the fallback.
+    pub fn find(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&DbEntry> {
+        self.entries.iter().find(|e| match e.sha1 {
+            Some(ref s) => s == sha1,
+            None        => e.crc32 == crc32,
+        })
+    }
+}
+
+/// Overrides the cart's header fields with a database entry.
+/// Returns the fields that actually changed.
+pub fn correct(cart: &mut CART, entry: &DbEntry) -> Vec<Correction> {
+    let mut fixes = Vec::new();
+
+    macro_rules! fix {
+        ($field:ident, $name:expr) => {
+            if let Some(val) = entry.$field.clone() {
+                if cart.$field != val {
+                    fixes.push(Correction {
+                        field:      $name,
+                        header:     format!("{:?}", cart.$field),
+                        database:   format!("{:?}", val),
+                    });
+                    cart.$field = val;
+                }
+            }
+        };
+    }
+    macro_rules! fix_as {
+        ($entry:ident, $field:ident, $name:expr) => {
+            if let Some(val) = entry.$entry.clone() {
+                if cart.$field != val {
+                    fixes.push(Correction {
+                        field:      $name,
+                        header:     format!("{:?}", cart.$field),
+                        database:   format!("{:?}", val),
+                    });
+                    cart.$field = val;
+                }
+            }
+        };
+    }
+
+    fix!(mapper,    "mapper");
+    fix!(submapper, "submapper");
+    fix!(mirroring, "mirroring");
+    fix!(battery,   "battery");
+    fix!(timing,    "timing");
+    fix!(console,   "console");
+    fix_as!(prg_ram,   prg_ram_size,   "PRG-RAM");
+    fix_as!(prg_nvram, prg_nvram_size, "PRG-NVRAM");
+    fix_as!(chr_ram,   chr_ram_size,   "CHR-RAM");
+    fix_as!(chr_nvram, chr_nvram_size, "CHR-NVRAM");
+
+    if cart.PRG_RAM.len() != cart.prg_ram_size + cart.prg_nvram_size {
+        cart.PRG_RAM = vec![0; cart.prg_ram_size + cart.prg_nvram_size];
+    }
+    fixes
+}
+
+fn read_field(entry: &mut DbEntry, tag: &Tag) -> io::Result<()> {
+    match tag.name.as_str() {
+        "rom" => {
+            if let Some(crc) = tag.attr("crc32") {
+                entry.crc32 = u32::from_str_radix(crc, 16).map_err(bad_data)?;
+            }
+            if let Some(sha) = tag.attr("sha1") {
+                entry.sha1 = Some(parse_sha1(sha)?);
+            }
+        },
+        "prgram"    => entry.prg_ram   = tag.num("size")?,
+        "prgnvram"  => entry.prg_nvram = tag.num("size")?,
+        "chrram"    => entry.chr_ram   = tag.num("size")?,
+        "chrnvram"  => entry.chr_nvram = tag.num("size")?,
+        "pcb" => {
+            entry.mapper    = tag.num("mapper")?;
+            entry.submapper = tag.num("submapper")?;
+            entry.battery   = tag.num::<u8>("battery")?.map(|b| b != 0);
+            entry.board     = tag.attr("board").map(|b| b.to_string());
+            entry.mirroring = match tag.attr("mirroring") {
+                Some("H") => Some(Mirroring::Horizontal),
+                Some("V") => Some(Mirroring::Vertical),
+                Some("4") => Some(Mirroring::FourScreen),
+                _ => None,
+            };
+        },
+        "console" => {
+            entry.timing = match tag.num::<u8>("region")? {
+                Some(0) => Some(Timing::Ntsc),
+                Some(1) => Some(Timing::Pal),
+                Some(2) => Some(Timing::Multi),
+                Some(3) => Some(Timing::Dendy),
+                _ => None,
+            };
+            entry.console = match tag.num::<u8>("type")? {
+                Some(0) => Some(Console::Nes),
+                Some(1) => Some(Console::VsSystem { ppu: 0, hardware: 0 }),
+                Some(2) => Some(Console::Playchoice),
+                Some(n) => Some(Console::Extended(n)),
+                None    => None,
+            };
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+fn parse_sha1(hex: &str) -> io::Result<[u8; 20]> {
+    let mut sha = [0u8; 20];
+    if hex.len() != 40 {
+        return Err(bad_data(format!("Bad SHA-1 \"{}\"", hex)));
+    }
+    for (i, byte) in sha.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i*2..i*2 + 2], 16).map_err(bad_data)?;
+    }
+    Ok(sha)
+}
+
+fn bad_data<E: ToString>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl fmt::Display for DbEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\" (CRC32: {:08X}", self.title, self.crc32)?;
+        if let Some(ref sha1) = self.sha1 {
+            write!(f, ", SHA-1: {}", to_hex(sha1))?;
+        }
+        if let Some(ref board) = self.board {
+            write!(f, ", board: {}", board)?;
+        }
+        write!(f, ")")
+    }
+}
+
+//~XML==========================================================================
+// Just enough XML for a flat database: tags, attributes and comments.
+
+struct Tag {
+    name:   String,         //"!--" for comments.
+    close:  bool,           //</name>
+    attrs:  Vec<(String, String)>,
+    text:   String,         //Comment body.
+}
+
+impl Tag {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+    fn num<T: std::str::FromStr>(&self, key: &str) -> io::Result<Option<T>> {
+        match self.attr(key) {
+            Some(v) => v.parse().map(Some)
+                        .map_err(|_| bad_data(format!("Bad number {}=\"{}\"", key, v))),
+            None => Ok(None),
+        }
+    }
+}
+
+struct Tags<'a> {
+    xml: &'a str,
+}
+
+impl<'a> Tags<'a> {
+    fn new(xml: &'a str) -> Tags<'a> {
+        Tags { xml }
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = io::Result<Tag>;
+
+    fn next(&mut self) -> Option<io::Result<Tag>> {
+        loop {
+            let start = self.xml.find('<')?;
+            let rest = &self.xml[start..];
+
+            if let Some(body) = rest.strip_prefix("<!--") {
+                let end = match body.find("-->") {
+                    Some(end) => end,
+                    None => return Some(Err(bad_data("Unterminated comment"))),
+                };
+                self.xml = &body[end + 3..];
+                return Some(Ok(Tag { name: "!--".into(), close: false,
+                                     attrs: Vec::new(), text: body[..end].to_string() }));
+            }
+
+            let end = match rest.find('>') {
+                Some(end) => end,
+                None => return Some(Err(bad_data("Unterminated tag"))),
+            };
+            let inner = &rest[1..end];
+            self.xml = &rest[end + 1..];
+
+            //Declarations and processing instructions aren't interesting.
+            if inner.starts_with('?') || inner.starts_with('!') {
+                continue;
+            }
+            return Some(parse_tag(inner));
+        }
+    }
+}
+
+fn parse_tag(inner: &str) -> io::Result<Tag> {
+    let close = inner.starts_with('/');
+    let inner = inner.trim_start_matches('/').trim_end_matches('/').trim();
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let mut tag = Tag { name: inner[..name_end].to_string(), close,
+                        attrs: Vec::new(), text: String::new() };
+
+    let mut rest = inner[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=').ok_or_else(|| bad_data(format!("Bad attribute in <{}>", inner)))?;
+        let key = rest[..eq].trim().to_string();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')
+            .ok_or_else(|| bad_data(format!("Unquoted attribute in <{}>", inner)))?;
+        let close = after[1..].find(quote)
+            .ok_or_else(|| bad_data(format!("Unterminated attribute in <{}>", inner)))?;
+        tag.attrs.push((key, unescape(&after[1..close + 1])));
+        rest = after[close + 2..].trim_start();
+    }
+    Ok(tag)
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"")
+     .replace("&apos;", "'").replace("&amp;", "&")
+}
@@ -0,0 +1,157 @@
+/* UNIF (.unf) images: a 32 byte header, then tagged chunks.
+ * Mostly multicarts and pirate boards, which have no iNES mapper number
+ *  of their own, only a board name.
+ * Author: Spalynx
+ *--------------Chunks-------------------------------------------------
+ * MAPR         Board name, null terminated, ie: "NES-SNROM".
+ * PRG0..PRGF   PRG-ROM, concatenated in order.
+ * CHR0..CHRF   CHR-ROM, concatenated in order.
+ * MIRR         0 H, 1 V, 2 single $2000, 3 single $2400, 4 four screen,
+ *               5 mapper controlled.
+ * BATR         Present if PRG-RAM is battery backed.
+ * TVCI         0 NTSC, 1 PAL, 2 either.
+ * VROR         Present if CHR is RAM, even with CHR chunks.
+ * NAME         Game title, null terminated.
+ *---------------------------------------------------------------------
+ * Anything else (READ, DINF, PCKn, CCKn, CTRL...) is skipped.
+ * https://wiki.nesdev.com/w/index.php/UNIF
+ */
+
+use std::io;
+use ::log::*;
+use crate::core::ppu::Mirroring;
+use crate::core::cartridge::Timing;
+use crate::core::registry::find_board;
+use crate::core::validate::{RomIssue, RomReport};
+
+/// The chunks of a UNIF image that a CART cares about.
+#[derive(Clone, Debug, Default)]
+pub struct Unif {
+    pub revision: u32,
+    pub board: String,
+    pub name: Option<String>,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub mirroring: Option<Mirroring>,   //None when the mapper controls it.
+    pub battery: bool,
+    pub timing: Option<Timing>,
+    pub chr_ram: bool,
+}
+
+/// True if the image starts with the UNIF magic.
+pub fn is_unif(image: &[u8]) -> bool {
+    image.starts_with(b"UNIF")
+}
+
+fn bad(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Null terminated (or not) chunk text.
+fn text(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+/// Splits a UNIF image into its chunks.
+pub fn parse(image: &[u8]) -> io::Result<Unif> {
+    if !is_unif(image) || image.len() < 32 {
+        return Err(bad("Not a UNIF image.".to_string()));
+    }
+    let mut unif = Unif {
+        revision: u32::from_le_bytes([image[4], image[5], image[6], image[7]]),
+        ..Unif::default()
+    };
+
+    //PRGn/CHRn chunks can come in any order, so gather them first.
+    let mut prg: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr: [Option<&[u8]>; 16] = [None; 16];
+
+    let mut pos = 32;
+    while pos + 8 <= image.len() {
+        let id = &image[pos..pos + 4];
+        let len = u32::from_le_bytes([image[pos+4], image[pos+5], image[pos+6], image[pos+7]]) as usize;
+        pos += 8;
+        let data = match image.get(pos..pos.saturating_add(len)) {
+            Some(data) => data,
+            None => return Err(bad(format!("UNIF chunk {} is truncated.",
+                                           String::from_utf8_lossy(id)))),
+        };
+        pos += len;
+
+        match id {
+            b"MAPR" => unif.board = text(data),
+            b"NAME" => unif.name = Some(text(data)),
+            b"BATR" => unif.battery = data.first() != Some(&0),
+            b"VROR" => unif.chr_ram = true,
+            b"MIRR" => unif.mirroring = match data.first() {
+                Some(0) => Some(Mirroring::Horizontal),
+                Some(1) => Some(Mirroring::Vertical),
+                Some(2) => Some(Mirroring::SingleLow),
+                Some(3) => Some(Mirroring::SingleHigh),
+                Some(4) => Some(Mirroring::FourScreen),
+                _ => None,
+            },
+            b"TVCI" => unif.timing = match data.first() {
+                Some(0) => Some(Timing::Ntsc),
+                Some(1) => Some(Timing::Pal),
+                Some(2) => Some(Timing::Multi),
+                _ => None,
+            },
+            _ if &id[..3] == b"PRG" || &id[..3] == b"CHR" => {
+                let n = match (id[3] as char).to_digit(16) {
+                    Some(n) => n as usize,
+                    None => {
+                        warn!("UNIF     -> Skipping chunk {}", String::from_utf8_lossy(id));
+                        continue;
+                    },
+                };
+                if &id[..3] == b"PRG" { prg[n] = Some(data); } else { chr[n] = Some(data); }
+            },
+            _ => debug!("UNIF     -> Skipping chunk {}", String::from_utf8_lossy(id)),
+        }
+    }
+
+    unif.prg = prg.iter().flatten().flat_map(|c| c.iter().cloned()).collect();
+    unif.chr = chr.iter().flatten().flat_map(|c| c.iter().cloned()).collect();
+
+    if unif.board.is_empty() {
+        return Err(bad("UNIF image has no MAPR (board name) chunk.".to_string()));
+    }
+    if unif.prg.is_empty() {
+        return Err(bad("UNIF image has no PRG chunks.".to_string()));
+    }
+    Ok(unif)
+}
+
+/// Looks a UNIF board name up in the mapper registry, for its (iNES mapper,
+///  submapper).
+pub fn board_mapper(board: &str) -> Option<(u16, u8)> {
+    find_board(board).map(|m| (m.number, m.submapper()))
+}
+
+/// There's no header to check the chunks against, but every board banks in
+///  8kb (or bigger) pieces, so a section that stops short of one was cut off.
+pub fn validate_chunks(unif: &Unif) -> RomReport {
+    let mut report = RomReport::default();
+    let expected = unif.prg.len().next_multiple_of(0x2000);
+    if expected != unif.prg.len() {
+        report.issues.push(RomIssue::PrgTruncated { expected, found: unif.prg.len() });
+    }
+    let expected = unif.chr.len().next_multiple_of(0x2000);
+    if expected != unif.chr.len() {
+        report.issues.push(RomIssue::ChrTruncated { expected, found: unif.chr.len() });
+    }
+    report
+}
+
+/// Pads short sections out to the next bank, PRG with $FF like iNES repair.
+pub fn repair_chunks(unif: &mut Unif, report: &RomReport) {
+    for issue in report.issues.iter() {
+        match *issue {
+            RomIssue::PrgTruncated { expected, .. } => unif.prg.resize(expected, 0xFF),
+            RomIssue::ChrTruncated { expected, .. } => unif.chr.resize(expected, 0x00),
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,210 @@
+/* Validation of iNES/NES 2.0 images, before they're trusted as a CART.
+ * Plenty of dumps in the wild have been through old tools that scribbled
+ *  over the header, or were cut short/padded out by bad transfers.
+ * Author: Spalynx
+ *--------------Checks-------------------------------------------------
+ * Size mismatches      PRG/CHR shorter than the header says.
+ * Trailing data        Bytes left over after the last section.
+ * Dirty headers        Junk in bytes 7-15, ie: "DiskDude!".
+ * Impossible mappers   Mapper numbers in unallocated NES 2.0 planes.
+ * Missing CHR          No CHR-ROM where there should be some, or no CHR
+ *                       memory of any kind.
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/INES#Variant_comparison
+ */
+
+use std::fmt;
+use crate::core::cartridge::*;
+
+/// What to do with a ROM that fails validation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Validation {
+    /// Log the issues and load it as-is. The old behaviour.
+    #[default]
+    Warn,
+    /// Refuse to load anything that isn't clean.
+    Refuse,
+    /// Fix what can be fixed, then load it.
+    Repair,
+}
+
+/// A single problem found in a ROM image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RomIssue {
+    /// The file ends before the PRG-ROM the header asks for.
+    PrgTruncated { expected: usize, found: usize },
+    /// The file ends partway through the CHR-ROM.
+    ChrTruncated { expected: usize, found: usize },
+    /// Bytes left over after the last section.
+    TrailingData(usize),
+    /// Junk in bytes 7-15, shown as text.
+    DirtyHeader(String),
+    /// A mapper number no board has ever been assigned.
+    ImpossibleMapper(u16),
+    /// CHR-ROM in the header but none in the file, or (NES 2.0) neither
+    ///  CHR-ROM nor CHR-RAM.
+    MissingChr,
+}
+
+impl RomIssue {
+    /// Repair can't make up a mapper, everything else it can patch over.
+    pub fn repairable(&self) -> bool {
+        !matches!(*self, RomIssue::ImpossibleMapper(_))
+    }
+}
+
+impl fmt::Display for RomIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomIssue::PrgTruncated { expected, found } =>
+                write!(f, "PRG-ROM truncated, {} of {} bytes", found, expected),
+            RomIssue::ChrTruncated { expected, found } =>
+                write!(f, "CHR-ROM truncated, {} of {} bytes", found, expected),
+            RomIssue::TrailingData(n) =>
+                write!(f, "{} bytes of trailing data", n),
+            RomIssue::DirtyHeader(ref junk) =>
+                write!(f, "Dirty header, bytes 7-15: \"{}\"", junk),
+            RomIssue::ImpossibleMapper(n) =>
+                write!(f, "Impossible mapper number {}", n),
+            RomIssue::MissingChr =>
+                write!(f, "Missing CHR"),
+        }
+    }
+}
+
+/// Everything validation found in an image, and whether it was repaired.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomReport {
+    pub issues:     Vec<RomIssue>,
+    pub repaired:   bool,
+}
+
+impl RomReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for RomReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_clean() {
+            return write!(f, "ROM OK");
+        }
+        let issues: Vec<String> = self.issues.iter().map(|i| i.to_string()).collect();
+        write!(f, "{}{}", issues.join("; "), if self.repaired {" (repaired)"} else {""})
+    }
+}
+
+/// Checks an iNES/NES 2.0 image against its own header.
+/// The image must at least have the 16 byte header and "NES<EOF>" magic.
+pub fn validate(image: &[u8]) -> RomReport {
+    let mut report = RomReport::default();
+    let mut head = header(image);
+
+    if is_dirty(&head) {
+        let junk: String = head[7..16].iter()
+            .map(|&b| if (0x20..0x7F).contains(&b) { b as char } else { '.' })
+            .collect();
+        report.issues.push(RomIssue::DirtyHeader(junk));
+        //Everything else is judged as if the junk wasn't there.
+        clean_header(&mut head);
+    }
+    let cart = layout(head);
+
+    let prg = cart.section_sizes[1] as usize;
+    let chr = cart.section_sizes[2] as usize;
+    let mut offset = (16 + cart.section_sizes[0] as usize).min(image.len());
+
+    let found = prg.min(image.len() - offset);
+    if found < prg {
+        report.issues.push(RomIssue::PrgTruncated { expected: prg, found });
+    }
+    offset += found;
+
+    let found = chr.min(image.len() - offset);
+    if chr > 0 && found == 0 {
+        report.issues.push(RomIssue::MissingChr);
+    }
+    else if found < chr {
+        report.issues.push(RomIssue::ChrTruncated { expected: chr, found });
+    }
+    else if chr == 0 && cart.chr_ram_size + cart.chr_nvram_size == 0 {
+        report.issues.push(RomIssue::MissingChr);
+    }
+    offset += found;
+
+    //Misc ROMs (NES 2.0) and PlayChoice INST-ROM/PROM live after CHR.
+    let extra_allowed = cart.misc_roms > 0 || cart.console == Console::Playchoice;
+    if offset < image.len() && !extra_allowed {
+        report.issues.push(RomIssue::TrailingData(image.len() - offset));
+    }
+
+    //Planes 3-15 of NES 2.0's mapper space have never been allocated.
+    if cart.mapper >= 768 {
+        report.issues.push(RomIssue::ImpossibleMapper(cart.mapper));
+    }
+
+    report
+}
+
+/// Returns a copy of the image with every repairable issue fixed:
+/// dirty headers are zeroed, short sections are padded (PRG with $FF, like
+///  an unprogrammed EPROM), trailing data is dropped, and carts with no
+///  CHR at all are given 8kb of CHR-RAM.
+pub fn repair(image: &[u8], report: &RomReport) -> Vec<u8> {
+    let mut out = image.to_vec();
+
+    for issue in report.issues.iter() {
+        if let RomIssue::DirtyHeader(_) = *issue {
+            clean_header(&mut out[..16]);
+        }
+    }
+    let cart = layout(header(&out));
+    let end_prg = 16 + cart.section_sizes[0] as usize + cart.section_sizes[1] as usize;
+    let end_chr = end_prg + cart.section_sizes[2] as usize;
+
+    for issue in report.issues.iter() {
+        match *issue {
+            RomIssue::PrgTruncated { .. } => out.resize(end_prg, 0xFF),
+            RomIssue::ChrTruncated { .. } => out.resize(end_chr, 0x00),
+            RomIssue::TrailingData(_)     => out.truncate(end_chr),
+            RomIssue::MissingChr if cart.section_sizes[2] > 0 => {
+                out.resize(end_prg, 0xFF);
+                out.resize(end_chr, 0x00);
+            },
+            //NES 2.0 with no CHR memory at all, give it 8kb (64 << 7) CHR-RAM.
+            RomIssue::MissingChr => out[11] |= 0x07,
+            _ => {},
+        }
+    }
+    out
+}
+
+fn header(image: &[u8]) -> [u8; 16] {
+    let mut head = [0; 16];
+    head.copy_from_slice(&image[..16]);
+    head
+}
+
+//An "archaic" iNES header has the NES 2.0 bits set to %01, or garbage in
+// bytes 12-15. Both come from old dumping tools signing their work.
+fn is_dirty(head: &[u8; 16]) -> bool {
+    match head[7] & 0x0C {
+        0x04 => true,
+        0x00 => head[12..16].iter().any(|&b| b != 0),
+        _    => false,
+    }
+}
+
+fn clean_header(head: &mut [u8]) {
+    for b in head[7..16].iter_mut() {
+        *b = 0;
+    }
+}
+
+//Parses a header the same way the loader would, to get at the sizes.
+fn layout(head: [u8; 16]) -> CART {
+    let mut cart = CART { HEAD: head, ..CART::default() };
+    cart.parse_header();
+    cart
+}
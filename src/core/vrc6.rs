@@ -0,0 +1,310 @@
+/* Konami VRC6, mappers #24 (VRC6a) and #26 (VRC6b).
+ * Banked PRG and CHR, the VRC IRQ counter, and three extra channels
+ *  on the cart's audio line: two pulses with 16 step duty cycles, and
+ *  a sawtooth that ramps an accumulator.
+ * VRC6b only swaps the A0/A1 register select lines.
+ * Author: Spalynx
+ *--------------Registers-----------------------------------------------
+ * $8000        16kb PRG at $8000.
+ * $9000-$9002  Pulse 1: mode/duty/volume, period low, enable/period high.
+ * $9003        Audio halt and frequency scaling.
+ * $A000-$A002  Pulse 2, as pulse 1.
+ * $B000-$B002  Saw: accumulator rate, period low, enable/period high.
+ * $B003        PPU banking mode, mirroring, PRG-RAM enable (bit 7).
+ * $C000        8kb PRG at $C000, the last 8kb fixed at $E000.
+ * $D000-$E003  CHR R0-R7.
+ * $F000-$F002  IRQ latch, control, acknowledge.
+ *---------------------------------------------------------------------
+ * USED: Akumajou Densetsu (VRC6a), Madara, Esper Dream 2 (VRC6b).
+ * http://wiki.nesdev.com/w/index.php/VRC6
+ * http://wiki.nesdev.com/w/index.php/VRC6_audio
+ */
+
+use std::io;
+use crate::core::*;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./vrc6_test.rs"]
+pub mod vrc6_test;
+
+//~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// The VRC6's pulses. Sixteen steps, with the output high on the steps
+///  at or below the duty, or always in digitized mode.
+#[derive(Clone, Copy, Default)]
+pub struct Vrc6Pulse {
+    pub volume:     u8,
+    pub duty:       u8,
+    pub digitized:  bool,
+    pub period:     u16,
+    pub enabled:    bool,
+    pub step:       u8,
+    timer:          u16,
+}
+impl Vrc6Pulse {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.volume    = val & 0x0F;
+                self.duty      = (val >> 4) & 7;
+                self.digitized = val & 0x80 != 0;
+            },
+            1 => self.period = (self.period & 0xF00) | val as u16,
+            _ => {
+                self.period  = (self.period & 0xFF) | ((val & 0x0F) as u16) << 8;
+                self.enabled = val & 0x80 != 0;
+                //Disabling resets the duty cycle.
+                if !self.enabled {
+                    self.step = 15;
+                }
+            },
+        }
+    }
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step  = self.step.wrapping_sub(1) & 15;
+        }
+        else {
+            self.timer -= 1;
+        }
+    }
+    pub fn output(&self) -> u8 {
+        if self.enabled && (self.digitized || self.step <= self.duty) { self.volume } else { 0 }
+    }
+}
+
+/// The sawtooth adds its rate to an accumulator every other step, and
+///  clears it after seven adds. The top 5 bits are the output.
+#[derive(Clone, Copy, Default)]
+pub struct Vrc6Saw {
+    pub rate:       u8,
+    pub period:     u16,
+    pub enabled:    bool,
+    pub accumulator: u8,
+    pub step:       u8,
+    timer:          u16,
+}
+impl Vrc6Saw {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => self.rate = val & 0x3F,
+            1 => self.period = (self.period & 0xF00) | val as u16,
+            _ => {
+                self.period  = (self.period & 0xFF) | ((val & 0x0F) as u16) << 8;
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.accumulator = 0;
+                    self.step        = 0;
+                }
+            },
+        }
+    }
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step        = 0;
+            self.accumulator = 0;
+        }
+        else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+    pub fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+/// All three channels, and $9003.
+#[derive(Clone, Copy, Default)]
+pub struct Vrc6Audio {
+    pub pulse:  [Vrc6Pulse; 2],
+    pub saw:    Vrc6Saw,
+    pub halt:   bool,
+    pub shift:  u8,     //Periods shifted right by 0, 4 or 8.
+}
+impl Vrc6Audio {
+    pub fn new() -> Vrc6Audio {
+        Vrc6Audio::default()
+    }
+
+    /// $9000-$B002, as VRC6a sees them.
+    pub fn write(&mut self, address: u16, val: u8) {
+        let reg = address & 3;
+        match address & 0xF003 {
+            0x9003 => {
+                self.halt  = val & 1 != 0;
+                self.shift = if val & 4 != 0 { 8 } else if val & 2 != 0 { 4 } else { 0 };
+            },
+            0x9000..=0x9002 => self.pulse[0].write(reg, val),
+            0xA000..=0xA002 => self.pulse[1].write(reg, val),
+            0xB000..=0xB002 => self.saw.write(reg, val),
+            _ => {},
+        }
+    }
+
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse[0].clock(self.shift);
+        self.pulse[1].clock(self.shift);
+        self.saw.clock(self.shift);
+    }
+
+    /// The channels sum linearly, 0 to 61. Scaled so a pulse at full
+    ///  volume is about as loud as one of the APU's, ~0.15 each.
+    pub fn output(&self) -> f32 {
+        let level = self.pulse[0].output() + self.pulse[1].output() + self.saw.output();
+        level as f32 * 0.01
+    }
+}
+
+//~~~MAPPER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+pub struct Vrc6 {
+    pub cart: Box<CART>,
+    pub swapped:    bool,       //VRC6b, A0/A1 crossed.
+    pub prg_16k:    u8,         //$8000
+    pub prg_8k:     u8,         //$C000
+    pub chr_bank:   [u8; 8],    //R0-R7
+    pub banking:    u8,         //$B003
+    pub irq:        VrcIrq,
+    pub audio:      Vrc6Audio,
+}
+
+impl Vrc6 {
+    pub fn new(cart: Box<CART>) -> Vrc6 {
+        Vrc6 {
+            swapped:    cart.mapper == 26,
+            cart,
+            prg_16k:    0,
+            prg_8k:     0,
+            chr_bank:   [0; 8],
+            banking:    0,
+            irq:        VrcIrq::default(),
+            audio:      Vrc6Audio::new(),
+        }
+    }
+
+    /// The register an address selects, as VRC6a would see it.
+    fn register(&self, address: u16) -> u16 {
+        if self.swapped {
+            (address & 0xF000) | (address & 1) << 1 | (address >> 1) & 1
+        }
+        else {
+            address & 0xF003
+        }
+    }
+    fn prg_index(&self, address: u16) -> usize {
+        let len = self.cart.PRG.len();
+        match address {
+            0x8000..=0xBFFF => self.prg_16k as usize * 0x4000 + (address as usize & 0x3FFF),
+            0xC000..=0xDFFF => self.prg_8k as usize * 0x2000 + (address as usize & 0x1FFF),
+            _ => len.saturating_sub(0x2000) + (address as usize & 0x1FFF),
+        }
+    }
+    /// $B003 bits 0-1: 1kb banks, 2kb banks, or 1kb then 2kb. In the 2kb
+    ///  windows bit 5 lets the PPU's A10 pick the half, otherwise the
+    ///  register's own low bit does.
+    fn chr_index(&self, address: u16) -> usize {
+        let slot = (address >> 10) as usize & 7;
+        let a10 = (address >> 10) as u8 & 1;
+        let two_k = |reg: u8| if self.banking & 0x20 != 0 { (reg & 0xFE) | a10 } else { reg };
+        let bank = match self.banking & 3 {
+            0 => self.chr_bank[slot],
+            1 => two_k(self.chr_bank[slot >> 1]),
+            _ => if slot < 4 { self.chr_bank[slot] } else { two_k(self.chr_bank[4 + ((slot - 4) >> 1)]) },
+        };
+        bank as usize * 0x400 + (address as usize & 0x3FF)
+    }
+    fn ram_enabled(&self) -> bool {
+        self.banking & 0x80 != 0
+    }
+}
+
+impl MAP for Vrc6 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if self.ram_enabled() { self.cart.get_ram(address) } else { 0 }
+        }
+        else {
+            prg_read(&self.cart, self.prg_index(address))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        if address < 0x8000 {
+            if self.ram_enabled() {
+                self.cart.set_ram(address, val);
+            }
+            return;
+        }
+        let register = self.register(address);
+        match register {
+            0x8000..=0x8003 => self.prg_16k = val & 0x0F,
+            0x9000..=0xB002 => self.audio.write(register, val),
+            0xB003 => self.banking = val,
+            0xC000..=0xC003 => self.prg_8k = val & 0x1F,
+            0xD000..=0xE003 => {
+                let slot = (((register >> 12) - 0xD) * 4 + (register & 3)) as usize;
+                self.chr_bank[slot] = val;
+            },
+            0xF000 => self.irq.latch = val,
+            0xF001 => self.irq.control(val),
+            0xF002 => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, self.chr_index(address))
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Vrc6::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+    /// $B003 bits 2-3, as every known game uses them. Nametables from
+    ///  CHR-ROM (bit 4) aren't supported.
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match (self.banking >> 2) & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleLow,
+            _ => Mirroring::SingleHigh,
+        })
+    }
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
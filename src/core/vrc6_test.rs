@@ -0,0 +1,140 @@
+/*  Unit test module of the VRC6 (vrc6.rs).
+ *  Carts are built by hand with every 8kb PRG and 1kb CHR bank filled
+ *   with its own bank number.
+ */
+use crate::core::vrc6::*;
+
+#[cfg(test)]
+pub mod vrc6_test {
+    use super::*;
+    use crate::core::mapper::*;
+    use crate::core::ppu::Mirroring;
+
+    fn cart(mapper: u16) -> Box<CART> {
+        Box::new(banked_cart(mapper, 0, 0x2000, 0x400, 16))
+    }
+
+    //~~~BANKING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_vrc6_prg(){
+        let mut map = new_map(24, cart(24)).unwrap();
+        map.set(0x8000, 3);
+        map.set(0xC000, 9);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![6, 7, 9, 15]);
+
+        //PRG-RAM only with $B003 bit 7.
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0);
+        map.set(0xB003, 0x80);
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_vrc6_chr(){
+        for &mapper in [24, 26].iter() {
+            let mut map = new_map(mapper, cart(mapper)).unwrap();
+            for i in 0..4 {
+                map.set(0xD000 + i, 10 + i as u8);
+                map.set(0xE000 + i, 20 + i as u8);
+            }
+            //VRC6b sees $D001 as $D002.
+            let expected: Vec<u8> = if mapper == 24 {
+                vec![10, 11, 12, 13, 20, 21, 22, 23]
+            } else {
+                vec![10, 12, 11, 13, 20, 22, 21, 23]
+            };
+            let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+            assert_eq!(banks, expected, "Mapper {}", mapper);
+        }
+    }
+
+    #[test]
+    fn test_vrc6_chr_modes(){
+        let mut map = new_map(24, cart(24)).unwrap();
+        for i in 0..4 {
+            map.set(0xD000 + i, 10 + i as u8);
+            map.set(0xE000 + i, 20 + i as u8);
+        }
+        //2kb banks, the register's low bit repeats...
+        map.set(0xB003, 0x01);
+        let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+        assert_eq!(banks, vec![10, 10, 11, 11, 12, 12, 13, 13]);
+        //...unless the PPU's A10 takes over.
+        map.set(0xB003, 0x21);
+        let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+        assert_eq!(banks, vec![10, 11, 10, 11, 12, 13, 12, 13]);
+        //1kb below $1000, 2kb from R4/R5 above.
+        map.set(0xB003, 0x22);
+        let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+        assert_eq!(banks, vec![10, 11, 12, 13, 20, 21, 20, 21]);
+    }
+
+    #[test]
+    fn test_vrc6_mirroring(){
+        let mut map = new_map(24, cart(24)).unwrap();
+        let modes = [Mirroring::Vertical, Mirroring::Horizontal,
+                     Mirroring::SingleLow, Mirroring::SingleHigh];
+        for (i, &mode) in modes.iter().enumerate() {
+            map.set(0xB003, 0x20 | (i as u8) << 2);
+            assert_eq!(map.mirroring(), Some(mode));
+        }
+    }
+
+    #[test]
+    fn test_vrc6_irq(){
+        let mut map = new_map(26, cart(26)).unwrap();
+        map.set(0xF000, 0xFE);
+        //$F001 is $F002 on VRC6b.
+        map.set(0xF002, 0x06);
+        map.clock();
+        assert!(!map.irq());
+        map.clock();
+        assert!(map.irq());
+        map.set(0xF001, 0);
+        assert!(!map.irq());
+    }
+
+    //~~~AUDIO~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_vrc6_pulse(){
+        let mut audio = Vrc6Audio::new();
+        //Duty 7 of 16, volume 15, period 16.
+        audio.write(0x9000, 0x7F);
+        audio.write(0x9001, 0x10);
+        audio.write(0x9002, 0x80);
+        let high = (0..17 * 16 * 10).filter(|_| { audio.clock(); audio.pulse[0].output() == 15 }).count();
+        assert_eq!(high, 17 * 8 * 10);
+
+        //Digitized mode is just the volume.
+        audio.write(0x9000, 0x8A);
+        assert_eq!(audio.pulse[0].output(), 10);
+        audio.write(0x9002, 0x00);
+        assert_eq!(audio.pulse[0].output(), 0);
+    }
+
+    #[test]
+    fn test_vrc6_saw(){
+        let mut audio = Vrc6Audio::new();
+        audio.write(0xB000, 8);
+        audio.write(0xB002, 0x80);
+        let levels: Vec<u8> = (0..14).map(|_| { audio.clock(); audio.saw.output() }).collect();
+        assert_eq!(levels, vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+
+        //Halted.
+        audio.write(0x9003, 0x01);
+        audio.clock();
+        audio.clock();
+        assert_eq!(audio.saw.output(), 0);
+    }
+
+    #[test]
+    fn test_vrc6_mix(){
+        let mut map = new_map(24, cart(24)).unwrap();
+        assert_eq!(map.audio(), 0.0);
+        map.set(0x9000, 0x8F);
+        map.set(0x9002, 0x80);
+        assert!(map.audio() > 0.1 && map.audio() < 0.2);
+    }
+}
@@ -0,0 +1,492 @@
+/* Konami VRC7, mapper #85, and its OPLL.
+ * VRC4 style banking and the VRC IRQ, plus a cut down YM2413 (OPLL):
+ *  six two-operator FM channels, fifteen built in instruments and one
+ *  custom one, with no rhythm mode. Used for sound by Lagrange Point.
+ * The OPLL runs at its own rate, the 3.58MHz master clock / 72, so one
+ *  sample every 36 CPU cycles. The mix gets those linearly interpolated.
+ * The synthesis here works in dB and radians rather than the chip's log
+ *  sine and exponent ROMs, the envelope times follow the datasheet.
+ * Author: Spalynx
+ *--------------Registers-----------------------------------------------
+ * $8000, $8010, $9000  8kb PRG banks at $8000, $A000, $C000. $E000 fixed.
+ * $9010, $9030         OPLL register select, data.
+ * $A000-$D010          CHR R0-R7, at $x000 and $x010.
+ * $E000                Mirroring (bits 0-1), audio reset (6), PRG-RAM (7).
+ * $E010, $F000, $F010  IRQ latch, control, acknowledge.
+ * VRC7a (submapper 2, Lagrange Point) selects with A4, VRC7b (submapper
+ *  1, Tiny Toon Adventures 2) with A3. Without a submapper both are used.
+ *--------------OPLL----------------------------------------------------
+ * $00-$07  Custom instrument, as the patches below.
+ * $10-$15  F-number low 8 bits.
+ * $20-$25  F-number bit 8, block (1-3), key on (4), sustain (5).
+ * $30-$35  Instrument (4-7), volume (0-3).
+ *---------------------------------------------------------------------
+ * http://wiki.nesdev.com/w/index.php/VRC7
+ * http://wiki.nesdev.com/w/index.php/VRC7_audio
+ */
+
+use std::io;
+use std::f32::consts::PI;
+use crate::core::*;
+
+//Test module definition.
+#[cfg(test)]
+#[path = "./vrc7_test.rs"]
+pub mod vrc7_test;
+
+//~~~OPLL~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// CPU cycles per OPLL sample.
+pub const OPLL_CYCLES: u32 = 36;
+/// OPLL samples per second.
+pub const OPLL_RATE: f32 = 49716.0;
+
+/// The built in instruments 1-15, as read off the die. 0 is the custom one.
+/// Bytes: modulator/carrier flags, modulator KSL/level, carrier KSL/
+///  waveforms/feedback, modulator/carrier attack-decay, sustain-release.
+pub const VRC7_PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],   //Buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],   //Guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],   //Wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],   //Flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],   //Clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],   //Synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],   //Trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],   //Organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],   //Bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],   //Vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],   //Vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],   //Tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],   //Fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],   //Synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],   //Sweep
+];
+
+//Frequency multipliers, half steps for 0.
+const MULTIPLY: [f32; 16] = [0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0,
+                             8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0];
+//Key scale level at block 7 in dB, by the top 4 F-number bits.
+const KSL_BASE: [f32; 16] = [0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25,
+                             36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0];
+//Fraction of KSL_BASE for KSL 0-3: 0, 1.5, 3 and 6 dB an octave.
+const KSL_SCALE: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+//Vibrato, in F-number steps per 128. 8 steps at ~6.1Hz.
+const VIBRATO: [f32; 8] = [0.0, 1.0, 2.0, 1.0, 0.0, -1.0, -2.0, -1.0];
+const VIBRATO_STEP: u32 = 1024;
+//Tremolo, a 4.8dB triangle at ~3.7Hz.
+const TREMOLO_PERIOD: u32 = 13432;
+const TREMOLO_DB: f32 = 4.8;
+//The envelope covers 48dB, past that an operator is silent.
+const ENVELOPE_DB: f32 = 48.0;
+//Datasheet times for the slowest rates (4): 0-100% attack and a 96dB decay.
+const ATTACK_SECS: f32 = 2.826;
+const DECAY_SECS: f32 = 39.28;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EgStage { Attack, Decay, Sustain, Release, Off }
+
+/// One operator's half of an instrument.
+#[derive(Clone, Copy, Debug)]
+pub struct OpPatch {
+    pub tremolo:    bool,
+    pub vibrato:    bool,
+    pub sustained:  bool,   //Holds at the sustain level, rather than decay.
+    pub ksr:        bool,
+    pub multiply:   u8,
+    pub ksl:        u8,
+    pub rectified:  bool,   //Negative half of the sine cut off.
+    pub attack:     u8,
+    pub decay:      u8,
+    pub sustain:    u8,
+    pub release:    u8,
+}
+impl OpPatch {
+    /// Operator 0 (modulator) or 1 (carrier) from an instrument.
+    pub fn from(patch: &[u8; 8], op: usize) -> OpPatch {
+        let flags = patch[op];
+        OpPatch {
+            tremolo:    flags & 0x80 != 0,
+            vibrato:    flags & 0x40 != 0,
+            sustained:  flags & 0x20 != 0,
+            ksr:        flags & 0x10 != 0,
+            multiply:   flags & 0x0F,
+            ksl:        patch[2 + op] >> 6,
+            rectified:  patch[3] & (0x08 << op) != 0,
+            attack:     patch[4 + op] >> 4,
+            decay:      patch[4 + op] & 0x0F,
+            sustain:    patch[6 + op] >> 4,
+            release:    patch[6 + op] & 0x0F,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Operator {
+    pub phase:  f32,        //Cycles, 0.0-1.0.
+    pub level:  f32,        //Envelope attenuation in dB, 0 is loudest.
+    pub stage:  EgStage,
+    pub output: [f32; 2],   //The last two outputs, for feedback.
+}
+impl Default for Operator {
+    fn default() -> Operator {
+        Operator { phase: 0.0, level: ENVELOPE_DB, stage: EgStage::Off, output: [0.0; 2] }
+    }
+}
+impl Operator {
+    fn key_on(&mut self) {
+        self.stage = EgStage::Attack;
+        self.phase = 0.0;
+    }
+    fn key_off(&mut self) {
+        if self.stage != EgStage::Off {
+            self.stage = EgStage::Release;
+        }
+    }
+
+    /// One sample of the envelope generator.
+    fn envelope(&mut self, patch: &OpPatch, rks: u8, channel_sustain: bool) {
+        //Rates 1-15 are scaled by 4 and the key scale, 0 never moves.
+        let rate = |r: u8| if r == 0 { 0 } else { (r * 4 + rks).min(63) };
+        let decay_db = |r: u8| match rate(r) {
+            0 => 0.0,
+            eff => 96.0 * 2f32.powf((eff as f32 - 4.0) / 4.0) / (DECAY_SECS * OPLL_RATE),
+        };
+        match self.stage {
+            EgStage::Attack => {
+                let eff = rate(patch.attack);
+                if eff >= 60 {
+                    self.level = 0.0;
+                }
+                else if eff > 0 {
+                    self.level -= ENVELOPE_DB * 2f32.powf((eff as f32 - 4.0) / 4.0)
+                                  / (ATTACK_SECS * OPLL_RATE);
+                }
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = EgStage::Decay;
+                }
+            },
+            EgStage::Decay => {
+                let sustain = patch.sustain as f32 * 3.0;
+                self.level += decay_db(patch.decay);
+                if self.level >= sustain {
+                    self.level = sustain;
+                    self.stage = EgStage::Sustain;
+                }
+            },
+            EgStage::Sustain => if !patch.sustained {
+                self.level += decay_db(patch.release);
+            },
+            EgStage::Release => {
+                let release = if channel_sustain { 5 } else { patch.release };
+                self.level += decay_db(release);
+            },
+            EgStage::Off => {},
+        }
+        if self.level >= ENVELOPE_DB {
+            self.level = ENVELOPE_DB;
+            if self.stage != EgStage::Attack {
+                self.stage = EgStage::Off;
+            }
+        }
+    }
+
+    /// Advances the phase and returns the output, -1.0 to 1.0, with the
+    ///  phase pushed along by modulation (in cycles).
+    fn output(&mut self, patch: &OpPatch, step: f32, attenuation: f32, modulation: f32) -> f32 {
+        self.phase = (self.phase + step * MULTIPLY[patch.multiply as usize]).fract();
+        let out = if self.stage == EgStage::Off {
+            0.0
+        }
+        else {
+            let wave = (2.0 * PI * (self.phase + modulation)).sin();
+            let wave = if patch.rectified && wave < 0.0 { 0.0 } else { wave };
+            wave * 10f32.powf(-(self.level + attenuation) / 20.0)
+        };
+        self.output = [self.output[1], out];
+        out
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct FmChannel {
+    pub fnum:       u16,
+    pub block:      u8,
+    pub key:        bool,
+    pub sustain:    bool,
+    pub instrument: u8,
+    pub volume:     u8,
+    pub ops:        [Operator; 2],
+}
+
+/// The YM2413 core as it is in the VRC7: six channels, no rhythm.
+#[derive(Clone, Default)]
+pub struct Opll {
+    pub custom:     [u8; 8],
+    pub channels:   [FmChannel; 6],
+    clock:          u32,
+}
+impl Opll {
+    pub fn new() -> Opll {
+        Opll::default()
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8) {
+        let ch = (reg & 0x0F) as usize;
+        match reg {
+            0x00..=0x07 => self.custom[reg as usize] = val,
+            0x10..=0x15 => self.channels[ch].fnum = (self.channels[ch].fnum & 0x100) | val as u16,
+            0x20..=0x25 => {
+                let channel = &mut self.channels[ch];
+                channel.fnum    = (channel.fnum & 0xFF) | ((val & 1) as u16) << 8;
+                channel.block   = (val >> 1) & 7;
+                channel.sustain = val & 0x20 != 0;
+                let key = val & 0x10 != 0;
+                if key && !channel.key {
+                    channel.ops.iter_mut().for_each(Operator::key_on);
+                }
+                else if !key && channel.key {
+                    channel.ops.iter_mut().for_each(Operator::key_off);
+                }
+                channel.key = key;
+            },
+            0x30..=0x35 => {
+                self.channels[ch].instrument = val >> 4;
+                self.channels[ch].volume     = val & 0x0F;
+            },
+            _ => {},
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> [u8; 8] {
+        if instrument == 0 { self.custom } else { VRC7_PATCHES[instrument as usize - 1] }
+    }
+
+    /// One OPLL sample: the six channels summed, each -1.0 to 1.0.
+    pub fn sample(&mut self) -> f32 {
+        self.clock = self.clock.wrapping_add(1);
+        let vibrato = VIBRATO[((self.clock / VIBRATO_STEP) & 7) as usize];
+        let t = (self.clock % TREMOLO_PERIOD) as f32 / TREMOLO_PERIOD as f32;
+        let tremolo = TREMOLO_DB * (1.0 - (2.0 * t - 1.0).abs());
+
+        let mut mix = 0.0;
+        for c in 0..6 {
+            let patch = self.patch(self.channels[c].instrument);
+            let channel = &mut self.channels[c];
+            let block = channel.block;
+            //Key scale rate, from the block and the F-number's top bit.
+            let rks = (block << 1) | (channel.fnum >> 8) as u8;
+            let ksl = (KSL_BASE[(channel.fnum >> 5) as usize] - 6.0 * (7 - block) as f32).max(0.0);
+
+            let mut out = 0.0;
+            for op in 0..2 {
+                let p = OpPatch::from(&patch, op);
+                let fnum = if p.vibrato {
+                    channel.fnum as f32 + vibrato * (channel.fnum >> 7) as f32
+                } else {
+                    channel.fnum as f32
+                };
+                //Cycles per sample: F-number * 2^block / 2^19.
+                let step = fnum * (1u32 << block) as f32 / 524_288.0;
+                let rks = if p.ksr { rks } else { rks >> 2 };
+                let operator = &mut channel.ops[op];
+                operator.envelope(&p, rks, channel.sustain);
+
+                let mut attenuation = ksl * KSL_SCALE[p.ksl as usize];
+                if p.tremolo {
+                    attenuation += tremolo;
+                }
+                if op == 0 {
+                    //Modulator: its own level, and feedback up to 4pi.
+                    attenuation += (patch[2] & 0x3F) as f32 * 0.75;
+                    let feedback = match patch[3] & 7 {
+                        0 => 0.0,
+                        fb => (operator.output[0] + operator.output[1]) / 2.0 * 2.0
+                              / (1 << (7 - fb)) as f32,
+                    };
+                    out = operator.output(&p, step, attenuation, feedback);
+                }
+                else {
+                    //Carrier: the channel volume, and up to 8pi of modulation.
+                    attenuation += channel.volume as f32 * 3.0;
+                    out = operator.output(&p, step, attenuation, out * 4.0);
+                }
+            }
+            mix += out;
+        }
+        mix
+    }
+}
+
+/// The OPLL as the cart sees it: a register select latch, the reset bit,
+///  and samples held between OPLL clocks.
+#[derive(Clone, Default)]
+pub struct Vrc7Audio {
+    pub opll:       Opll,
+    pub address:    u8,
+    pub silenced:   bool,
+    cycle:          u32,
+    previous:       f32,
+    current:        f32,
+}
+impl Vrc7Audio {
+    pub fn new() -> Vrc7Audio {
+        Vrc7Audio::default()
+    }
+
+    /// $9010 and $9030.
+    pub fn write(&mut self, address: u16, val: u8) {
+        if address & 0x20 != 0 {
+            self.opll.write(self.address, val);
+        }
+        else {
+            self.address = val;
+        }
+    }
+    /// $E000 bit 6 holds the OPLL in reset.
+    pub fn reset(&mut self, held: bool) {
+        if held {
+            self.opll = Opll::new();
+        }
+        self.silenced = held;
+    }
+
+    /// One CPU cycle.
+    pub fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle == OPLL_CYCLES {
+            self.cycle    = 0;
+            self.previous = self.current;
+            self.current  = if self.silenced { 0.0 } else { self.opll.sample() };
+        }
+    }
+
+    /// Interpolated between OPLL samples. A channel at full volume swings
+    ///  about as far as one of the APU's pulses.
+    pub fn output(&self) -> f32 {
+        let t = self.cycle as f32 / OPLL_CYCLES as f32;
+        (self.previous + (self.current - self.previous) * t) * 0.08
+    }
+}
+
+//~~~MAPPER~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+pub struct Vrc7 {
+    pub cart: Box<CART>,
+    pub pins:       u16,        //Address lines of the register select pin.
+    pub prg_bank:   [u8; 3],
+    pub chr_bank:   [u8; 8],
+    pub control:    u8,         //$E000
+    pub irq:        VrcIrq,
+    pub audio:      Vrc7Audio,
+}
+
+impl Vrc7 {
+    pub fn new(cart: Box<CART>) -> Vrc7 {
+        let pins = match cart.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18,
+        };
+        Vrc7 {
+            cart,
+            pins,
+            prg_bank:   [0; 3],
+            chr_bank:   [0; 8],
+            control:    0,
+            irq:        VrcIrq::default(),
+            audio:      Vrc7Audio::new(),
+        }
+    }
+
+    fn prg_index(&self, address: u16) -> usize {
+        let len = self.cart.PRG.len();
+        let offset = address as usize & 0x1FFF;
+        match address {
+            0x8000..=0xDFFF => self.prg_bank[(address as usize - 0x8000) >> 13] as usize * 0x2000 + offset,
+            _ => len.saturating_sub(0x2000) + offset,
+        }
+    }
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr_bank[(address >> 10) as usize & 7] as usize;
+        bank * 0x400 + (address as usize & 0x3FF)
+    }
+    fn ram_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+}
+
+impl MAP for Vrc7 {
+    fn peek(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        }
+        else if address < 0x8000 {
+            if self.ram_enabled() { self.cart.get_ram(address) } else { 0 }
+        }
+        else {
+            prg_read(&self.cart, self.prg_index(address))
+        }
+    }
+    fn set(&mut self, address: u16, val: u8) {
+        if address < 0x6000 {
+            return;
+        }
+        if address < 0x8000 {
+            if self.ram_enabled() {
+                self.cart.set_ram(address, val);
+            }
+            return;
+        }
+        let high = address & self.pins != 0;
+        match (address & 0xF000, high) {
+            (0x8000, false) => self.prg_bank[0] = val & 0x3F,
+            (0x8000, true)  => self.prg_bank[1] = val & 0x3F,
+            (0x9000, false) => self.prg_bank[2] = val & 0x3F,
+            (0x9000, true)  => self.audio.write(address, val),
+            (0xA000..=0xD000, _) => {
+                let slot = (((address >> 12) - 0xA) * 2 + high as u16) as usize;
+                self.chr_bank[slot] = val;
+            },
+            (0xE000, false) => {
+                self.control = val;
+                self.audio.reset(val & 0x40 != 0);
+            },
+            (0xE000, true)  => self.irq.latch = val,
+            (0xF000, false) => self.irq.control(val),
+            (0xF000, true)  => self.irq.acknowledge(),
+            _ => {},
+        }
+    }
+    fn get_chr(&self, address: u16) -> u8 {
+        chr_read(&self.cart, self.chr_index(address))
+    }
+    fn set_chr(&mut self, address: u16, val: u8){
+        let index = self.chr_index(address);
+        chr_write(&mut self.cart, index, val);
+    }
+    fn power(&mut self, src: &mut PowerSource) {
+        *self = power_cycle(&mut self.cart, src, Vrc7::new);
+    }
+    fn flush_sav(&self) -> io::Result<()> {
+        self.cart.save_sav()
+    }
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleLow,
+            _ => Mirroring::SingleHigh,
+        })
+    }
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
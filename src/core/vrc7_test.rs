@@ -0,0 +1,175 @@
+/*  Unit test module of the VRC7 and its OPLL (vrc7.rs).
+ *  The OPLL tests use a custom instrument with the modulator silenced,
+ *   so the carrier is a plain sine that can be measured.
+ */
+use crate::core::vrc7::*;
+
+#[cfg(test)]
+pub mod vrc7_test {
+    use super::*;
+    use crate::core::mapper::*;
+    use crate::core::ppu::Mirroring;
+
+    fn cart(submapper: u8) -> Box<CART> {
+        Box::new(banked_cart(85, submapper, 0x2000, 0x400, 16))
+    }
+
+    //~~~BANKING~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    #[test]
+    fn test_vrc7_banks(){
+        let mut map = new_map(85, cart(2)).unwrap();
+        map.set(0x8000, 3);
+        map.set(0x8010, 4);
+        map.set(0x9000, 5);
+        let banks: Vec<u8> = [0x8000, 0xA000, 0xC000, 0xE000].iter().map(|&a| map.peek(a)).collect();
+        assert_eq!(banks, vec![3, 4, 5, 15]);
+
+        for (i, &address) in [0xA000, 0xA010, 0xB000, 0xB010, 0xC000, 0xC010, 0xD000, 0xD010].iter().enumerate() {
+            map.set(address, 20 + i as u8);
+        }
+        let banks: Vec<u8> = (0..8).map(|slot| map.get_chr(slot * 0x400)).collect();
+        assert_eq!(banks, vec![20, 21, 22, 23, 24, 25, 26, 27]);
+
+        map.set(0xE000, 0x01);
+        assert_eq!(map.mirroring(), Some(Mirroring::Horizontal));
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0);
+        map.set(0xE000, 0x80);
+        map.set(0x6000, 0x42);
+        assert_eq!(map.peek(0x6000), 0x42);
+        assert_eq!(map.mirroring(), Some(Mirroring::Vertical));
+    }
+
+    #[test]
+    fn test_vrc7_pins(){
+        //VRC7b on A3, unknown boards take either.
+        for &(sub, address) in [(1, 0x8008), (0, 0x8008), (0, 0x8010)].iter() {
+            let mut map = new_map(85, cart(sub)).unwrap();
+            map.set(address, 7);
+            assert_eq!(map.peek(0xA000), 7, "Submapper {} ${:04X}", sub, address);
+        }
+        //VRC7b ignores A4.
+        let mut map = new_map(85, cart(1)).unwrap();
+        map.set(0x8010, 7);
+        assert_eq!((map.peek(0x8000), map.peek(0xA000)), (7, 0));
+    }
+
+    #[test]
+    fn test_vrc7_irq(){
+        let mut map = new_map(85, cart(2)).unwrap();
+        map.set(0xE010, 0xFE);
+        map.set(0xF000, 0x06);
+        map.clock();
+        assert!(!map.irq());
+        map.clock();
+        assert!(map.irq());
+        map.set(0xF010, 0);
+        assert!(!map.irq());
+    }
+
+    //~~~OPLL~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    /// Modulator at full attenuation and never attacking; carrier a
+    ///  sustained sine, instant attack, no decay, fast release.
+    const SINE: [u8; 8] = [0x01, 0x21, 0x3F, 0x00, 0x00, 0xF0, 0x00, 0x0F];
+
+    /// Keys channel 0 on at ~440Hz: F-number 290, block 4.
+    fn play(opll: &mut Opll, patch: [u8; 8], volume: u8) {
+        for (reg, &val) in patch.iter().enumerate() {
+            opll.write(reg as u8, val);
+        }
+        opll.write(0x30, volume);
+        opll.write(0x10, (290u16 & 0xFF) as u8);
+        opll.write(0x20, 0x10 | (4 << 1) | 1);
+    }
+
+    #[test]
+    fn test_opll_frequency(){
+        let mut opll = Opll::new();
+        play(&mut opll, SINE, 0);
+        let samples: Vec<f32> = (0..OPLL_RATE as usize).map(|_| opll.sample()).collect();
+        let rising = samples.windows(2).filter(|w| w[0] <= 0.0 && w[1] > 0.0).count();
+        assert!((438..=442).contains(&rising), "{} cycles", rising);
+        let peak = samples.iter().fold(0f32, |a, &s| a.max(s));
+        assert!(peak > 0.99 && peak <= 1.0);
+    }
+
+    #[test]
+    fn test_opll_volume(){
+        let mut opll = Opll::new();
+        play(&mut opll, SINE, 15);
+        let peak = (0..1000).map(|_| opll.sample()).fold(0f32, f32::max);
+        //3dB a step, 45dB down.
+        assert!((peak - 10f32.powf(-45.0 / 20.0)).abs() < 0.001, "{}", peak);
+    }
+
+    #[test]
+    fn test_opll_envelope(){
+        let mut opll = Opll::new();
+        play(&mut opll, SINE, 0);
+        for _ in 0..100 {
+            opll.sample();
+        }
+        assert_eq!(opll.channels[0].ops[1].stage, EgStage::Sustain);
+        //Key off: release 15 is gone in a few hundred samples.
+        opll.write(0x20, (4 << 1) | 1);
+        assert_eq!(opll.channels[0].ops[1].stage, EgStage::Release);
+        for _ in 0..300 {
+            opll.sample();
+        }
+        assert_eq!(opll.channels[0].ops[1].stage, EgStage::Off);
+        assert_eq!(opll.sample(), 0.0);
+
+        //Without the sustained flag, the release rate runs while held.
+        let mut decaying = SINE;
+        decaying[1] = 0x01;
+        decaying[7] = 0x08;
+        play(&mut opll, decaying, 0);
+        for _ in 0..100 {
+            opll.sample();
+        }
+        assert_eq!(opll.channels[0].ops[1].stage, EgStage::Sustain);
+        for _ in 0..10000 {
+            opll.sample();
+        }
+        assert_eq!(opll.channels[0].ops[1].stage, EgStage::Off);
+    }
+
+    #[test]
+    fn test_opll_patches(){
+        //Every built in instrument makes a sound.
+        for instrument in 1..16u8 {
+            let mut opll = Opll::new();
+            opll.write(0x30, instrument << 4);
+            opll.write(0x10, (290u16 & 0xFF) as u8);
+            opll.write(0x20, 0x10 | (4 << 1) | 1);
+            let peak = (0..5000).map(|_| opll.sample().abs()).fold(0f32, f32::max);
+            assert!(peak > 0.01, "Instrument {}", instrument);
+        }
+    }
+
+    #[test]
+    fn test_vrc7_mix(){
+        let mut map = new_map(85, cart(2)).unwrap();
+        for (reg, &val) in SINE.iter().enumerate() {
+            map.set(0x9010, reg as u8);
+            map.set(0x9030, val);
+        }
+        for &(reg, val) in [(0x30, 0x00), (0x10, 0x22), (0x20, 0x19)].iter() {
+            map.set(0x9010, reg);
+            map.set(0x9030, val);
+        }
+        let mut loudest: f32 = 0.0;
+        for _ in 0..OPLL_CYCLES * 200 {
+            map.clock();
+            loudest = loudest.max(map.audio());
+        }
+        assert!(loudest > 0.05);
+
+        //$E000 bit 6 holds it in reset.
+        map.set(0xE000, 0x40);
+        for _ in 0..OPLL_CYCLES * 2 {
+            map.clock();
+        }
+        assert_eq!(map.audio(), 0.0);
+    }
+}
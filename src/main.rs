@@ -14,11 +14,135 @@ fn main() {
         .init();
 
     debug!("COMPLETE -> Logger init.");
-    let mut nes_main = 
-        core::nes::NES::new("example/nestest.nes");
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|a| a.as_str()) {
+        Some("info")       => return info(&args[2..]),
+        Some("fix-header") => return fix_header(&args[2..]),
+        _ => {},
+    }
+    let rom = std::env::args().nth(1)
+        .unwrap_or_else(|| "example/nestest.nes".to_string());
+    //Music files are rendered headless: [track] [seconds], to a .wav.
+    let lower = rom.to_lowercase();
+    if lower.ends_with(".nsf") || lower.ends_with(".nsfe") {
+        let arg = |n: usize, default: u64| std::env::args().nth(n)
+            .and_then(|a| a.parse().ok()).unwrap_or(default);
+        let nsf = core::nsf::Nsf::from_path(&rom)
+            .unwrap_or_else(|e| panic!("ERROR    -> NSF read: {}", e));
+        //Tracks are numbered from 1, like players show them.
+        let track = match std::env::args().nth(2) {
+            Some(a) => a.parse::<u8>().ok()
+                .and_then(|t| t.checked_sub(1))
+                .filter(|&t| t < nsf.songs),
+            None => Some(0),
+        };
+        let track = track.unwrap_or_else(|| {
+            eprintln!("usage: soliloquy <nsf> [track 1-{}] [seconds]", nsf.songs);
+            std::process::exit(2);
+        });
+        let pcm = core::nsf::render_track(&rom, track,
+                                          std::time::Duration::from_secs(arg(3, 30)), 44100)
+            .unwrap_or_else(|e| panic!("ERROR    -> NSF read: {}", e));
+        let out = std::path::Path::new(&rom).with_extension("wav");
+        std::fs::write(&out, core::nsf::wav(&pcm, 44100))
+            .unwrap_or_else(|e| panic!("ERROR    -> WAV write: {}", e));
+        debug!("COMPLETE -> Rendered {}", out.display());
+        return;
+    }
+    //Disk images need the FDS BIOS, which defaults to disksys.rom.
+    let mut nes_main = if rom.to_lowercase().ends_with(".fds") {
+        let bios = std::env::args().nth(2)
+            .unwrap_or_else(|| "disksys.rom".to_string());
+        core::nes::NES::from_fds(&rom, bios)
+            .unwrap_or_else(|e| panic!("ERROR    -> FDS read: {}", e))
+    }
+    else {
+        core::nes::NES::new(rom)
+    };
     debug!("COMPLETE -> NES boot/CPU boot");
     for i in 1..=200 { 
         debug!("INSTRUCTION: #{}", i );
         nes_main.step();
     }
 }
+
+/// Loads a ROM as it is on disk, no sidecar patches, for the subcommands.
+/// Headers are corrected from the bundled database, or --db=<nes20db.xml>.
+fn load(rom: &str, validation: core::Validation, args: &[String]) -> core::CART {
+    let opts = core::LoadOptions { validation, database: Some(database(args)),
+                                   auto_patch: false, ..Default::default() };
+    core::CART::from_path_with(rom, &opts).unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom, e);
+        std::process::exit(1);
+    })
+}
+
+/// The --db=<path> argument's database, or the bundled one.
+fn database(args: &[String]) -> std::sync::Arc<core::romdb::GameDb> {
+    let path = match args.iter().find_map(|a| a.strip_prefix("--db=")) {
+        Some(path) => path,
+        None => return core::romdb::GameDb::bundled(),
+    };
+    let db = core::romdb::GameDb::from_path(path).unwrap_or_else(|e| {
+        eprintln!("{}: {}", path, e);
+        std::process::exit(1);
+    });
+    std::sync::Arc::new(db)
+}
+
+/// soliloquy info <rom>... [--db=<path>]
+fn info(args: &[String]) {
+    let roms: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    if roms.is_empty() {
+        eprintln!("usage: soliloquy info <rom>... [--db=<path>]");
+        std::process::exit(2);
+    }
+    for rom in roms {
+        print!("{}", load(rom, core::Validation::Warn, args));
+    }
+}
+
+/// soliloquy fix-header <rom> <out> [--ines|--nes2] [--force] [--db=<path>]
+/// Rewrites the header from what the loader (and database) decided, into
+///  a new file. The format is kept unless it can't hold the cart.
+/// Forcing iNES onto a cart it can't describe needs --force, since the
+///  file would load as something else.
+fn fix_header(args: &[String]) {
+    let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    if paths.len() != 2 {
+        eprintln!("usage: soliloquy fix-header <rom> <out> [--ines|--nes2] [--force] [--db=<path>]");
+        std::process::exit(2);
+    }
+    let (rom, out) = (paths[0], paths[1]);
+    if std::path::Path::new(rom) == std::path::Path::new(out) {
+        eprintln!("fix-header writes a new file, <out> can't be <rom>.");
+        std::process::exit(2);
+    }
+
+    let cart = load(rom, core::Validation::Repair, args);
+    let nes2 = if args.iter().any(|a| a == "--nes2") { true }
+               else if args.iter().any(|a| a == "--ines") { false }
+               else { cart.nes2_fmt || cart.unif_fmt || cart.needs_nes2() };
+    if !nes2 && cart.needs_nes2() {
+        if !args.iter().any(|a| a == "--force") {
+            eprintln!("{}: iNES can't describe this cart fully, use --nes2 (or --force to lose it).", rom);
+            std::process::exit(2);
+        }
+        warn!("ROM      -> Forced to iNES, the fields it can't hold are lost.");
+    }
+
+    let header = cart.header(nes2);
+    println!("Old header: {}", hex(&cart.HEAD));
+    println!("New header: {} ({})", hex(&header), if nes2 { "NES 2.0" } else { "iNES" });
+    for fix in cart.corrections.iter() {
+        println!("  Corrected {}", fix);
+    }
+    std::fs::write(out, cart.to_ines(nes2)).unwrap_or_else(|e| {
+        eprintln!("{}: {}", out, e);
+        std::process::exit(1);
+    });
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}